@@ -0,0 +1,9507 @@
+//! pd: An interactive parent directory navigator.
+//!
+//! This tool allows a user to interactively select a component of the current
+//! working directory's path. It then prints the selected parent path to stdout,
+//! which is intended to be captured by a shell function to quickly change
+//! directories "up" the tree.
+//!
+//! # Features
+//! - Vim and Emacs style keybindings.
+//! - Mouse click and scroll wheel support for navigation.
+//! - Cross-platform compatibility (Windows, macOS, Linux).
+//! - Guaranteed terminal state restoration on exit via the RAII pattern.
+//!
+//! # Usage
+//! The keymap can be set to Emacs mode by setting the `PD_KEYMAP` environment
+//! variable to `emacs`. It defaults to Vim mode otherwise.
+//!
+//! # Library
+//! The `pd` binary only calls `run`. The path splitting and expansion, the
+//! `AppState` motions and the key handling are exported as well, so that they
+//! can be tested without a terminal.
+
+use std::{
+    cell::OnceCell,
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{stderr, IsTerminal, Result, Write},
+    path::{Component, Path, PathBuf, Prefix},
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        MouseButton, MouseEvent, MouseEventKind, PopKeyboardEnhancementFlags,
+        PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{
+        self, disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, Clear, ClearType,
+    },
+};
+
+use globset::{Glob, GlobMatcher};
+use unicode_normalization::UnicodeNormalization;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// Conditionally compile the nix dependency only for unix targets.
+#[cfg(unix)]
+use nix::sys::signal::{self, Signal};
+
+/// Whether keyboard enhancement flags are currently pushed onto the terminal.
+static KEYBOARD_ENHANCED: AtomicBool = AtomicBool::new(false);
+
+/// Whether `set_terminal_mode` has changed the terminal, so there is something to restore.
+static TERMINAL_MODE_SET: AtomicBool = AtomicBool::new(false);
+
+/// A plain-text breadcrumb that `restore_terminal_mode` leaves on the terminal
+/// instead of clearing the UI, set on confirm with `PD_KEEP_BREADCRUMB=1`.
+static FINAL_LINE: OnceLock<String> = OnceLock::new();
+
+/// Decides once whether to use the kitty keyboard protocol.
+///
+/// `PD_KITTY_KEYBOARD=1` forces it on and `PD_KITTY_KEYBOARD=0` forces it off.
+/// Otherwise the terminal is queried for support, which requires a round trip
+/// to the terminal and is therefore only done on the first call.
+fn use_keyboard_enhancement() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| match env::var("PD_KITTY_KEYBOARD").as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        Ok(other) => {
+            eprintln!(
+                "Warning: Unknown PD_KITTY_KEYBOARD value '{}', detecting support",
+                other
+            );
+            supports_keyboard_enhancement().unwrap_or(false)
+        }
+        // Detection queries the controlling terminal, which is the wrong one
+        // when the UI has been redirected with `PD_TERM_OUT`.
+        Err(_) if TERM_DEVICE.get().is_some() => false,
+        Err(_) => supports_keyboard_enhancement().unwrap_or(false),
+    })
+}
+
+/// The terminal device named by `PD_TERM_OUT`, once it has been opened.
+static TERM_DEVICE: OnceLock<fs::File> = OnceLock::new();
+
+/// Returns the writer the UI is drawn to: the `PD_TERM_OUT` device if one was
+/// opened, and stderr otherwise.
+fn term_out() -> Box<dyn Write> {
+    match TERM_DEVICE.get() {
+        Some(file) => Box::new(file),
+        None => Box::new(stderr()),
+    }
+}
+
+/// Redirects the UI to the terminal device at `path`, for `PD_TERM_OUT`.
+///
+/// Output goes to the device through `term_out`. For input, the device replaces
+/// stdin, since crossterm reads events from (and sets raw mode on) stdin
+/// whenever it is a terminal.
+#[cfg(unix)]
+fn open_term_device(path: &Path) -> Result<()> {
+    use std::io::{Error, ErrorKind};
+
+    let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    if !file.is_terminal() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("PD_TERM_OUT: {} is not a terminal", path.display()),
+        ));
+    }
+    nix::unistd::dup2_stdin(&file)?;
+    let _ = TERM_DEVICE.set(file);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn open_term_device(_path: &Path) -> Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "PD_TERM_OUT is only supported on Unix",
+    ))
+}
+
+/// Puts the terminal into a "raw" mode.
+///
+/// This function enables raw mode, hides the cursor, and enables mouse capture.
+/// This allows the application to have full control over terminal input and
+/// display, rather than relying on line-buffered input. On terminals that
+/// support the kitty keyboard protocol, it also enables unambiguous reporting of
+/// modified keys, which makes Ctrl and Alt combinations reliable.
+///
+/// If a step fails after raw mode has been enabled, everything set up so far is
+/// undone before the error is returned, so the terminal is never left half set up.
+fn set_terminal_mode() -> Result<()> {
+    let mut out = term_out();
+    set_up_terminal(&mut out, &TERMINAL_MODE_SET, enable_raw_mode, disable_raw_mode)?;
+    if use_keyboard_enhancement() {
+        let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+            | KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+        // Terminals without support ignore the sequence, so failure is not fatal.
+        if execute!(out, PushKeyboardEnhancementFlags(flags)).is_ok() {
+            KEYBOARD_ENHANCED.store(true, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// Enables raw mode with `enable_raw`, records it in `mode_set`, and hides the
+/// cursor and enables mouse capture on `out`: the steps of `set_terminal_mode`
+/// that must be undone if a later one fails.
+///
+/// If writing to `out` fails, the cursor is shown again, raw mode is disabled
+/// with `disable_raw`, and `mode_set` is cleared before the error is returned.
+fn set_up_terminal<W: Write>(
+    out: &mut W,
+    mode_set: &AtomicBool,
+    enable_raw: impl FnOnce() -> Result<()>,
+    disable_raw: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    enable_raw()?;
+    mode_set.store(true, Ordering::Relaxed);
+    if let Err(e) = execute!(out, cursor::Hide, event::EnableMouseCapture) {
+        let _ = execute!(out, cursor::Show, event::DisableMouseCapture);
+        let _ = disable_raw();
+        mode_set.store(false, Ordering::Relaxed);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Asks the terminal which row the cursor is on, counting from 0, for mapping
+/// clicks onto a wrapped breadcrumb. Returns `None` if the terminal does not
+/// answer within 100 milliseconds, or is the `PD_TERM_OUT` device.
+///
+/// Unlike `cursor::position`, this writes the query to the terminal itself, so it
+/// works while stdout is redirected. It must run before the first event is read.
+#[cfg(unix)]
+fn query_cursor_row() -> Option<u16> {
+    use nix::poll::{PollFd, PollFlags, poll};
+    use std::io::Read as _;
+    use std::os::fd::AsFd as _;
+
+    if TERM_DEVICE.get().is_some() {
+        return None;
+    }
+    let mut tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+    tty.write_all(b"\x1b[6n").ok()?;
+    tty.flush().ok()?;
+    // The reply is `ESC [ <row> ; <column> R`.
+    let mut reply = Vec::new();
+    loop {
+        let mut fds = [PollFd::new(tty.as_fd(), PollFlags::POLLIN)];
+        if poll(&mut fds, 100u8).ok()? == 0 || reply.len() > 16 {
+            return None;
+        }
+        let mut byte = [0];
+        tty.read_exact(&mut byte).ok()?;
+        if byte[0] == b'R' {
+            break;
+        }
+        reply.push(byte[0]);
+    }
+    let reply = String::from_utf8(reply).ok()?;
+    let (row, _) = reply.strip_prefix("\x1b[")?.split_once(';')?;
+    row.parse::<u16>().ok()?.checked_sub(1)
+}
+
+#[cfg(not(unix))]
+fn query_cursor_row() -> Option<u16> {
+    None
+}
+
+/// Restores the terminal to its normal state.
+///
+/// This function disables raw mode, shows the cursor, and disables mouse capture.
+/// It also clears the screen from the cursor's position down to remove any UI artifacts,
+/// then prints the `FINAL_LINE`, if one was set, to stay in the scrollback.
+fn restore_terminal_mode() -> Result<()> {
+    // Leave a terminal that was never set up (or is already restored) untouched.
+    if !TERMINAL_MODE_SET.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+    // Failure to disable raw mode is usually safe to ignore, as the program is exiting.
+    let mut out = term_out();
+    if KEYBOARD_ENHANCED.swap(false, Ordering::Relaxed) {
+        let _ = execute!(out, PopKeyboardEnhancementFlags);
+    }
+    let _ = disable_raw_mode();
+    let _ = execute!(out, cursor::Show, event::DisableMouseCapture);
+    let _ = execute!(
+        out,
+        cursor::MoveToColumn(0),
+        Clear(ClearType::FromCursorDown)
+    );
+    if let Some(line) = FINAL_LINE.get() {
+        // Raw mode is off again, so the newline is translated as usual.
+        let _ = execute!(out, Print(line), Print("\n"));
+    }
+    Ok(())
+}
+
+/// A guard struct to ensure terminal state is restored when it goes out of scope.
+///
+/// This struct leverages Rust's RAII (Resource Acquisition Is Initialization) pattern.
+/// When an instance of `TermCleanup` is created, it does nothing. However, when it
+/// falls out of scope (e.g., at the end of a function, on return, or during a panic),
+/// its `drop` method is automatically called, which executes `restore_terminal_mode()`
+/// to clean up the terminal state.
+struct TermCleanup;
+
+impl Drop for TermCleanup {
+    fn drop(&mut self) {
+        // Ensures that no matter how the program exits, the terminal mode is restored.
+        let _ = restore_terminal_mode();
+    }
+}
+
+/// Represents the application's input mode for handling multi-key sequences.
+enum InputMode {
+    /// The default mode, where each key press is treated as a standalone command.
+    Normal,
+    /// A mode where the application is waiting for the next key event to complete a command.
+    /// The contained closure will be executed with the next key press, and the
+    /// prompt (e.g. `f_`) is shown after the path in the meantime.
+    WaitForNextKey(&'static str, PendingAction),
+    /// A mode where the subdirectories of a component are listed so that one of
+    /// them can be picked and appended to the path.
+    Descend(ChildList),
+    /// A mode where a command such as `cd <path>` is being typed.
+    Command(CommandLine),
+    /// A mode where a substring is being typed to pick out matching components.
+    Filter(FilterQuery),
+    /// A mode where a few characters are being typed to select the first
+    /// component containing them in order (see `AppState::fuzzy_find`).
+    Fuzzy(FilterQuery),
+}
+
+/// A deferred action that consumes the next key event of a multi-key sequence.
+type PendingAction = Box<dyn FnOnce(KeyEvent, &mut AppState)>;
+
+/// A listing of the subdirectories of one path component, used for descending.
+struct ChildList {
+    /// The index in `path_parts` of the component whose children are listed.
+    parent_index: usize,
+    /// The names of the subdirectories, sorted.
+    entries: Vec<OsString>,
+    /// Lazily computed size annotations, one per entry.
+    annotations: Vec<OnceCell<String>>,
+    /// The index of the highlighted entry.
+    selected: usize,
+}
+
+/// The text typed in `Command` mode, along with any in-progress Tab completion.
+#[derive(Default)]
+struct CommandLine {
+    /// The command typed so far, without the leading `:`.
+    input: String,
+    /// The completions being cycled through by repeated presses of Tab.
+    completion: Option<Completion>,
+}
+
+/// The text typed in `Filter` or `Fuzzy` mode.
+struct FilterQuery {
+    /// The text typed so far, without the prompt.
+    query: String,
+    /// The selection before typing began, restored by `Esc`.
+    previous_index: usize,
+}
+
+/// The state of an ambiguous Tab completion.
+struct Completion {
+    /// Every possible completion of the command's path argument.
+    candidates: Vec<String>,
+    /// The index of the candidate currently inserted into the command.
+    index: usize,
+}
+
+impl CommandLine {
+    /// Completes the path argument of a `cd` command, relative to `base`.
+    ///
+    /// A unique match is inserted in full (with a trailing separator, so another
+    /// Tab continues into it). When several directories match, the first is
+    /// inserted and repeated Tabs cycle through the rest, like a shell.
+    fn complete(&mut self, base: &Path) {
+        if let Some(completion) = &mut self.completion {
+            completion.index = (completion.index + 1) % completion.candidates.len();
+            self.input = format!("cd {}", completion.candidates[completion.index]);
+            return;
+        }
+
+        let Some(partial) = self.input.strip_prefix("cd ") else {
+            return;
+        };
+        let candidates = complete_path(base, partial.trim_start());
+        if let Some(first) = candidates.first() {
+            self.input = format!("cd {}", first);
+        }
+        if candidates.len() > 1 {
+            self.completion = Some(Completion {
+                candidates,
+                index: 0,
+            });
+        }
+    }
+}
+
+/// The number of entries shown at once in a `ChildList`.
+const MAX_LISTING_ROWS: usize = 10;
+
+/// The number of entries shown in the `PD_PREVIEW_DIR` line.
+const PREVIEW_ENTRIES: usize = 5;
+
+/// The number of entries shown in the preview pane, before a `…` for the rest.
+const PREVIEW_PANE_ROWS: usize = 10;
+
+/// How long the selection must rest on a component before it is previewed, so
+/// that moving quickly across the path does not read every directory on the way.
+const PREVIEW_DELAY: Duration = Duration::from_millis(150);
+
+/// Defines what, if anything, is shown next to each entry of a `ChildList`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SizeAnnotation {
+    /// No annotation. (Default)
+    None,
+    /// The number of entries directly inside the directory.
+    EntryCount,
+    /// The total size of all files below the directory. This can be expensive.
+    RecursiveSize,
+}
+
+/// Defines how the confirmed path is printed, as cycled with the format key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// The absolute path. (Default)
+    Absolute,
+    /// The path relative to the working directory, e.g. `../..`.
+    Relative,
+    /// The absolute path with the home directory written as `~`.
+    Tilde,
+}
+
+impl OutputFormat {
+    /// Returns the format that follows this one when cycling.
+    fn next(self) -> Self {
+        match self {
+            OutputFormat::Absolute => OutputFormat::Relative,
+            OutputFormat::Relative => OutputFormat::Tilde,
+            OutputFormat::Tilde => OutputFormat::Absolute,
+        }
+    }
+
+    /// Returns the name shown in the status line.
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Absolute => "absolute",
+            OutputFormat::Relative => "relative",
+            OutputFormat::Tilde => "~",
+        }
+    }
+
+    /// Rewrites the absolute `path` in this format, relative to `cwd` or with
+    /// `home` replaced by `~`. A path outside `home` is left absolute.
+    fn apply(self, path: PathBuf, cwd: &Path, home: Option<&Path>) -> PathBuf {
+        match self {
+            OutputFormat::Absolute => path,
+            OutputFormat::Relative => relative_path(&path, cwd),
+            OutputFormat::Tilde => match home.and_then(|home| path.strip_prefix(home).ok()) {
+                Some(rest) => Path::new("~").join(rest),
+                None => path,
+            },
+        }
+    }
+}
+
+/// The most events applied at once before drawing a frame (see `event_loop`).
+const MAX_EVENTS_PER_FRAME: usize = 64;
+
+/// The shortest time between two frames while events keep arriving faster.
+const MIN_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// The most keys that macros may replay between two real events.
+const MAX_REPLAYED_KEYS: usize = 1000;
+
+/// How long after a digit in `DigitMode::Select` a second one still extends it
+/// to a two-digit index.
+const DIGIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a multi-key sequence such as `f<char>` waits for its next key before
+/// it is cancelled.
+const SEQUENCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How soon after a left click a second one on the same component confirms it.
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+
+/// How soon after a scroll wheel event the next one moves twice as far, so that
+/// spinning the wheel covers a deep path quickly.
+const SCROLL_MOMENTUM_TIME: Duration = Duration::from_millis(80);
+
+/// Defines what the digit keys do in the Vim keymap.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DigitMode {
+    /// Digits accumulate a count for the next motion. (Default)
+    Count,
+    /// Digits select the component with that index, as shown in `numbered` mode.
+    Select,
+}
+
+/// Defines how the selected component is distinguished from the others.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HighlightMode {
+    /// The selected component is shown in reverse video. (Default)
+    Reverse,
+    /// All other components are dimmed, and the selected one is left as is.
+    DimOthers,
+    /// The selected component is shown in reverse video, and the components
+    /// below it, which confirming drops, are dimmed.
+    DimDescendants,
+}
+
+/// A custom look for the selected component, replacing the reverse video.
+#[derive(Clone, Default)]
+struct Style {
+    /// Attributes such as bold or underline, applied in order.
+    attributes: Vec<Attribute>,
+    /// The text color, if changed.
+    foreground: Option<Color>,
+    /// The background color, if changed.
+    background: Option<Color>,
+}
+
+impl Style {
+    /// Parses a comma-separated list such as `bold,underline` or
+    /// `fg=green,bg=black`, warning about and skipping unknown tokens.
+    fn parse(spec: &str) -> Style {
+        let mut style = Style::default();
+        for token in spec.split(',').map(str::trim).filter(|token| !token.is_empty()) {
+            let attribute = match token {
+                "bold" => Some(Attribute::Bold),
+                "underline" => Some(Attribute::Underlined),
+                "italic" => Some(Attribute::Italic),
+                "dim" => Some(Attribute::Dim),
+                "reverse" => Some(Attribute::Reverse),
+                _ => None,
+            };
+            let color = token
+                .split_once('=')
+                .and_then(|(key, name)| Some((key, color_by_name(name)?)));
+            match (attribute, color) {
+                (Some(attribute), _) => style.attributes.push(attribute),
+                (None, Some(("fg", color))) => style.foreground = Some(color),
+                (None, Some(("bg", color))) => style.background = Some(color),
+                _ => eprintln!("Warning: Unknown PD_STYLE token '{}', ignoring it", token),
+            }
+        }
+        style
+    }
+
+    /// Returns whether the style changes nothing.
+    fn is_empty(&self) -> bool {
+        self.attributes.is_empty() && self.foreground.is_none() && self.background.is_none()
+    }
+
+    /// Writes the escape sequences that switch to the style.
+    fn apply(&self, out: &mut impl Write) -> Result<()> {
+        for &attribute in &self.attributes {
+            queue!(out, SetAttribute(attribute))?;
+        }
+        if let Some(color) = self.foreground {
+            queue!(out, SetForegroundColor(color))?;
+        }
+        if let Some(color) = self.background {
+            queue!(out, SetBackgroundColor(color))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the basic terminal color called `name`, e.g. `red`, or its bright
+/// variant for names such as `bright-red`.
+fn color_by_name(name: &str) -> Option<Color> {
+    let color = match name {
+        "black" => Color::Black,
+        "red" => Color::DarkRed,
+        "green" => Color::DarkGreen,
+        "yellow" => Color::DarkYellow,
+        "blue" => Color::DarkBlue,
+        "magenta" => Color::DarkMagenta,
+        "cyan" => Color::DarkCyan,
+        "white" => Color::Grey,
+        "bright-black" => Color::DarkGrey,
+        "bright-red" => Color::Red,
+        "bright-green" => Color::Green,
+        "bright-yellow" => Color::Yellow,
+        "bright-blue" => Color::Blue,
+        "bright-magenta" => Color::Magenta,
+        "bright-cyan" => Color::Cyan,
+        "bright-white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
+}
+
+/// A listing of subdirectories read ahead of time on a background thread.
+///
+/// The thread sends its result over a bounded channel exactly once, so the
+/// event loop never blocks on it until the listing is actually needed.
+struct Prefetch {
+    /// The directory being listed.
+    path: PathBuf,
+    receiver: Receiver<Vec<OsString>>,
+    /// The listing, once it has been received.
+    entries: Option<Vec<OsString>>,
+}
+
+impl Prefetch {
+    /// Starts listing the subdirectories of `path` in the background.
+    fn spawn(path: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let thread_path = path.clone();
+        thread::spawn(move || {
+            // The receiver may be gone if the prefetch was invalidated meanwhile.
+            let _ = sender.send(list_subdirectories(&thread_path));
+        });
+        Self {
+            path,
+            receiver,
+            entries: None,
+        }
+    }
+
+    /// Returns the listing, waiting for the background thread if needed.
+    fn entries(&mut self) -> Vec<OsString> {
+        if self.entries.is_none() {
+            self.entries = Some(
+                self.receiver
+                    .recv()
+                    .unwrap_or_else(|_| list_subdirectories(&self.path)),
+            );
+        }
+        self.entries.clone().unwrap_or_default()
+    }
+}
+
+/// Which paths are printed when a visual range is confirmed.
+#[derive(Clone, Copy)]
+enum RangeOutput {
+    /// Every component in the range. (Default)
+    All,
+    /// Only the two ends of the range.
+    Ends,
+}
+
+/// What a motion does when it would go past either end of the path.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Boundary {
+    /// Stop at the end. (Default)
+    #[default]
+    Clamp,
+    /// Continue from the other end.
+    Wrap,
+}
+
+/// The `Boundary` used by each source of motions, as configured by `PD_WRAP`.
+#[derive(Clone, Copy, Default)]
+struct WrapSettings {
+    /// The left and right arrow keys.
+    arrows: Boundary,
+    /// The keymap's motion keys, such as `h` and `l` in the Vim keymap, and
+    /// `PD_INPUTRC` bindings.
+    keys: Boundary,
+    /// The scroll wheel.
+    scroll: Boundary,
+}
+
+/// Where the selection starts when `pd` is opened.
+#[derive(Clone, Copy)]
+pub enum InitialPos {
+    /// The deepest component, i.e. the directory itself.
+    End,
+    /// The first component (the root or prefix).
+    Start,
+    /// The middle component.
+    Middle,
+    /// `n` levels above the deepest component.
+    Depth(usize),
+}
+
+impl InitialPos {
+    /// Returns the index this position refers to in a path of `len` components.
+    fn index(self, len: usize) -> usize {
+        let last = len.saturating_sub(1);
+        match self {
+            InitialPos::End => last,
+            InitialPos::Start => 0,
+            InitialPos::Middle => len / 2,
+            InitialPos::Depth(n) => last.saturating_sub(n),
+        }
+    }
+}
+
+/// What to do when stderr is not a terminal, so the UI cannot be shown.
+#[derive(Clone, Copy)]
+enum NoTtyFallback {
+    /// Exit with an error explaining that a terminal is needed.
+    Error,
+    /// Print the starting directory as if it had been confirmed.
+    Cwd,
+    /// Print the parent of the starting directory, as if it had been confirmed
+    /// one level up.
+    Parent,
+}
+
+/// What to do when the path has only one part, so there is nothing to choose.
+#[derive(Clone, Copy)]
+enum SinglePart {
+    /// Show the UI anyway.
+    Interactive,
+    /// Print the path as if it had been confirmed, without showing the UI.
+    Confirm,
+    /// Exit without printing anything, as if the user had quit.
+    Quit,
+}
+
+/// How a path wider than the terminal is drawn.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// On one line, showing the parts around the selection. (Default)
+    Scroll,
+    /// On as many lines as it takes, breaking between parts.
+    Wrap,
+}
+
+/// What a part of the path is on disk, for `PD_COLORIZE_TYPES`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PartType {
+    /// A directory, drawn in the default color.
+    Directory,
+    /// A symlink, whether or not it leads anywhere, drawn in cyan.
+    Symlink,
+    /// Anything else that exists, such as a regular file, drawn in yellow.
+    File,
+    /// Nothing, or something that cannot be inspected, drawn in red.
+    Missing,
+}
+
+impl PartType {
+    /// Returns the color the part is drawn in, if not the default one.
+    fn color(self) -> Option<Color> {
+        match self {
+            PartType::Directory => None,
+            PartType::Symlink => Some(Color::DarkCyan),
+            PartType::File => Some(Color::DarkYellow),
+            PartType::Missing => Some(Color::DarkRed),
+        }
+    }
+}
+
+/// Where status messages are drawn.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StatusLayout {
+    /// On the breadcrumb line, after the path.
+    Inline,
+    /// On a line of their own below the breadcrumb.
+    Line,
+    /// On a line of their own, separated from the rest by a blank line.
+    Blank,
+    /// On a line of their own, separated from the rest by a horizontal rule.
+    Rule,
+}
+
+/// Defines the supported keymap schemes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Keymap {
+    /// Vim-style keybindings (h, j, k, l, etc.).
+    Vim,
+    /// Emacs-style keybindings (Ctrl-f, Ctrl-b, etc.).
+    Emacs,
+    /// No letter bindings: typing narrows the path to the components whose names
+    /// start with the typed text (see `AppState::narrow`).
+    None,
+    /// The keys most GUI applications use: the arrows, Home/End and
+    /// PageUp/PageDown. Letters do nothing.
+    Standard,
+}
+
+/// The bindings of the Vim keymap as `(key, action)` pairs, in the order they
+/// are listed by `--dump-keys`. Keys are written as for `PD_KEY_CONFIRM`, with
+/// names such as `left` and `f1` for keys that it does not accept.
+///
+/// This describes `handle_vim_keys`, and must be kept in step with it.
+const VIM_KEYS: &[(&str, &str)] = &[
+    ("h", "move-left"),
+    ("k", "move-left"),
+    ("b", "move-left"),
+    ("l", "move-right-or-descend"),
+    ("j", "move-right"),
+    ("w", "move-right"),
+    ("^", "move-to-start"),
+    ("H", "move-to-start"),
+    ("gg", "move-to-start"),
+    ("g/", "fuzzy-jump"),
+    ("0", "move-to-start"),
+    ("$", "move-to-end"),
+    ("G", "move-to-count-or-end"),
+    ("L", "move-to-end"),
+    ("M", "move-to-middle"),
+    ("%", "move-to-percent"),
+    ("C-d", "move-half-right"),
+    ("C-u", "move-half-left"),
+    ("D", "move-to-mount-point"),
+    ("|", "move-to-index"),
+    ("f", "jump-forward"),
+    ("F", "jump-backward"),
+    ("t", "till-forward"),
+    ("T", "till-backward"),
+    (";", "repeat-jump"),
+    (",", "repeat-jump-reversed"),
+    ("m", "set-mark"),
+    ("Q", "record-macro"),
+    ("@", "replay-macro"),
+    ("`", "jump-to-mark"),
+    ("'", "jump-to-mark"),
+    ("(", "move-to-even-index"),
+    (")", "move-to-odd-index"),
+    ("{", "move-to-previous-branch"),
+    ("}", "move-to-next-branch"),
+    ("#", "cycle-same-name"),
+    ("v", "toggle-range"),
+    ("p", "jump-to-clipboard"),
+    ("c", "copy-path"),
+    ("y", "copy-path-unquoted"),
+    ("Y", "copy-name"),
+    ("S", "git-status"),
+    ("o", "browse"),
+    (":", "command-mode"),
+    ("/", "filter"),
+    ("*", "sticky-count"),
+    ("R", "reload"),
+    ("=", "resolve-symlinks"),
+    ("P", "toggle-preview"),
+    ("~", "toggle-home"),
+    ("u", "undo-motion"),
+    ("C-r", "redo-motion"),
+    ("q", "quit"),
+];
+
+/// The bindings of the Emacs keymap, like `VIM_KEYS` for `handle_emacs_keys`.
+const EMACS_KEYS: &[(&str, &str)] = &[
+    ("C-b", "move-left"),
+    ("M-b", "move-left"),
+    ("C-f", "move-right"),
+    ("M-f", "move-right"),
+    ("C-a", "move-to-start"),
+    ("C-e", "move-to-end"),
+    ("C-u", "universal-argument"),
+    ("C-v", "move-half-right"),
+    ("M-v", "move-half-left"),
+    ("M-m", "move-to-mount-point"),
+    ("M-r", "resolve-symlinks"),
+    ("M-p", "toggle-preview"),
+    ("M-~", "toggle-home"),
+    ("C-/", "undo-motion"),
+    ("M-_", "redo-motion"),
+    ("C-]", "jump-forward"),
+    ("M-{", "move-to-previous-branch"),
+    ("M-}", "move-to-next-branch"),
+    ("M-s", "cycle-same-name"),
+    ("C-space", "toggle-range"),
+    ("C-y", "jump-to-clipboard"),
+    ("M-q", "copy-path"),
+    ("M-w", "copy-path-unquoted"),
+    ("M-W", "copy-name"),
+    ("M-g", "git-status"),
+    ("M-o", "browse"),
+    ("C-n", "list-children"),
+    ("M-x", "command-mode"),
+    ("/", "filter"),
+    ("C-s", "fuzzy-jump"),
+    ("q", "quit"),
+];
+
+/// The bindings of the `none` keymap (see `handle_narrow_keys`).
+const NONE_KEYS: &[(&str, &str)] = &[("<char>", "narrow"), ("backspace", "widen")];
+
+/// The bindings of the `standard` keymap (see `handle_standard_keys`).
+const STANDARD_KEYS: &[(&str, &str)] =
+    &[("pagedown", "move-half-right"), ("pageup", "move-half-left")];
+
+/// The bindings shared by every keymap, from `handle_normal_inputmode`.
+const SHARED_KEYS: &[(&str, &str)] = &[
+    ("left", "move-left"),
+    ("right", "move-right"),
+    ("home", "move-to-start"),
+    ("end", "move-to-end"),
+    ("down", "list-children"),
+    ("C-l", "reload"),
+    ("C-o", "jump-back"),
+    ("f1", "next-keymap"),
+    ("tab", "next-tab-or-zoom-in"),
+    ("S-tab", "previous-tab"),
+    ("esc", "quit"),
+    ("C-c", "interrupt"),
+    ("C-z", "suspend"),
+];
+
+impl Keymap {
+    /// Returns the bindings specific to this keymap as `(key, action)` pairs.
+    /// The `SHARED_KEYS` apply in addition.
+    fn key_table(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Keymap::Vim => VIM_KEYS,
+            Keymap::Emacs => EMACS_KEYS,
+            Keymap::None => NONE_KEYS,
+            Keymap::Standard => STANDARD_KEYS,
+        }
+    }
+
+    /// Returns the `PD_KEYMAP` value that selects this keymap.
+    fn name(self) -> &'static str {
+        match self {
+            Keymap::Vim => "vim",
+            Keymap::Emacs => "emacs",
+            Keymap::None => "none",
+            Keymap::Standard => "standard",
+        }
+    }
+}
+
+/// A single key press that an action can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    const ENTER: Self = Self {
+        code: KeyCode::Enter,
+        modifiers: KeyModifiers::NONE,
+    };
+
+    const CTRL_T: Self = Self {
+        code: KeyCode::Char('t'),
+        modifiers: KeyModifiers::CONTROL,
+    };
+
+    /// Parses a key spec such as `enter`, `space`, `tab`, `esc`, `x`, `C-x` or `M-x`.
+    /// The arrow keys, `home`, `end`, `backspace`, `delete` and `f1` to `f12` are
+    /// accepted as well.
+    fn parse(spec: &str) -> Option<Self> {
+        let (modifiers, name) = if let Some(name) = spec.strip_prefix("C-") {
+            (KeyModifiers::CONTROL, name)
+        } else if let Some(name) = spec.strip_prefix("M-") {
+            (KeyModifiers::ALT, name)
+        } else {
+            (KeyModifiers::NONE, spec)
+        };
+        let code = match name.to_ascii_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "space" => KeyCode::Char(' '),
+            "tab" => KeyCode::Tab,
+            "esc" | "escape" => KeyCode::Esc,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            function if let Some(Ok(n @ 1..=12)) =
+                function.strip_prefix('f').map(str::parse::<u8>) =>
+            {
+                KeyCode::F(n)
+            }
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        };
+        Some(Self { code, modifiers })
+    }
+
+    /// Returns the key spec that `parse` reads as this key, e.g. `enter` or `C-x`.
+    fn spec(&self) -> String {
+        let prefix = if self.modifiers.contains(KeyModifiers::CONTROL) {
+            "C-"
+        } else if self.modifiers.contains(KeyModifiers::ALT) {
+            "M-"
+        } else {
+            ""
+        };
+        let name = match self.code {
+            KeyCode::Enter => "enter".to_string(),
+            KeyCode::Char(' ') => "space".to_string(),
+            KeyCode::Tab => "tab".to_string(),
+            KeyCode::Esc => "esc".to_string(),
+            KeyCode::Left => "left".to_string(),
+            KeyCode::Right => "right".to_string(),
+            KeyCode::Up => "up".to_string(),
+            KeyCode::Down => "down".to_string(),
+            KeyCode::Home => "home".to_string(),
+            KeyCode::End => "end".to_string(),
+            KeyCode::Backspace => "backspace".to_string(),
+            KeyCode::Delete => "delete".to_string(),
+            KeyCode::F(n) => format!("f{}", n),
+            KeyCode::Char(c) => c.to_string(),
+            _ => "?".to_string(),
+        };
+        format!("{}{}", prefix, name)
+    }
+
+    /// Returns whether `key` is this key. Shift is ignored for characters,
+    /// since it is already reflected in the character itself.
+    fn matches(&self, key: &KeyEvent) -> bool {
+        let modifiers = match key.code {
+            KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+            _ => key.modifiers,
+        };
+        key.code == self.code && modifiers == self.modifiers
+    }
+}
+
+/// An action that can be bound to a key from an inputrc file.
+#[derive(Clone, Copy)]
+enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveToStart,
+    MoveToEnd,
+    Confirm,
+    JumpForward,
+    JumpBackward,
+    Reload,
+}
+
+impl Action {
+    /// Maps a readline function name to the closest action, if there is one.
+    fn from_readline_function(name: &str) -> Option<Self> {
+        match name {
+            "backward-char" | "backward-word" => Some(Action::MoveLeft),
+            "forward-char" | "forward-word" => Some(Action::MoveRight),
+            "beginning-of-line" => Some(Action::MoveToStart),
+            "end-of-line" => Some(Action::MoveToEnd),
+            "accept-line" => Some(Action::Confirm),
+            "character-search" => Some(Action::JumpForward),
+            "character-search-backward" => Some(Action::JumpBackward),
+            "clear-screen" | "re-read-init-file" => Some(Action::Reload),
+            _ => None,
+        }
+    }
+}
+
+/// The direction for a character-based jump (`f`/`F`).
+#[derive(Clone, Copy)]
+pub enum JumpDirection {
+    Forward,
+    Backward,
+}
+
+/// How character jumps compare case, set with `PD_JUMP_CASE`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum JumpCase {
+    /// The target only matches its own case.
+    Sensitive,
+    /// The target matches either case.
+    Insensitive,
+    /// Like `Insensitive`, unless the target is uppercase, as with Vim's `smartcase`.
+    Smart,
+}
+
+/// The parity of a component index, used by `move_to_parity`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Parity {
+    Even,
+    Odd,
+}
+
+/// Stores the state of the last character jump for repetition with `;` and `,`.
+#[derive(Clone, Copy)]
+struct LastJump {
+    char: char,
+    direction: JumpDirection,
+    /// Whether it was a "till" jump (`t` or `T`), which stops short of the match.
+    till: bool,
+}
+
+/// Holds the current state of the application.
+///
+/// This struct contains all the data necessary to drive the UI and logic.
+pub struct AppState {
+    /// A vector of strings representing the components of the current path.
+    path_parts: Vec<OsString>,
+    /// The index of the currently selected part in `path_parts`.
+    current_index: usize,
+    /// Stores numeric input for Vim-style count prefixes (e.g., `3h`).
+    count_input: String,
+    /// Set by `C-u` in the Emacs keymap while `count_input` holds its default of 4
+    /// (or 16, ...), which a typed digit replaces rather than extends.
+    universal_argument: bool,
+    /// Stores the last character jump action to allow for repeats.
+    last_jump: Option<LastJump>,
+    /// The character of a jump made with the last key, for `render` to underline
+    /// the other parts that `;` and `,` would move to.
+    jump_highlight: Option<char>,
+    /// The current input mode, used to handle multi-key sequences generically.
+    input_mode: InputMode,
+    /// Caches whether each visited directory branches (see `is_branching_dir`).
+    branching_cache: HashMap<PathBuf, bool>,
+    /// What to display next to each entry when listing subdirectories.
+    size_annotation: SizeAnnotation,
+    /// A short message displayed after the path until the next key press.
+    status_message: Option<String>,
+    /// Restricts motions and listings to components whose names match this glob.
+    filter: Option<GlobMatcher>,
+    /// How the selected component is highlighted in the breadcrumb.
+    highlight_mode: HighlightMode,
+    /// The look of the selected component set by `PD_STYLE`, used in place of
+    /// the reverse video.
+    selection_style: Option<Style>,
+    /// Set when `NO_COLOR` is, to mark the selection with brackets instead of
+    /// styling it.
+    no_color: bool,
+    /// The mount points of the system, used to find filesystem boundaries.
+    mount_points: HashSet<PathBuf>,
+    /// Whether components that are mount points are underlined.
+    show_mounts: bool,
+    /// How long the mouse must rest on a component before it is selected.
+    hover_delay: Duration,
+    /// The component under the mouse and when the mouse arrived there, while
+    /// waiting for `hover_delay` to elapse.
+    pending_hover: Option<(usize, Instant)>,
+    /// How the part under the mouse is shown, if it is. With a style, hovering
+    /// no longer selects by itself (see `hover_at`).
+    hover_style: Option<Attribute>,
+    /// The part under the mouse, when `hover_style` is set.
+    hover_index: Option<usize>,
+    /// The working directory `pd` was started from, as last seen by `reload`.
+    cwd: PathBuf,
+    /// Set when the next render should clear the whole screen first.
+    clear_screen: bool,
+    /// Set when the terminal was resized, so that the next render first clears
+    /// whatever the previous frame left, as rewrapped by the terminal.
+    resized: bool,
+    /// Whether each part is prefixed with its index, as in `0:/ 1:home/`.
+    numbered: bool,
+    /// Directories that navigation and confirmation are confined to.
+    /// Empty means unrestricted.
+    allowed_roots: Vec<PathBuf>,
+    /// The key that confirms the selection in `Normal` mode.
+    confirm_key: KeyBinding,
+    /// The key that quits in `Normal` mode instead of `q` and `Esc`, if one is set.
+    quit_key: Option<KeyBinding>,
+    /// The read-ahead listing of the deepest component, if one was started.
+    prefetch: Option<Prefetch>,
+    /// Whether jump matching compares names in Unicode NFC form.
+    normalize_unicode: bool,
+    /// A count that `move_by` keeps using until cleared, set with `*`.
+    sticky_count: Option<usize>,
+    /// Whether lowercase text typed to narrow or filter also matches uppercase
+    /// characters. This is also the default for `jump_case`.
+    smartcase: bool,
+    /// How `f`, `F`, `t` and `T` and their repeats compare case.
+    jump_case: JumpCase,
+    /// Whether symlinked parts are marked with an `@`.
+    show_symlinks: bool,
+    /// The indices of parts that are symlinks, kept by `refresh_symlinks`.
+    symlinks: HashSet<usize>,
+    /// Whether parts are colored by what they are on disk (see `PartType`).
+    colorize_types: bool,
+    /// What each part is on disk, by index, kept by `refresh_part_types`.
+    part_types: HashMap<usize, PartType>,
+    /// Whether each part shows how many subdirectories it has, as in `home²/`.
+    show_child_counts: bool,
+    /// The number of subdirectories of each part that could be read, by index,
+    /// kept by `refresh_child_counts`.
+    child_counts: HashMap<usize, usize>,
+    /// Whether the root or prefix component is excluded from selection.
+    skip_root: bool,
+    /// The lowest selectable index, kept by `update_min_index`.
+    min_index: usize,
+    /// Whether a line of carets is drawn under the selected part.
+    show_caret: bool,
+    /// Whether a line below the path shows the full selected path, its position
+    /// (e.g. `[3/7]`), and any count being typed.
+    show_path_line: bool,
+    /// Bindings read from `PD_INPUTRC`, checked before the keymap.
+    custom_bindings: Vec<(KeyBinding, Action)>,
+    /// Whether the selection's distance from the deepest part is shown as `../..`.
+    show_dotdots: bool,
+    /// Whether the path is drawn from the current directory on the left to the
+    /// root on the right. Left and right motions are mirrored to match.
+    reverse: bool,
+    /// Whether the deepest part is always drawn in bold, as an anchor.
+    bold_last: bool,
+    /// Where status messages are drawn.
+    status_layout: StatusLayout,
+    /// A custom separator to split the path on instead of filesystem components.
+    separator: Option<char>,
+    /// Whether to show the first few entries of the selected directory below the path.
+    preview_dir: bool,
+    /// Whether to list the entries of the selected directory on the lines below
+    /// the path, one per line, instead.
+    preview_pane: bool,
+    /// The contents of each component that has been previewed, by index.
+    previews: HashMap<usize, DirPreview>,
+    /// The component waiting to be previewed and when it was selected.
+    pending_preview: Option<(usize, Instant)>,
+    /// How many components the arrow keys move at a time.
+    step: isize,
+    /// How many components a scroll wheel event moves, before any momentum.
+    scroll_step: isize,
+    /// When the last scroll wheel event was, for its momentum.
+    last_scroll: Option<Instant>,
+    /// Whether motions from each source stop or wrap around at the ends.
+    wrap: WrapSettings,
+    /// The other end of the visual range, while one is being selected.
+    anchor: Option<usize>,
+    /// Which paths a confirmed visual range prints.
+    range_output: RangeOutput,
+    /// The directory components above which are drawn dimmed, as "outside the project".
+    focus_root: Option<PathBuf>,
+    /// The index of `focus_root` in `path_parts`, if it is one of the components.
+    focus_index: Option<usize>,
+    /// Whether the parts up to the home directory are drawn as a single `~/`.
+    tilde: bool,
+    /// The index of the home directory in `path_parts`, with `tilde`, if it is
+    /// one of the components (see `collapsed_home`).
+    home_index: Option<usize>,
+    /// Whether the collapsed home directory is drawn expanded for the moment
+    /// (see `toggle_home`).
+    expand_home: bool,
+    /// Whether to leave a plain-text breadcrumb on the terminal after confirming.
+    keep_breadcrumb: bool,
+    /// The component the left mouse button was pressed on, while it is held.
+    /// Dragging to another component makes a visual range from this one.
+    drag_start: Option<usize>,
+    /// When and on which component the left mouse button was last pressed, so
+    /// that a second press soon after on the same one is a double click.
+    last_click: Option<(Instant, usize)>,
+    /// The text typed so far with the `none` keymap. Only components whose names
+    /// start with it are landable; the others are drawn dimmed.
+    narrow: String,
+    /// With `PD_TABULAR_WIDTH`, the number of columns every part is padded or
+    /// truncated to, so that paths line up across invocations.
+    cell_width: Option<usize>,
+    /// With `PD_SELECTED_MIN_WIDTH`, the number of characters of the selected
+    /// part that a `cell_width` never cuts off.
+    selected_min_width: usize,
+    /// The `PD_FILEMANAGER` command, e.g. `lf` or `nnn -d`, to browse the selection in.
+    filemanager: Option<String>,
+    /// What the digit keys do in the Vim keymap.
+    digit_mode: DigitMode,
+    /// In `DigitMode::Select`, the digit just typed and when, which a second digit
+    /// typed within `DIGIT_TIMEOUT` extends.
+    pending_digit: Option<(usize, Instant)>,
+    /// The active keymap. It starts out as `PD_KEYMAP` and is cycled with `F1`.
+    keymap: Keymap,
+    /// Whether parts other than the last and the selected one are shortened to
+    /// their first letter, as in fish's prompt (see `abbreviate_part`).
+    abbreviate: bool,
+    /// Named marks set with `m<letter>`, from the letter to the marked index.
+    marks: HashMap<char, usize>,
+    /// Bookmarks set with `m` and an uppercase letter, kept across sessions in
+    /// the `bookmarks_file`.
+    bookmarks: HashMap<char, PathBuf>,
+    /// A path that a key sequence asked to confirm, such as a bookmark, handed
+    /// on by `handle_key_event` once the sequence is done.
+    pending_confirm: Option<PathBuf>,
+    /// Whether confirming a part above the deepest one lists its subdirectories
+    /// instead, so that exploring comes before confirming (see `confirm_or_drill`).
+    drill_down: bool,
+    /// How the confirmed path will be printed.
+    output_format: OutputFormat,
+    /// The key that cycles `output_format`.
+    format_key: KeyBinding,
+    /// Recorded macros, from the register letter to the keys pressed.
+    registers: HashMap<char, Vec<KeyEvent>>,
+    /// When the key that started a pending `WaitForNextKey` sequence was pressed.
+    pending_since: Option<Instant>,
+    /// The selections that keys moved away from, most recent last, for `Ctrl-o`
+    /// to go back to. Changing the path clears it.
+    index_history: Vec<usize>,
+    /// The selections that `Ctrl-o` went back from, most recent last, for a redo
+    /// to return to. Any other move clears it, as does changing the path.
+    redo_history: Vec<usize>,
+    /// Whether the last key went back or forward through the history, and so
+    /// should not add to it.
+    went_back: bool,
+    /// The register being recorded into with `Q`, and the keys so far.
+    recording: Option<(char, Vec<KeyEvent>)>,
+    /// Keys of a macro being replayed, fed to the event loop before any input.
+    replay: VecDeque<KeyEvent>,
+    /// The width of the line the path is drawn on, as of the last frame.
+    view_width: usize,
+    /// Whether a path wider than `view_width` scrolls or wraps onto more lines.
+    layout: Layout,
+    /// The screen row the breadcrumb starts on, with `Layout::Wrap`, so that
+    /// clicks can be mapped onto its rows. It is moved up whenever a frame
+    /// reaches the bottom of the screen and so scrolls the terminal.
+    frame_row: Option<u16>,
+    /// The positions in `display_order` of the parts drawn by the last frame,
+    /// kept by `update_viewport`. A path wider than `view_width` is drawn in part.
+    view_range: std::ops::Range<usize>,
+    /// Whether `path_parts` are independent items to pick from (`--pick`) rather
+    /// than the components of one path. Each item is then a whole path.
+    pick: bool,
+    /// The path that was selected when `pd` started, to tell whether a confirmed
+    /// selection is any different.
+    initial_path: PathBuf,
+    /// Whether `S` (`M-g` in Emacs) may run `git` to summarize a repository's status.
+    git_status: bool,
+    /// The summaries shown by `show_git_status`, by path, so that `git` runs once
+    /// per part. `None` if the path is not in a repository or `git` failed.
+    git_summaries: HashMap<PathBuf, Option<String>>,
+    /// Whether each frame is built in memory and written to the terminal at once,
+    /// rather than piece by piece as it is drawn.
+    batch_render: bool,
+    /// Which of the start paths `pd` was given this state navigates, from 0.
+    tab: usize,
+    /// The states of the other tabs, in the order they follow this one (see
+    /// `switch_tab`). Empty unless `pd` was given several start paths.
+    tabs: VecDeque<AppState>,
+}
+
+impl AppState {
+    /// Creates a new `AppState` from a vector of path components, selecting the
+    /// component at `initial_position`.
+    pub fn new(path_parts: Vec<OsString>, initial_position: InitialPos) -> Self {
+        let current_index = initial_position.index(path_parts.len());
+        Self {
+            path_parts,
+            current_index,
+            count_input: String::new(),
+            universal_argument: false,
+            last_jump: None,
+            jump_highlight: None,
+            input_mode: InputMode::Normal,
+            branching_cache: HashMap::new(),
+            size_annotation: SizeAnnotation::None,
+            status_message: None,
+            filter: None,
+            highlight_mode: HighlightMode::Reverse,
+            selection_style: None,
+            no_color: false,
+            mount_points: HashSet::new(),
+            show_mounts: false,
+            hover_delay: Duration::ZERO,
+            pending_hover: None,
+            hover_style: None,
+            hover_index: None,
+            cwd: PathBuf::new(),
+            clear_screen: false,
+            resized: false,
+            numbered: false,
+            allowed_roots: Vec::new(),
+            confirm_key: KeyBinding::ENTER,
+            quit_key: None,
+            prefetch: None,
+            normalize_unicode: false,
+            sticky_count: None,
+            smartcase: false,
+            jump_case: JumpCase::Sensitive,
+            show_symlinks: false,
+            symlinks: HashSet::new(),
+            colorize_types: false,
+            part_types: HashMap::new(),
+            show_child_counts: false,
+            child_counts: HashMap::new(),
+            skip_root: false,
+            min_index: 0,
+            show_caret: false,
+            show_path_line: false,
+            custom_bindings: Vec::new(),
+            show_dotdots: false,
+            reverse: false,
+            bold_last: false,
+            status_layout: StatusLayout::Inline,
+            separator: None,
+            preview_dir: false,
+            preview_pane: false,
+            previews: HashMap::new(),
+            pending_preview: None,
+            step: 1,
+            scroll_step: 1,
+            wrap: WrapSettings::default(),
+            anchor: None,
+            range_output: RangeOutput::All,
+            focus_root: None,
+            focus_index: None,
+            tilde: false,
+            home_index: None,
+            expand_home: false,
+            keep_breadcrumb: false,
+            drag_start: None,
+            last_click: None,
+            last_scroll: None,
+            narrow: String::new(),
+            cell_width: None,
+            selected_min_width: 0,
+            filemanager: None,
+            digit_mode: DigitMode::Count,
+            pending_digit: None,
+            keymap: Keymap::Vim,
+            abbreviate: false,
+            marks: HashMap::new(),
+            bookmarks: HashMap::new(),
+            pending_confirm: None,
+            drill_down: false,
+            output_format: OutputFormat::Absolute,
+            format_key: KeyBinding::CTRL_T,
+            registers: HashMap::new(),
+            pending_since: None,
+            index_history: Vec::new(),
+            redo_history: Vec::new(),
+            went_back: false,
+            recording: None,
+            replay: VecDeque::new(),
+            view_width: usize::MAX,
+            layout: Layout::Scroll,
+            frame_row: None,
+            view_range: 0..0,
+            pick: false,
+            initial_path: PathBuf::new(),
+            git_status: false,
+            git_summaries: HashMap::new(),
+            batch_render: false,
+            tab: 0,
+            tabs: VecDeque::new(),
+        }
+    }
+
+    /// Moves the selection index by a given step.
+    ///
+    /// `step` can be positive (move right) or negative (move left).
+    /// The movement distance is multiplied by the number accumulated in `count_input`.
+    /// The index is clamped to the valid range of `[0, path_parts.len() - 1]`.
+    ///
+    /// Only components accepted by `is_landable` are counted as steps.
+    ///
+    /// `boundary` decides what happens when the motion would go past either end.
+    pub fn move_by(&mut self, step: isize, boundary: Boundary) {
+        // Keep motions going the way they look when the path is drawn reversed.
+        let step = if self.reverse { step.saturating_neg() } else { step };
+        let count = self
+            .count_input
+            .parse::<isize>()
+            .ok()
+            .or(self.sticky_count.map(|count| count as isize))
+            .unwrap_or(1);
+        self.count_input.clear(); // Reset count after movement
+
+        let landable = self.landable_indices();
+        if landable.is_empty() {
+            self.status_message = Some(NO_LANDABLE_MESSAGE.to_string());
+            return;
+        }
+        // Locate the selection among the landable components. If it is not
+        // landable itself, start from the neighbor behind it in the direction of travel.
+        let position = match landable.binary_search(&self.current_index) {
+            Ok(position) => position as isize,
+            Err(position) if step < 0 => position as isize,
+            Err(position) => position as isize - 1,
+        };
+        // Saturate, since `PD_STEP` and the count can each be as large as an isize.
+        let target = position.saturating_add(step.saturating_mul(count));
+        let target = match boundary {
+            Boundary::Clamp => target.clamp(0, landable.len() as isize - 1),
+            Boundary::Wrap => target.rem_euclid(landable.len() as isize),
+        };
+        self.current_index = landable[target as usize];
+    }
+
+    /// Returns the next component, going toward the root and wrapping around to
+    /// the deepest, whose name equals that of the component at `from`.
+    fn next_same_name(&self, from: usize) -> Option<usize> {
+        let name = component_name(self.path_parts.get(from)?);
+        let len = self.path_parts.len();
+        (1..len)
+            .map(|offset| (from + len - offset) % len)
+            .find(|&i| self.is_landable(i) && component_name(&self.path_parts[i]) == name)
+    }
+
+    /// Jumps to the next component with the same name as the selected one (see
+    /// `next_same_name`), showing the position among all such components.
+    fn cycle_same_name(&mut self) {
+        self.count_input.clear();
+        let Some(index) = self.next_same_name(self.current_index) else {
+            self.status_message = Some("no other part with this name".to_string());
+            return;
+        };
+        self.current_index = index;
+        let name = component_name(&self.path_parts[index]);
+        let matches: Vec<usize> = (0..self.path_parts.len())
+            .filter(|&i| self.is_landable(i) && component_name(&self.path_parts[i]) == name)
+            .collect();
+        let position = matches.iter().position(|&i| i == index).unwrap_or(0) + 1;
+        self.status_message = Some(format!("{}/{}", position, matches.len()));
+    }
+
+    /// Makes the pending count sticky, or clears the sticky count if there is
+    /// no pending count.
+    fn set_sticky_count(&mut self) {
+        self.sticky_count = self.count_input.parse::<usize>().ok().filter(|&count| count > 0);
+        self.count_input.clear();
+    }
+
+    /// Moves the selection to the start of the path (the first component).
+    ///
+    /// This behaves like an editor's "smart home": if the selection is already at
+    /// the start, it moves on to the first real directory after the root or
+    /// prefix instead, so repeated presses toggle between the two.
+    fn move_to_start(&mut self) {
+        self.count_input.clear();
+        let current = self.current_index;
+        let first_normal = self.first_normal_index();
+        self.select_landable(|landable| {
+            let first = *landable.first()?;
+            if current == first {
+                landable.iter().copied().find(|&i| i >= first_normal).or(Some(first))
+            } else {
+                Some(first)
+            }
+        });
+    }
+
+    /// Returns the index of the first component that is a directory name rather
+    /// than a root or prefix (e.g. `home/` in `/home/user`, or 0 for relative paths).
+    fn first_normal_index(&self) -> usize {
+        let is_root = self.path_parts.first().is_some_and(|part| {
+            let path = Path::new(part);
+            path.has_root() || matches!(path.components().next(), Some(Component::Prefix(_)))
+        });
+        usize::from(is_root)
+    }
+
+    /// Moves the selection to the end of the path (the last component).
+    fn move_to_end(&mut self) {
+        self.count_input.clear();
+        self.select_landable(|landable| landable.last().copied());
+    }
+
+    /// Moves the selection to the middle of the path.
+    fn move_to_middle(&mut self) {
+        self.count_input.clear();
+        self.select_landable(|landable| landable.get(landable.len() / 2).copied());
+    }
+
+    /// Moves the selection by half the number of components in `direction`,
+    /// stopping at either end, like a half-page scroll. A count multiplies the step.
+    fn move_half(&mut self, direction: JumpDirection) {
+        let half = (self.path_parts.len() / 2).max(1) as isize;
+        let step = match direction {
+            JumpDirection::Forward => half,
+            JumpDirection::Backward => -half,
+        };
+        self.move_by(step, Boundary::Clamp);
+    }
+
+    /// Moves to the `n`-th component in `direction` whose index has the given parity.
+    ///
+    /// `n` is taken from `count_input` and defaults to 1. This is useful for paths
+    /// where only every other level is meaningful. The selection stops at the last
+    /// matching component if fewer than `n` exist, and does not move if none do.
+    fn move_to_parity(&mut self, parity: Parity, direction: JumpDirection) {
+        let count = self.count_input.parse::<usize>().unwrap_or(1);
+        self.count_input.clear();
+
+        let matches_parity = |i: &usize| i.is_multiple_of(2) == (parity == Parity::Even);
+        let candidates: Vec<usize> = match direction {
+            JumpDirection::Forward => ((self.current_index + 1)..self.path_parts.len())
+                .filter(matches_parity)
+                .filter(|&i| self.is_landable(i))
+                .collect(),
+            JumpDirection::Backward => (0..self.current_index)
+                .rev()
+                .filter(matches_parity)
+                .filter(|&i| self.is_landable(i))
+                .collect(),
+        };
+        if let Some(&index) = candidates.get(count.saturating_sub(1)).or(candidates.last()) {
+            self.current_index = index;
+        }
+    }
+
+    /// Returns whether a different filesystem is mounted at the component at `index`.
+    fn is_mount_point(&self, index: usize) -> bool {
+        self.mount_points.contains(&self.path_at(index))
+    }
+
+    /// Selects the part whose index is given by `count_input`, as shown in
+    /// `numbered` mode. Without a count the first part is selected, and an index
+    /// past the end selects the last part.
+    fn move_to_index(&mut self) {
+        let index = self.count_input.parse::<usize>().unwrap_or(0);
+        self.count_input.clear();
+        if index >= self.path_parts.len() {
+            self.move_to_end();
+        } else if self.is_landable(index) {
+            self.current_index = index;
+        }
+    }
+
+    /// Selects the part numbered by `count_input` from the root, counting from 1
+    /// like Vim's `G` counts lines. Without a count the last part is selected,
+    /// and so is it for a number past the end.
+    fn move_to_count_or_end(&mut self) {
+        match self.count_input.parse::<usize>() {
+            Ok(number @ 1..) if number <= self.path_parts.len() => {
+                self.count_input.clear();
+                if self.is_landable(number - 1) {
+                    self.current_index = number - 1;
+                } else {
+                    self.status_message = Some(NO_LANDABLE_MESSAGE.to_string());
+                }
+            }
+            _ => self.move_to_end(),
+        }
+    }
+
+    /// Selects the part `count_input` percent of the way from the root to the
+    /// last part, like Vim's `{count}%`, or the landable part nearest to it.
+    /// Counts over 100 mean 100, and without a count the middle part is selected.
+    fn move_to_percent(&mut self) {
+        let Ok(percent) = self.count_input.parse::<usize>() else {
+            self.move_to_middle();
+            return;
+        };
+        self.count_input.clear();
+        let index = percent.min(100) * self.path_parts.len().saturating_sub(1) / 100;
+        self.select_landable(|landable| {
+            landable.iter().copied().min_by_key(|&i| i.abs_diff(index))
+        });
+    }
+
+    /// Starts or multiplies a count the Emacs way: `C-u` alone means 4, and each
+    /// further `C-u` multiplies it by 4 until a digit is typed.
+    fn universal_argument(&mut self) {
+        let count = match self.count_input.parse::<usize>() {
+            Ok(count) if self.universal_argument => count.saturating_mul(4),
+            _ => 4,
+        };
+        self.count_input = count.to_string();
+        self.universal_argument = true;
+    }
+
+    /// Adds a digit typed after `C-u` to the count. The first one replaces the
+    /// default of `C-u`, so `C-u 3` means 3. Digits without a `C-u` are ignored.
+    fn universal_argument_digit(&mut self, digit: char) {
+        if self.count_input.is_empty() {
+            return;
+        }
+        if std::mem::take(&mut self.universal_argument) {
+            self.count_input.clear();
+        }
+        self.count_input.push(digit);
+    }
+
+    /// Marks the selected component with `letter`, replacing any earlier mark.
+    /// An uppercase letter sets a bookmark instead (see `set_bookmark`).
+    fn set_mark(&mut self, letter: char) {
+        if letter.is_ascii_uppercase() {
+            return self.set_bookmark(letter);
+        }
+        self.marks.insert(letter, self.current_index);
+    }
+
+    /// Selects the component marked with `letter`, or says that there is none.
+    /// An uppercase letter confirms the bookmarked path instead.
+    fn jump_to_mark(&mut self, letter: char) {
+        if letter.is_ascii_uppercase() {
+            return self.confirm_bookmark(letter);
+        }
+        match self.marks.get(&letter) {
+            Some(&index) if self.is_landable(index) => self.current_index = index,
+            Some(_) => self.status_message = Some(NO_LANDABLE_MESSAGE.to_string()),
+            None => self.status_message = Some(format!("mark '{}' is not set", letter)),
+        }
+    }
+
+    /// Bookmarks the selected path under `letter` and saves the bookmarks, so
+    /// that the bookmark lasts beyond the session.
+    fn set_bookmark(&mut self, letter: char) {
+        let Some(file) = bookmarks_file() else {
+            self.status_message = Some("cannot find the home directory".to_string());
+            return;
+        };
+        self.bookmarks.insert(letter, self.selected_path());
+        self.status_message = Some(match save_bookmarks(&file, &self.bookmarks) {
+            Ok(()) => format!("bookmarked as '{}'", letter),
+            Err(e) => format!("bookmark failed: {}", e),
+        });
+    }
+
+    /// Asks to confirm the path bookmarked under `letter`, if it is still a
+    /// directory (see `pending_confirm`).
+    fn confirm_bookmark(&mut self, letter: char) {
+        match self.bookmarks.get(&letter) {
+            Some(path) if path.is_dir() => self.pending_confirm = Some(path.clone()),
+            Some(_) => self.status_message = Some(format!("bookmark '{}' is gone", letter)),
+            None => self.status_message = Some(format!("bookmark '{}' is not set", letter)),
+        }
+    }
+
+    /// Finishes recording a macro and stores it in its register. The `Q` that
+    /// stopped the recording was recorded too, and is dropped again.
+    fn stop_recording(&mut self) {
+        if let Some((register, mut keys)) = self.recording.take() {
+            keys.pop();
+            self.registers.insert(register, keys);
+        }
+    }
+
+    /// Queues the keys of the macro in `register` to be replayed.
+    fn replay_register(&mut self, register: char) {
+        match self.registers.get(&register) {
+            Some(keys) => self.replay.extend(keys.iter().copied()),
+            None => self.status_message = Some(format!("register '{}' is empty", register)),
+        }
+    }
+
+    /// Switches to the next `OutputFormat` and says which one is now active.
+    fn cycle_output_format(&mut self) {
+        self.output_format = self.output_format.next();
+        self.status_message = Some(format!("output: {}", self.output_format.name()));
+    }
+
+    /// Switches to the next keymap, Vim → Emacs → None → Standard → Vim, and says
+    /// which one is now active in the status line.
+    fn cycle_keymap(&mut self) {
+        self.keymap = match self.keymap {
+            Keymap::Vim => Keymap::Emacs,
+            Keymap::Emacs => Keymap::None,
+            Keymap::None => Keymap::Standard,
+            Keymap::Standard => Keymap::Vim,
+        };
+        // A count or narrowing text typed for the old keymap means nothing in the new one.
+        self.count_input.clear();
+        self.narrow.clear();
+        self.status_message = Some(format!("keymap: {}", self.keymap.name()));
+    }
+
+    /// Makes the next tab (or the previous one, unless `forward`) the active one,
+    /// wrapping around. Each tab keeps its own path and selection, while macros
+    /// and the keymap carry over, since they belong to the session.
+    fn switch_tab(&mut self, forward: bool) {
+        let mut tabs = std::mem::take(&mut self.tabs);
+        let next = if forward { tabs.pop_front() } else { tabs.pop_back() };
+        let Some(mut next) = next else {
+            return;
+        };
+        next.registers = std::mem::take(&mut self.registers);
+        next.bookmarks = std::mem::take(&mut self.bookmarks);
+        next.recording = self.recording.take();
+        next.replay = std::mem::take(&mut self.replay);
+        next.keymap = self.keymap;
+        next.frame_row = self.frame_row;
+        let previous = std::mem::replace(self, next);
+        if forward {
+            tabs.push_back(previous);
+        } else {
+            tabs.push_front(previous);
+        }
+        self.tabs = tabs;
+    }
+
+    /// Selects the part with the index typed as `digit` in `DigitMode::Select`.
+    ///
+    /// The part is selected right away. A second digit typed within
+    /// `DIGIT_TIMEOUT` makes a two-digit index with the first one, unless no
+    /// part has that index, in which case it starts over as a new first digit.
+    fn select_digit(&mut self, digit: usize, now: Instant) {
+        let index = match self.pending_digit.take() {
+            Some((first, at)) if now.duration_since(at) < DIGIT_TIMEOUT
+                && first * 10 + digit < self.path_parts.len() =>
+            {
+                first * 10 + digit
+            }
+            _ => {
+                self.pending_digit = Some((digit, now));
+                digit
+            }
+        };
+        if index < self.path_parts.len() && self.is_landable(index) {
+            self.current_index = index;
+        }
+    }
+
+    /// Moves the selection to the nearest ancestor that is a mount point.
+    ///
+    /// This repeats `count_input` times, and does nothing if no such ancestor
+    /// exists (e.g. when mount points are unknown on this platform).
+    fn move_to_mount_point(&mut self) {
+        let count = self.count_input.parse::<usize>().unwrap_or(1);
+        self.count_input.clear();
+
+        let mut ancestors = (0..self.current_index)
+            .rev()
+            .filter(|&i| self.is_mount_point(i) && self.is_landable(i));
+        if let Some(index) = ancestors.nth(count.saturating_sub(1)) {
+            self.current_index = index;
+        }
+    }
+
+    /// Returns whether motions may land on the component at `index`.
+    ///
+    /// Without a `filter`, every component is landable. With one, only components
+    /// whose name (without its trailing separator) matches the glob are.
+    fn is_landable(&self, index: usize) -> bool {
+        index >= self.min_index
+            && self.is_allowed(index)
+            && self.matches_narrow(index)
+            && self.filter.as_ref().is_none_or(|matcher| {
+            self.path_parts
+                .get(index)
+                .is_some_and(|part| matcher.is_match(component_name(part)))
+        })
+    }
+
+    /// Returns whether `path` lies within one of the `allowed_roots`.
+    ///
+    /// The roots are canonical, so a path that does not lie within one as given
+    /// is canonicalized too, in case it reaches a root through a symlink.
+    fn is_path_allowed(&self, path: &Path) -> bool {
+        let within = |path: &Path| self.allowed_roots.iter().any(|root| path.starts_with(root));
+        self.allowed_roots.is_empty()
+            || within(path)
+            || fs::canonicalize(path).is_ok_and(|path| within(&path))
+    }
+
+    /// Returns whether the component at `index` lies within the `allowed_roots`.
+    fn is_allowed(&self, index: usize) -> bool {
+        self.allowed_roots.is_empty() || self.is_path_allowed(&self.path_at(index))
+    }
+
+    /// Returns the index of the shallowest allowed component, if any.
+    ///
+    /// Components below an allowed one are allowed too, so this is the boundary
+    /// that ascending motions clamp at.
+    fn allowed_start(&self) -> Option<usize> {
+        (0..self.path_parts.len()).find(|&i| self.is_allowed(i))
+    }
+
+    /// Returns the indices of all landable components in ascending order.
+    fn landable_indices(&self) -> Vec<usize> {
+        (0..self.path_parts.len())
+            .filter(|&i| self.is_landable(i))
+            .collect()
+    }
+
+    /// Selects the landable component picked by `pick`, or reports that none exist.
+    fn select_landable(&mut self, pick: impl FnOnce(&[usize]) -> Option<usize>) {
+        match pick(&self.landable_indices()) {
+            Some(index) => self.current_index = index,
+            None => self.status_message = Some(NO_LANDABLE_MESSAGE.to_string()),
+        }
+    }
+
+    /// Implements the core logic for jumping to a path component containing a target character.
+    ///
+    /// This function searches for the `n`-th path component that contains `target_char`,
+    /// where `n` is determined by the numeric `count_input`. The search proceeds
+    /// from the current selection in the given `direction`. If a match is found,
+    /// `current_index` is updated to the new position. This function does not
+    /// record the jump for repetition; it only performs the search and selection.
+    pub fn find_and_select_char_match(&mut self, direction: JumpDirection, target_char: char) {
+        let count = self.count_input.parse::<usize>().unwrap_or(1);
+        self.count_input.clear();
+
+        // Step through the indices directly, checking the bounds before every step,
+        // so that an empty path or a selection at either end cannot overrun.
+        let len = self.path_parts.len();
+        let mut index = self.current_index.min(len.saturating_sub(1));
+        let mut found_count = 0;
+        loop {
+            index = match direction {
+                JumpDirection::Forward if index + 1 < len => index + 1,
+                JumpDirection::Backward if index > 0 => index - 1,
+                _ => return, // Reached the end without enough matches
+            };
+            if self.is_landable(index) && self.part_contains(&self.path_parts[index], target_char) {
+                found_count += 1;
+                if found_count >= count {
+                    self.current_index = index;
+                    return; // Found it
+                }
+            }
+        }
+    }
+
+    /// Returns whether the name of `part` contains `target_char`.
+    ///
+    /// With `normalize_unicode`, the name is compared in NFC form, so a typed
+    /// precomposed character also matches its decomposed spelling (as HFS+ and
+    /// APFS often store it). Case is compared as `jump_case` says.
+    fn part_contains(&self, part: &OsStr, target_char: char) -> bool {
+        let mut name = part.to_string_lossy().into_owned();
+        let mut target = target_char.to_string();
+        if self.normalize_unicode {
+            name = name.nfc().collect();
+            target = target.nfc().collect();
+        }
+        let fold = match self.jump_case {
+            JumpCase::Sensitive => false,
+            JumpCase::Insensitive => true,
+            JumpCase::Smart => !target_char.is_uppercase(),
+        };
+        if fold {
+            name = name.to_lowercase();
+            target = target.to_lowercase();
+        }
+        name.contains(&target)
+    }
+
+    /// Returns whether the name of the component at `index` starts with `narrow`.
+    ///
+    /// Names are compared like `part_contains` does: NFC-normalized with
+    /// `normalize_unicode`, and ignoring case with `smartcase` unless the typed
+    /// text contains an uppercase letter.
+    fn matches_narrow(&self, index: usize) -> bool {
+        self.narrow.is_empty()
+            || self
+                .folded_name(index, &self.narrow)
+                .is_some_and(|(name, prefix)| name.starts_with(&prefix))
+    }
+
+    /// Returns whether the name of the component at `index` contains `query`.
+    fn contains_query(&self, index: usize, query: &str) -> bool {
+        self.folded_name(index, query)
+            .is_some_and(|(name, query)| name.contains(&query))
+    }
+
+    /// Returns whether the name of the component at `index` contains the
+    /// characters of `query` in order, though not necessarily next to each other.
+    fn contains_subsequence(&self, index: usize, query: &str) -> bool {
+        self.folded_name(index, query).is_some_and(|(name, query)| {
+            let mut chars = name.chars();
+            query.chars().all(|c| chars.any(|name_char| name_char == c))
+        })
+    }
+
+    /// Returns whether the component at `index` matches the query being typed in
+    /// `Filter` or `Fuzzy` mode. Outside those modes every component matches.
+    fn matches_filter_query(&self, index: usize) -> bool {
+        match &self.input_mode {
+            InputMode::Filter(filter) => self.contains_query(index, &filter.query),
+            InputMode::Fuzzy(fuzzy) => self.contains_subsequence(index, &fuzzy.query),
+            _ => true,
+        }
+    }
+
+    /// Returns the shallowest landable component whose name contains the
+    /// characters of `query` in order, so that `Dcs` finds `Documents`.
+    pub fn fuzzy_find(&self, query: &str) -> Option<usize> {
+        (0..self.path_parts.len())
+            .find(|&i| self.is_landable(i) && self.contains_subsequence(i, query))
+    }
+
+    /// Returns the name of the component at `index` and `pattern`, both folded
+    /// for comparison by the `normalize_unicode` and `smartcase` settings.
+    fn folded_name(&self, index: usize, pattern: &str) -> Option<(String, String)> {
+        let part = self.path_parts.get(index)?;
+        let mut name = component_name(part).to_string_lossy().into_owned();
+        let mut pattern = pattern.to_string();
+        if self.normalize_unicode {
+            name = name.nfc().collect();
+            pattern = pattern.nfc().collect();
+        }
+        if self.smartcase && !pattern.chars().any(char::is_uppercase) {
+            name = name.to_lowercase();
+        }
+        Some((name, pattern))
+    }
+
+    /// Selects the shallowest landable component whose name contains `query`,
+    /// returning false if there is none.
+    fn select_first_containing(&mut self, query: &str) -> bool {
+        match (0..self.path_parts.len())
+            .find(|&i| self.is_landable(i) && self.contains_query(i, query))
+        {
+            Some(index) => {
+                self.current_index = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enters `Filter` mode, remembering the selection so `Esc` can restore it.
+    fn start_filter(&mut self) {
+        self.input_mode = InputMode::Filter(FilterQuery {
+            query: String::new(),
+            previous_index: self.current_index,
+        });
+    }
+
+    /// Enters `Fuzzy` mode, remembering the selection so `Esc` can restore it.
+    fn start_fuzzy(&mut self) {
+        self.count_input.clear();
+        self.input_mode = InputMode::Fuzzy(FilterQuery {
+            query: String::new(),
+            previous_index: self.current_index,
+        });
+    }
+
+    /// Appends `c` to the narrowing text and moves the selection onto a match.
+    ///
+    /// The selection stays put if it still matches, and otherwise moves to the
+    /// deepest component that does. A character that would leave no match is
+    /// rejected with a status message, so there is always something to confirm.
+    fn narrow_by(&mut self, c: char) {
+        self.narrow.push(c);
+        if self.is_landable(self.current_index) {
+            return;
+        }
+        match self.landable_indices().last() {
+            Some(&index) => self.current_index = index,
+            None => {
+                self.status_message = Some(format!("no component starts with '{}'", self.narrow));
+                self.narrow.pop();
+            }
+        }
+    }
+
+    /// Initiates a character-based jump (like Vim's `f` or `F`) and records it for repetition.
+    ///
+    /// This function wraps the core jump logic from `find_and_select_char_match`. After
+    /// executing the jump, it stores the `target_char` and `direction` in `last_jump`.
+    /// This allows the user to repeat the same search forward (`;`) or backward (`,`).
+    fn jump_to_char(&mut self, direction: JumpDirection, target_char: char) {
+        self.find_and_select_char_match(direction, target_char);
+        self.jump_highlight = Some(target_char);
+        // Record this jump so it can be repeated.
+        self.last_jump = Some(LastJump {
+            char: target_char,
+            direction,
+            till: false,
+        });
+    }
+
+    /// Initiates a "till" jump (like Vim's `t` or `T`) and records it for repetition.
+    fn till_char(&mut self, direction: JumpDirection, target_char: char) {
+        self.find_and_select_till_match(direction, target_char, false);
+        self.jump_highlight = Some(target_char);
+        self.last_jump = Some(LastJump {
+            char: target_char,
+            direction,
+            till: true,
+        });
+    }
+
+    /// Like `find_and_select_char_match`, but selects the landable component just
+    /// short of the match, on the side of the current selection. If there is none
+    /// (the match is next to the selection), the selection stays put.
+    ///
+    /// With `repeat`, the search starts one component further along, so that `;`
+    /// moves on past a match that the previous till jump stopped next to, as in Vim.
+    fn find_and_select_till_match(
+        &mut self,
+        direction: JumpDirection,
+        target_char: char,
+        repeat: bool,
+    ) {
+        let origin = self.current_index;
+        if repeat {
+            self.current_index = match direction {
+                JumpDirection::Forward => (origin + 1).min(self.path_parts.len().saturating_sub(1)),
+                JumpDirection::Backward => origin.saturating_sub(1),
+            };
+        }
+        let searched_from = self.current_index;
+        self.find_and_select_char_match(direction, target_char);
+        let found = self.current_index;
+        if found == searched_from {
+            self.current_index = origin;
+            return;
+        }
+        let before_match = match direction {
+            JumpDirection::Forward => (origin + 1..found).rev().find(|&i| self.is_landable(i)),
+            JumpDirection::Backward => (found + 1..origin).find(|&i| self.is_landable(i)),
+        };
+        self.current_index = before_match.unwrap_or(origin);
+    }
+
+    /// Repeats the last `f`, `F`, `t` or `T` jump.
+    ///
+    /// `reverse` determines the direction: `false` for the same direction (`;`),
+    /// `true` for the opposite direction (`,`).
+    fn repeat_jump(&mut self, reverse: bool) {
+        if let Some(last_jump) = self.last_jump {
+            let direction = if reverse {
+                match last_jump.direction {
+                    JumpDirection::Forward => JumpDirection::Backward,
+                    JumpDirection::Backward => JumpDirection::Forward,
+                }
+            } else {
+                last_jump.direction
+            };
+            self.jump_highlight = Some(last_jump.char);
+            // Use the character from the last jump and call the main jump function.
+            // The main jump function will handle the new count from `count_input`.
+            if last_jump.till {
+                self.find_and_select_till_match(direction, last_jump.char, true);
+            } else {
+                self.find_and_select_char_match(direction, last_jump.char);
+            }
+        }
+    }
+
+    /// Returns whether the directory at `index` branches, consulting the cache first.
+    fn is_branching_at(&mut self, index: usize) -> bool {
+        let path = self.path_at(index);
+        *self
+            .branching_cache
+            .entry(path)
+            .or_insert_with_key(|path| is_branching_dir(path))
+    }
+
+    /// Moves the selection to the next "branching" component in `direction`.
+    ///
+    /// Runs of directories that contain exactly one subdirectory (such as
+    /// `com/example/foo/` in Java source trees) are skipped over, so the selection
+    /// lands on the next ancestor or descendant where the tree actually forks.
+    /// The first and last components are always valid landing points. The motion
+    /// is repeated as many times as the number in `count_input`.
+    fn move_to_branch(&mut self, direction: JumpDirection) {
+        let count = self.count_input.parse::<usize>().unwrap_or(1);
+        self.count_input.clear();
+
+        let last = self.path_parts.len().saturating_sub(1);
+        for _ in 0..count {
+            let mut index = self.current_index;
+            let mut target = None;
+            loop {
+                match direction {
+                    JumpDirection::Forward if index < last => index += 1,
+                    JumpDirection::Backward if index > 0 => index -= 1,
+                    _ => break,
+                }
+                let is_stop = index == 0 || index == last || self.is_branching_at(index);
+                if is_stop && self.is_landable(index) {
+                    target = Some(index);
+                    break;
+                }
+            }
+            match target {
+                Some(index) => self.current_index = index,
+                None => break,
+            }
+        }
+    }
+
+    /// Selects a path component based on the terminal position of a mouse click.
+    ///
+    /// This function iterates through the path parts, calculating their cumulative width,
+    /// to determine which part covers the given `column`. It handles clicks before
+    /// the first part and after the last part gracefully.
+    fn select_part_at(&mut self, column: u16, row: u16) {
+        if let Some(index) = self.part_at(column, row) {
+            self.current_index = index;
+        }
+    }
+
+    /// Returns the index of the path component displayed at the given terminal
+    /// column and row. The row only matters when the path wraps (see
+    /// `part_rows`); rows below the breadcrumb count as its last one.
+    ///
+    /// Returns `None` only if there are no path components at all.
+    fn part_at(&self, column: u16, row: u16) -> Option<usize> {
+        let mut rows = self.part_rows();
+        let row = self.frame_row.map_or(0, |top| usize::from(row.saturating_sub(top)));
+        let row = row.min(rows.len() - 1);
+        let order = rows.swap_remove(row);
+        let mut current_pos = u16::from(row == 0 && self.viewport().clipped_left);
+        // Default to the first part drawn. This handles clicks before any text,
+        // including on the `…` of a clipped path.
+        let mut new_index = order.first().copied().unwrap_or(0);
+
+        for i in order {
+            // As soon as the cursor position is beyond the start of the current part,
+            // it becomes the candidate for selection.
+            if column >= current_pos {
+                new_index = i;
+            }
+            // Saturate rather than overflow on a path wider than any terminal.
+            let width = u16::try_from(self.part_width(i)).unwrap_or(u16::MAX);
+            current_pos = current_pos.saturating_add(width);
+        }
+
+        // Positions above the allowed roots clamp at their boundary.
+        self.allowed_start()
+            .map(|start| new_index.max(start).max(self.min_index))
+    }
+
+    /// Returns the column and width at which the text of part `index` is drawn,
+    /// not counting its `numbered` prefix. The column is counted from the start
+    /// of the row the part is drawn on (see `part_rows`).
+    fn part_span(&self, index: usize) -> (usize, usize) {
+        let rows = self.part_rows();
+        let row = rows.iter().position(|row| row.contains(&index)).unwrap_or(0);
+        let column: usize = usize::from(row == 0 && self.viewport().clipped_left)
+            + rows[row]
+                .iter()
+                .take_while(|&&i| i != index)
+                .map(|&i| self.part_width(i))
+                .sum::<usize>();
+        let width = self
+            .path_parts
+            .get(index)
+            .map_or(0, |part| display_width(&self.part_text(index, part)));
+        (column + display_width(&self.index_prefix(index)), width)
+    }
+
+    /// Returns the indices of the parts in the order they are drawn, which is
+    /// from the root outward, or the other way around with `reverse`.
+    ///
+    /// The parts above a `collapsed_home` are not drawn at all.
+    fn display_order(&self) -> Vec<usize> {
+        let order = self.collapsed_home().unwrap_or(0)..self.path_parts.len();
+        if self.reverse {
+            order.rev().collect()
+        } else {
+            order.collect()
+        }
+    }
+
+    /// Returns the parts drawn on the breadcrumb line, in the order they are
+    /// drawn (see `update_viewport`).
+    fn viewport(&self) -> Viewport {
+        let order = self.display_order();
+        // The path may have changed since the last frame; it is then drawn whole
+        // until the next one.
+        let range = match &self.view_range {
+            range if range.end <= order.len() && !range.is_empty() => range.clone(),
+            _ => 0..order.len(),
+        };
+        Viewport {
+            clipped_left: range.start > 0,
+            clipped_right: range.end < order.len(),
+            order: order[range].to_vec(),
+        }
+    }
+
+    /// Returns the parts drawn (see `viewport`) grouped into the rows they are
+    /// drawn on. That is a single row, except with `Layout::Wrap`, where a part
+    /// that does not fit on the rest of a row starts the next one.
+    fn part_rows(&self) -> Vec<Vec<usize>> {
+        let order = self.viewport().order;
+        if self.layout == Layout::Scroll {
+            return vec![order];
+        }
+        let mut rows = vec![Vec::new()];
+        let mut used = 0;
+        for i in order {
+            let width = self.part_width(i);
+            if used > 0 && used + width > self.view_width {
+                rows.push(Vec::new());
+                used = 0;
+            }
+            used += width;
+            rows.last_mut().expect("there is always a row").push(i);
+        }
+        rows
+    }
+
+    /// Chooses the parts that fit on a line of `width` columns for the next frame.
+    ///
+    /// A path that fits is drawn whole, as is every path with `Layout::Wrap`. Otherwise the parts drawn last time are
+    /// kept as long as they include the selection, so that moving within them
+    /// does not shift anything. Once the selection leaves them, the window starts
+    /// over at the selection. Either way it then grows a part at a time on both
+    /// sides, alternately, while it still fits next to a one-column `…` at each
+    /// end, so the selection is always drawn.
+    fn update_viewport(&mut self, width: usize) {
+        self.view_width = width;
+        let order = self.display_order();
+        let widths: Vec<usize> = order.iter().map(|&i| self.part_width(i)).collect();
+        let selected = order.iter().position(|&i| i == self.current_index);
+        let fits = self.layout == Layout::Wrap || widths.iter().sum::<usize>() <= width;
+        let Some(selected) = selected.filter(|_| !fits) else {
+            self.view_range = 0..order.len();
+            return;
+        };
+        let budget = width.saturating_sub(2);
+        let kept = &self.view_range;
+        let (mut start, mut end) = if kept.contains(&selected)
+            && kept.end <= widths.len()
+            && widths[kept.clone()].iter().sum::<usize>() <= budget
+        {
+            (kept.start, kept.end)
+        } else {
+            (selected, selected + 1)
+        };
+        let mut used: usize = widths[start..end].iter().sum();
+        loop {
+            let mut grown = false;
+            if end < widths.len() && used + widths[end] <= budget {
+                used += widths[end];
+                end += 1;
+                grown = true;
+            }
+            if start > 0 && used + widths[start - 1] <= budget {
+                start -= 1;
+                used += widths[start];
+                grown = true;
+            }
+            if !grown {
+                break;
+            }
+        }
+        self.view_range = start..end;
+    }
+
+    /// Returns the number of columns part `index` takes up, including its prefix.
+    fn part_width(&self, index: usize) -> usize {
+        let text_width = self
+            .path_parts
+            .get(index)
+            .map_or(0, |part| display_width(&self.part_text(index, part)));
+        display_width(&self.index_prefix(index)) + text_width
+    }
+
+    /// Returns the text drawn for part `index` (see `part_label`), fitted to a
+    /// cell of `cell_width` columns if one is set. With `no_color`, a selected
+    /// part is put in brackets.
+    ///
+    /// The selected part's cell grows to keep `selected_min_width` characters
+    /// before the `…`, which leaves less room for its neighbors: the viewport then
+    /// drops them first.
+    fn part_text(&self, index: usize, part: &OsStr) -> String {
+        let text = self.part_label(index, part);
+        let text = match self.cell_width {
+            Some(width) if index == self.current_index && self.selected_min_width > 0 => {
+                let limit = width.max(self.selected_min_width + 1);
+                pad_to_width(&truncate_to_width(&text, limit), width)
+            }
+            Some(width) => pad_to_width(&truncate_to_width(&text, width), width),
+            None => text,
+        };
+        if self.no_color && (index == self.current_index || self.in_range(index)) {
+            format!("[{}]", text)
+        } else {
+            text
+        }
+    }
+
+    /// Returns the label of part `index`, with an `@` before its trailing
+    /// separator if it is a symlink and `show_symlinks` is on (like `ls -F`),
+    /// followed by its number of subdirectories with `show_child_counts`.
+    ///
+    /// With `reverse`, trailing separators are dropped, since the parts are then
+    /// joined by arrows instead (see `index_prefix`).
+    fn part_label(&self, index: usize, part: &OsStr) -> String {
+        if self.pick {
+            return display_part(part);
+        }
+        let home;
+        let part = if self.collapsed_home() == Some(index) {
+            home = if ends_with_separator(part) {
+                OsString::from(format!("~{}", std::path::MAIN_SEPARATOR))
+            } else {
+                OsString::from("~")
+            };
+            &home
+        } else {
+            part
+        };
+        let name = component_name(part);
+        // Custom separators are not known to `abbreviate_part`.
+        let abbreviate = self.abbreviate
+            && self.separator.is_none()
+            && index + 1 < self.path_parts.len()
+            && index != self.current_index;
+        if self.reverse && !name.is_empty() {
+            let name = display_part(name);
+            let name = if abbreviate { abbreviate_name(&name) } else { name };
+            return self.part_text_plain(index, &name);
+        }
+        let mut text = if abbreviate { abbreviate_part(part) } else { display_part(part) };
+        let at = if ends_with_separator(part) {
+            text.len() - std::path::MAIN_SEPARATOR.len_utf8()
+        } else {
+            text.len()
+        };
+        text.insert_str(at, &self.part_markers(index));
+        text
+    }
+
+    /// Returns the breadcrumb as plain text, with the selection in brackets, e.g.
+    /// `/home/[user/]project`.
+    fn plain_breadcrumb(&self) -> String {
+        let mut line = String::new();
+        for i in self.display_order() {
+            line.push_str(&self.index_prefix(i));
+            let text = self.part_text(i, &self.path_parts[i]);
+            if i == self.current_index && !self.no_color {
+                line.push_str(&format!("[{}]", text));
+            } else {
+                line.push_str(&text);
+            }
+        }
+        line
+    }
+
+    /// Appends the markers of part `index` (see `part_markers`) to its `name`.
+    fn part_text_plain(&self, index: usize, name: &str) -> String {
+        format!("{}{}", name, self.part_markers(index))
+    }
+
+    /// Returns what is drawn after the name of part `index`: the symlink marker
+    /// and the child count badge, if it has them, e.g. `@³`.
+    fn part_markers(&self, index: usize) -> String {
+        let mut markers = String::new();
+        if self.show_symlinks && self.symlinks.contains(&index) {
+            markers.push('@');
+        }
+        if let Some(&count) = self.child_counts.get(&index) {
+            markers.push_str(&superscript(count));
+        }
+        markers
+    }
+
+    /// Counts the subdirectories of every part for `show_child_counts`. Like
+    /// `refresh_symlinks`, this only runs when the path changes or is reloaded.
+    ///
+    /// The root and prefixes get no count, which would run into the next part
+    /// since they have no name to end with a separator.
+    fn refresh_child_counts(&mut self) {
+        self.child_counts.clear();
+        if !self.show_child_counts {
+            return;
+        }
+        for index in 0..self.path_parts.len() {
+            if component_name(&self.path_parts[index]).is_empty() {
+                continue;
+            }
+            if let Some(count) = count_subdirectories(&self.path_at(index)) {
+                self.child_counts.insert(index, count);
+            }
+        }
+    }
+
+    /// Records which parts are symlinks. Each part is `lstat`-ed, so this only
+    /// runs with `show_symlinks` on, and only when the path changes.
+    fn refresh_symlinks(&mut self) {
+        self.symlinks.clear();
+        if !self.show_symlinks {
+            return;
+        }
+        for index in 0..self.path_parts.len() {
+            // Rebuilding from components drops the trailing separator, which would
+            // otherwise make `lstat` follow the link.
+            let path: PathBuf = self.path_at(index).components().collect();
+            if fs::symlink_metadata(path).is_ok_and(|meta| meta.is_symlink()) {
+                self.symlinks.insert(index);
+            }
+        }
+    }
+
+    /// Records what each part is on disk for `colorize_types`. Like
+    /// `refresh_symlinks`, each part is `lstat`-ed, so this only runs with the
+    /// setting on, and only when the path changes or is reloaded.
+    fn refresh_part_types(&mut self) {
+        self.part_types.clear();
+        if !self.colorize_types {
+            return;
+        }
+        for index in 0..self.path_parts.len() {
+            let path: PathBuf = self.path_at(index).components().collect();
+            let part_type = match fs::symlink_metadata(path) {
+                Ok(meta) if meta.is_symlink() => PartType::Symlink,
+                Ok(meta) if meta.is_dir() => PartType::Directory,
+                Ok(_) => PartType::File,
+                Err(_) => PartType::Missing,
+            };
+            self.part_types.insert(index, part_type);
+        }
+    }
+
+    /// Returns the text drawn before part `index`: its label in `numbered` mode,
+    /// and with `reverse`, the arrow joining it to the previously drawn part.
+    ///
+    /// In `numbered` mode, parts after the first are separated from the previous
+    /// one by a space.
+    fn index_prefix(&self, index: usize) -> String {
+        let first = self.display_order().first().copied();
+        let gap = match (self.reverse, self.numbered) {
+            _ if first == Some(index) => "",
+            // Items have no separators of their own to tell them apart.
+            _ if self.pick => "  ",
+            (true, _) => " < ",
+            (false, true) => " ",
+            (false, false) => "",
+        };
+        if self.numbered {
+            format!("{}{}:", gap, index)
+        } else {
+            gap.to_string()
+        }
+    }
+
+    /// Handles the mouse moving over the given column, honoring `hover_delay`.
+    ///
+    /// Without a delay the component under the mouse is selected immediately.
+    /// Otherwise it only becomes a pending hover, which `tick` selects once the
+    /// mouse has rested on the same component for the whole delay. Moving on to
+    /// another component restarts the wait, so sweeping across does nothing.
+    ///
+    /// With a `hover_style`, the component under the mouse is highlighted with
+    /// it, and without a delay it is only selected by a click.
+    fn hover_at(&mut self, column: u16, row: u16, now: Instant) {
+        if self.hover_style.is_some() {
+            self.hover_index = self.part_at(column, row);
+            if self.hover_delay.is_zero() {
+                return;
+            }
+        }
+        if self.hover_delay.is_zero() {
+            self.select_part_at(column, row);
+            return;
+        }
+        let Some(index) = self.part_at(column, row) else {
+            return;
+        };
+        match self.pending_hover {
+            Some((pending, _)) if pending == index => {}
+            _ if index == self.current_index => self.pending_hover = None,
+            _ => self.pending_hover = Some((index, now)),
+        }
+        self.tick(now);
+    }
+
+    /// Returns the next point in time at which `tick` has work to do, if any.
+    fn next_deadline(&self) -> Option<Instant> {
+        let hover = self.pending_hover.map(|(_, since)| since + self.hover_delay);
+        let preview = self.pending_preview.map(|(_, since)| since + PREVIEW_DELAY);
+        let sequence = self.pending_since.map(|since| since + SEQUENCE_TIMEOUT);
+        hover.into_iter().chain(preview).chain(sequence).min()
+    }
+
+    /// Applies any time-based state changes that are due at `now`.
+    fn tick(&mut self, now: Instant) {
+        if let Some((index, since)) = self.pending_hover
+            && now >= since + self.hover_delay
+        {
+            self.current_index = index;
+            self.pending_hover = None;
+        }
+        if let Some((index, since)) = self.pending_preview
+            && now >= since + PREVIEW_DELAY
+        {
+            self.pending_preview = None;
+            let preview = read_preview(&self.path_at(index));
+            self.previews.insert(index, preview);
+        }
+        if let Some(since) = self.pending_since
+            && now >= since + SEQUENCE_TIMEOUT
+        {
+            self.pending_since = None;
+            if let InputMode::WaitForNextKey(prompt, _) = &self.input_mode {
+                self.status_message = Some(format!("{} timed out", prompt.trim_end_matches('_')));
+                self.input_mode = InputMode::Normal;
+            }
+        }
+    }
+
+    /// Schedules a preview of the selected component if `preview_dir` or
+    /// `preview_pane` is set and it has not been previewed yet. A pending preview
+    /// of another component is dropped.
+    fn schedule_preview(&mut self, now: Instant) {
+        let enabled = self.preview_dir || self.preview_pane;
+        if !enabled || self.previews.contains_key(&self.current_index) {
+            self.pending_preview = None;
+            return;
+        }
+        if self.pending_preview.is_none_or(|(index, _)| index != self.current_index) {
+            self.pending_preview = Some((self.current_index, now));
+        }
+    }
+
+    /// Shows or hides the preview pane.
+    fn toggle_preview_pane(&mut self) {
+        self.preview_pane = !self.preview_pane;
+    }
+
+    /// Starts reading the subdirectories of the deepest component ahead of time
+    /// once it is selected, since descending from there is the likely next step.
+    fn prefetch_children(&mut self) {
+        if self.current_index + 1 != self.path_parts.len() {
+            return;
+        }
+        let path = self.selected_path();
+        if self.prefetch.as_ref().is_none_or(|prefetch| prefetch.path != path) {
+            self.prefetch = Some(Prefetch::spawn(path));
+        }
+    }
+
+    /// Returns whether `l` would go past the deepest part rather than move or
+    /// wrap around, so that it can descend instead.
+    fn at_deepest_part(&self) -> bool {
+        self.current_index + 1 == self.path_parts.len()
+            && self.wrap.keys == Boundary::Clamp
+            && !self.reverse
+            && !self.pick
+    }
+
+    /// Lists the subdirectories of the selected component and enters `Descend` mode.
+    fn open_child_list(&mut self) {
+        self.count_input.clear();
+        if self.pick {
+            self.status_message = Some("not available with --pick".to_string());
+            return;
+        }
+        let path = self.selected_path();
+        let mut entries = match &mut self.prefetch {
+            Some(prefetch) if prefetch.path == path => prefetch.entries(),
+            _ => list_subdirectories(&path),
+        };
+        if let Some(matcher) = &self.filter {
+            entries.retain(|name| matcher.is_match(name));
+        }
+        let annotations = entries.iter().map(|_| OnceCell::new()).collect();
+        self.input_mode = InputMode::Descend(ChildList {
+            parent_index: self.current_index,
+            entries,
+            annotations,
+            selected: 0,
+        });
+    }
+
+    /// Zooms in on the selected component: the components below it are dropped
+    /// and its subdirectories are listed, to keep going deeper from there rather
+    /// than confirm it.
+    fn zoom_in(&mut self) {
+        if self.pick {
+            self.status_message = Some("not available with --pick".to_string());
+            return;
+        }
+        let parts = self.path_parts[..=self.current_index].to_vec();
+        self.set_path_parts(parts, self.current_index);
+        self.open_child_list();
+    }
+
+    /// Replaces everything after `parent_index` with `child` and selects it.
+    ///
+    /// Components that were below the parent are discarded, so descending from
+    /// an ancestor effectively switches to a sibling branch of the tree.
+    fn descend_into(&mut self, parent_index: usize, child: &OsStr) {
+        let mut parts = self.path_parts.clone();
+        parts.truncate(parent_index + 1);
+        if let Some(parent) = parts.last_mut()
+            && !ends_with_separator(parent)
+        {
+            parent.push(std::path::MAIN_SEPARATOR_STR);
+        }
+        parts.push(child.to_owned());
+        self.set_path_parts(parts, usize::MAX);
+    }
+
+    /// Replaces `path_parts` and selects `index`, clamped to the new parts.
+    ///
+    /// Every change to `path_parts` goes through here so that `current_index`
+    /// can never point past the end.
+    fn set_path_parts(&mut self, path_parts: Vec<OsString>, index: usize) {
+        self.current_index = index.min(path_parts.len().saturating_sub(1));
+        self.path_parts = path_parts;
+        self.anchor = None;
+        self.narrow.clear();
+        self.index_history.clear();
+        self.redo_history.clear();
+        let len = self.path_parts.len();
+        self.marks.retain(|_, &mut index| index < len);
+        self.previews.clear();
+        self.hover_index = None;
+        self.refresh_symlinks();
+        self.refresh_part_types();
+        self.refresh_child_counts();
+        self.update_min_index();
+        self.update_focus_index();
+        self.update_home_index();
+    }
+
+    /// Recomputes `min_index` for the current path and moves the selection up to it.
+    ///
+    /// With `skip_root`, the root or prefix becomes unselectable, unless it is the
+    /// only component (as when `pd` runs in `/`).
+    fn update_min_index(&mut self) {
+        self.min_index = if self.skip_root && self.path_parts.len() > 1 {
+            self.first_normal_index()
+        } else {
+            0
+        };
+        self.current_index = self.current_index.max(self.min_index);
+    }
+
+    /// Returns the component that `text` (e.g. pasted from the clipboard) refers to.
+    ///
+    /// An absolute path matches the component that ends it, if it is the current
+    /// path or one of its ancestors. Anything else is taken as a name, matching the
+    /// deepest component with that name.
+    fn find_pasted(&self, text: &str) -> Option<usize> {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let path = Path::new(text);
+        let mut candidates = (0..self.path_parts.len()).rev();
+        if path.is_absolute() {
+            candidates.find(|&i| self.path_at(i) == path && self.is_landable(i))
+        } else {
+            let name = OsStr::new(text.trim_end_matches(std::path::is_separator));
+            candidates.find(|&i| component_name(&self.path_parts[i]) == name && self.is_landable(i))
+        }
+    }
+
+    /// Selects the component matching the clipboard contents (see `find_pasted`).
+    fn jump_to_clipboard(&mut self) {
+        self.count_input.clear();
+        match read_clipboard() {
+            Ok(text) => match self.find_pasted(&text) {
+                Some(index) => self.current_index = index,
+                None => self.status_message = Some("clipboard matches no part".to_string()),
+            },
+            Err(e) => self.status_message = Some(format!("paste failed: {}", e)),
+        }
+    }
+
+    /// Recomputes `focus_index` for the current path.
+    fn update_focus_index(&mut self) {
+        self.focus_index = self.focus_root.as_ref().and_then(|root| {
+            (0..self.path_parts.len()).find(|&i| self.path_at(i) == *root)
+        });
+    }
+
+    /// Recomputes `home_index` for the current path. The root is never taken
+    /// for the home directory, since there would be nothing to collapse.
+    fn update_home_index(&mut self) {
+        let home = env::home_dir()
+            .filter(|_| self.tilde && self.separator.is_none() && !self.pick);
+        self.home_index = home.and_then(|home| {
+            (1..self.path_parts.len()).find(|&i| self.path_at(i) == home)
+        });
+    }
+
+    /// Returns the index of the home directory while it is drawn as `~/`, which
+    /// is while neither the selection nor a visual range reaches above it.
+    /// Moving above it shows the parts it stands for again, as does `toggle_home`.
+    fn collapsed_home(&self) -> Option<usize> {
+        self.home_index.filter(|&home| {
+            !self.expand_home
+                && self.current_index >= home
+                && self.anchor.is_none_or(|anchor| anchor >= home)
+        })
+    }
+
+    /// Switches between drawing the home directory as `~/` and drawing the parts
+    /// it stands for, to glance at the real path. Only the drawing changes.
+    fn toggle_home(&mut self) {
+        if self.home_index.is_none() {
+            self.status_message = Some("no ~ to expand".to_string());
+            return;
+        }
+        self.expand_home = !self.expand_home;
+    }
+
+    /// Returns whether component `index` lies above the focus root.
+    fn is_outside_focus(&self, index: usize) -> bool {
+        self.focus_index.is_some_and(|focus| index < focus)
+    }
+
+    /// Copies the selected path, quoted for the shell, to the system clipboard.
+    fn copy_quoted_path(&mut self) {
+        self.copy_text(&shell_quote(self.selected_path().as_os_str()));
+    }
+
+    /// Copies the selected path as it is to the system clipboard.
+    fn copy_selected_path(&mut self) {
+        self.copy_text(self.selected_path().as_os_str());
+    }
+
+    /// Copies just the name of the selected component to the system clipboard.
+    fn copy_selected_name(&mut self) {
+        self.copy_text(&self.selected_name());
+    }
+
+    /// Copies `text` to the system clipboard and says whether that worked.
+    fn copy_text(&mut self, text: &OsStr) {
+        self.status_message = Some(match copy_to_clipboard(text.as_encoded_bytes()) {
+            Ok(()) => "copied".to_string(),
+            Err(e) => format!("copy failed: {}", e),
+        });
+    }
+
+    /// Shows a summary of `git status` for the repository the selected part is
+    /// in, e.g. `git: 2 modified, 1 untracked`. Nothing is shown outside of a
+    /// repository. The result is cached until the next reload.
+    fn show_git_status(&mut self) {
+        if !self.git_status {
+            self.status_message = Some("PD_GIT_STATUS is not set".to_string());
+            return;
+        }
+        let path = self.selected_path();
+        if find_git_root(&path).is_none() {
+            return;
+        }
+        let summary = match self.git_summaries.get(&path) {
+            Some(summary) => summary.clone(),
+            None => match git_status_command(&path).output() {
+                Ok(output) if output.status.success() => {
+                    Some(summarize_git_status(&String::from_utf8_lossy(&output.stdout)))
+                }
+                Ok(_) => None,
+                // Not cached, so that installing git meanwhile works.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    self.status_message = Some("git is not installed".to_string());
+                    return;
+                }
+                Err(e) => {
+                    self.status_message = Some(format!("cannot run 'git': {}", e));
+                    return;
+                }
+            },
+        };
+        self.git_summaries.insert(path, summary.clone());
+        self.status_message = summary;
+    }
+
+    /// Returns the name of the selected component with its trailing separator
+    /// stripped. The root and prefixes have no name and are returned as is.
+    fn selected_name(&self) -> OsString {
+        self.path_parts
+            .get(self.current_index)
+            .map_or_else(OsString::new, |part| part_name(part, self.separator))
+    }
+
+    /// Replaces the navigated path with `path` and selects its last component.
+    fn set_path(&mut self, path: &Path) {
+        self.set_path_parts(split_path(path, self.separator), usize::MAX);
+    }
+
+    /// Replaces the path with its canonical form, with every symlink resolved,
+    /// keeping the selection on the directory it was on where possible.
+    fn resolve_symlinks(&mut self) {
+        if self.separator.is_some() || self.pick {
+            self.status_message = Some("nothing to resolve".to_string());
+            return;
+        }
+        let deepest = self.path_at(self.path_parts.len() - 1);
+        let resolved = match fs::canonicalize(&deepest) {
+            Ok(resolved) if resolved == deepest => {
+                self.status_message = Some("no symlinks to resolve".to_string());
+                return;
+            }
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.status_message = Some(format!("cannot resolve symlinks: {}", e));
+                return;
+            }
+        };
+        let selected = fs::canonicalize(self.selected_path()).ok();
+        let parts = split_path(&resolved, None);
+        let index = (0..parts.len())
+            .find(|&i| Some(parts[..=i].iter().collect::<PathBuf>()) == selected)
+            .unwrap_or(usize::MAX);
+        self.set_path_parts(parts, index);
+    }
+
+    /// Records that a key moved the selection away from `before`, unless it only
+    /// went back or forward (see `jump_back`). A fresh move cannot be redone past.
+    fn record_history(&mut self, before: usize) {
+        if !std::mem::take(&mut self.went_back) && self.current_index != before {
+            self.index_history.push(before);
+            self.redo_history.clear();
+        }
+    }
+
+    /// Moves the selection back to where it was before the last key moved it,
+    /// undoing that move.
+    fn jump_back(&mut self) {
+        match self.index_history.pop() {
+            Some(index) => {
+                self.redo_history.push(self.current_index);
+                self.current_index = index.min(self.path_parts.len().saturating_sub(1));
+                self.went_back = true;
+            }
+            None => self.status_message = Some("no earlier selection".to_string()),
+        }
+    }
+
+    /// Redoes the last move undone by `jump_back`.
+    fn jump_forward(&mut self) {
+        match self.redo_history.pop() {
+            Some(index) => {
+                self.index_history.push(self.current_index);
+                self.current_index = index.min(self.path_parts.len().saturating_sub(1));
+                self.went_back = true;
+            }
+            None => self.status_message = Some("nothing to redo".to_string()),
+        }
+    }
+
+    /// Re-reads the filesystem state and schedules a full redraw.
+    ///
+    /// Cached directory information is discarded. If the working directory has
+    /// changed (e.g. it was renamed by another process), the path is rebuilt from
+    /// it, keeping the selection on a component with the same name if possible.
+    fn reload(&mut self) {
+        self.branching_cache.clear();
+        self.previews.clear();
+        self.git_summaries.clear();
+        self.refresh_child_counts();
+        self.refresh_part_types();
+        self.prefetch = None;
+        self.clear_screen = true;
+        if self.separator.is_some() || self.pick {
+            // The path is not the working directory, so there is nothing to rebuild.
+            return;
+        }
+
+        let Ok(cwd) = env::current_dir() else {
+            self.status_message = Some("cannot read the current directory".to_string());
+            return;
+        };
+        if cwd == self.cwd {
+            return;
+        }
+        let old_name = self.path_parts.get(self.current_index).cloned();
+        let old_index = self.current_index;
+        let parts = split_path(&cwd, None);
+        let index = (0..parts.len())
+            .filter(|&i| Some(&parts[i]) == old_name.as_ref())
+            .min_by_key(|&i| i.abs_diff(old_index))
+            .unwrap_or(old_index);
+        self.cwd = cwd;
+        self.set_path_parts(parts, index);
+    }
+
+    /// Runs a command entered in `Command` mode.
+    ///
+    /// The only supported command is `cd <path>`, which switches to another
+    /// directory. A relative `path` is resolved from the selected component.
+    fn run_command(&mut self, input: &str) {
+        let (command, argument) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+        match command {
+            "cd" if self.pick => {
+                self.status_message = Some("cd: not available with --pick".to_string());
+            }
+            "cd" if argument.trim().is_empty() => {
+                self.status_message = Some("cd: missing path".to_string());
+            }
+            "cd" => {
+                let target = normalize_lexically(&self.selected_path().join(argument.trim()));
+                if !self.is_path_allowed(&target) {
+                    self.status_message =
+                        Some(format!("cd: outside the allowed roots: {}", argument.trim()));
+                } else if target.is_dir() {
+                    self.set_path(&target);
+                } else {
+                    self.status_message = Some(format!("cd: not a directory: {}", argument.trim()));
+                }
+            }
+            "" => {}
+            other => self.status_message = Some(format!("unknown command: {}", other)),
+        }
+    }
+
+    /// Constructs a `PathBuf` from the path components up to the given index.
+    ///
+    /// An out-of-range index is clamped to the last component.
+    fn path_at(&self, index: usize) -> PathBuf {
+        if self.pick {
+            let last = self.path_parts.len().saturating_sub(1);
+            return self.path_parts.get(index.min(last)).map(PathBuf::from).unwrap_or_default();
+        }
+        let end = (index + 1).min(self.path_parts.len());
+        if let Some(separator) = self.separator {
+            // The parts already end with the separator, so they only need to be
+            // joined, leaving off the separator after the last one.
+            let joined: String = self.path_parts[..end]
+                .iter()
+                .map(|part| part.to_string_lossy())
+                .collect();
+            return PathBuf::from(joined.strip_suffix(separator).unwrap_or(&joined));
+        }
+        self.path_parts[..end].iter().collect::<PathBuf>()
+    }
+
+    /// Returns how many levels the selection is above the deepest component:
+    /// 0 for the directory itself, 1 for its parent, and so on.
+    fn depth(&self) -> usize {
+        self.path_parts.len().saturating_sub(1).saturating_sub(self.current_index)
+    }
+
+    /// Returns the selection relative to the deepest part as a chain of `..`,
+    /// e.g. `../..` two levels up, or `.` for the deepest part itself.
+    fn dotdots(&self) -> String {
+        match self.depth() {
+            0 => ".".to_string(),
+            depth => vec![".."; depth].join(std::path::MAIN_SEPARATOR_STR),
+        }
+    }
+
+    /// Confirms the current selection. Keyboard and mouse confirmation both go
+    /// through here.
+    fn confirm(&self) -> EventAction {
+        EventAction::Confirm(self.selected_path())
+    }
+
+    /// Handles the confirm key. With `drill_down`, a part above the deepest one
+    /// that has subdirectories lists them instead of being confirmed, unless
+    /// `force` is set (the confirm key was pressed with `Alt`).
+    fn confirm_or_drill(&mut self, force: bool) -> EventAction {
+        if !self.drill_down || force || self.current_index + 1 >= self.path_parts.len() {
+            return self.confirm();
+        }
+        self.open_child_list();
+        if let InputMode::Descend(list) = &self.input_mode
+            && list.entries.is_empty()
+        {
+            self.input_mode = InputMode::Normal;
+            return self.confirm();
+        }
+        EventAction::Continue
+    }
+
+    /// Starts a visual range anchored at the selection, or drops the current one.
+    fn toggle_anchor(&mut self) {
+        self.count_input.clear();
+        self.anchor = match self.anchor {
+            Some(_) => None,
+            None => Some(self.current_index),
+        };
+    }
+
+    /// Returns whether component `index` lies in the visual range, if there is one.
+    fn in_range(&self, index: usize) -> bool {
+        self.anchor.is_some_and(|anchor| {
+            let (low, high) = (anchor.min(self.current_index), anchor.max(self.current_index));
+            (low..=high).contains(&index)
+        })
+    }
+
+    /// Returns the paths a confirmed visual range prints, from the root down, or
+    /// nothing if there is no range. Components motions would skip are left out.
+    fn range_paths(&self) -> Vec<PathBuf> {
+        let Some(anchor) = self.anchor else {
+            return Vec::new();
+        };
+        let (low, high) = (anchor.min(self.current_index), anchor.max(self.current_index));
+        match self.range_output {
+            RangeOutput::All => (low..=high)
+                .filter(|&i| self.is_landable(i))
+                .map(|i| self.path_at(i))
+                .collect(),
+            RangeOutput::Ends if low == high => vec![self.path_at(low)],
+            RangeOutput::Ends => vec![self.path_at(low), self.path_at(high)],
+        }
+    }
+
+    /// Constructs a `PathBuf` from the path components up to the current index.
+    pub fn selected_path(&self) -> PathBuf {
+        self.path_at(self.current_index)
+    }
+}
+
+/// The parts of a path that are drawn, which may not be all of them if it does
+/// not fit (see `AppState::update_viewport`).
+struct Viewport {
+    /// The indices of the parts drawn, in the order they are drawn.
+    order: Vec<usize>,
+    /// Whether parts before the first one drawn were left out, shown by a `…`.
+    clipped_left: bool,
+    /// Whether parts after the last one drawn were left out, shown by a `…`.
+    clipped_right: bool,
+}
+
+/// The outcome of a confirmed interactive session.
+struct Selection {
+    /// The confirmed path.
+    path: PathBuf,
+    /// How many levels above the deepest component the selection was (see `AppState::depth`).
+    depth: usize,
+    /// The paths to print instead of `path` when a visual range was confirmed
+    /// (see `AppState::range_paths`); empty otherwise.
+    range: Vec<PathBuf>,
+    /// How the paths are to be printed.
+    format: OutputFormat,
+    /// The index of the start path whose tab the selection was made in.
+    tab: usize,
+    /// Whether the selection is still the one `pd` started with.
+    unchanged: bool,
+}
+
+/// Represents the possible actions resulting from event handling.
+///
+/// This enum defines the outcomes of processing user input, determining whether the
+/// application should continue running, confirm a selected path, or quit.
+pub enum EventAction {
+    /// Continue running the event loop.
+    Continue,
+    /// Confirm the selection of a path, carrying the selected `PathBuf`.
+    Confirm(PathBuf),
+    /// Quit the application.
+    Quit,
+    /// Hand off to the `PD_FILEMANAGER`, opened at the selected `PathBuf`.
+    Browse(PathBuf),
+}
+
+/// Options given on the command line.
+#[derive(Default)]
+struct Options {
+    /// Print the confirmed path quoted for the shell (see `shell_quote`).
+    shell_quote: bool,
+    /// Only land on components and list entries whose names match this glob.
+    filter: Option<GlobMatcher>,
+    /// Refuse to start if any component of the path is not valid UTF-8.
+    require_utf8: bool,
+    /// Resolve a relative confirmed path against `CDPATH`, like `cd` does.
+    cdpath: bool,
+    /// Also show the confirmed path on the terminal after exiting.
+    echo: bool,
+    /// Print the confirmed path relative to the enclosing git repository's root.
+    git_relative: bool,
+    /// Print the styled breadcrumb of the current directory and exit.
+    mini: bool,
+    /// Print the key bindings of the active keymap and exit (see `Keymap::key_table`).
+    dump_keys: bool,
+    /// Print the `HELP` text and exit.
+    help: bool,
+    /// Print the version and exit.
+    version: bool,
+    /// Print the path, its parts and the initial selection as JSON and exit (see `render_json`).
+    json: bool,
+    /// Write the selection, NUL-terminated, to this file descriptor whenever it changes.
+    live_fd: Option<u32>,
+    /// Write the confirmed path to this file descriptor instead of stdout.
+    output_fd: Option<u32>,
+    /// The path to navigate instead of the current directory, as given.
+    start: Option<OsString>,
+    /// Further paths to navigate, each in a tab of its own, as given.
+    tabs: Vec<OsString>,
+    /// Also print how many levels up the selection is, on stderr.
+    print_depth: bool,
+    /// Pick one of the paths read from stdin instead of navigating a path.
+    pick: bool,
+    /// Print the path and the current directory as two NUL-terminated fields.
+    oldpwd: bool,
+    /// Exit with status 3 when the confirmed selection is the initial one.
+    exit_unchanged: bool,
+    /// Describe how `pd` ended on stderr (see `summary_line`), unless `PD_QUIET`.
+    verbose: bool,
+    /// Don't write the trailing newline after the path.
+    no_newline: bool,
+    /// Terminate each printed path with a NUL instead of a newline.
+    print0: bool,
+    /// Whether to end each printed path with a separator, or leave it as it is
+    /// (see `set_trailing_separator`).
+    trailing_slash: Option<bool>,
+    /// Print selections at most this many levels up as `.`, `..`, `../..`, etc.
+    relative_within: Option<usize>,
+    /// Print the confirmed path as a `file://` URI (see `file_uri`).
+    uri: bool,
+    /// Print the directory `pd` started in on a line before the confirmed path.
+    with_origin: bool,
+    /// Print a command (`cd` or `pushd`) for the confirmed path instead (see `shell_command`).
+    emit_command: Option<&'static str>,
+    /// Print the previous or next directory of the `PD_DIRSTACK_FILE` history and exit.
+    history: Option<HistoryStep>,
+    /// Print only the name of the confirmed directory (see `part_name`).
+    name_only: bool,
+    /// Run this command, split into words, on the confirmed path instead of
+    /// printing it (see `exec_command`).
+    exec: Option<Vec<String>>,
+    /// The file that macros are loaded from and saved to (see `load_registers`).
+    save_macro: Option<PathBuf>,
+}
+
+/// A step through the `PD_DIRSTACK_FILE` history (see `step_history`).
+#[derive(Clone, Copy)]
+enum HistoryStep {
+    Back,
+    Forward,
+}
+
+/// The text printed by `--help`: how to run `pd`, every option, the main keys
+/// and the most useful environment variables. The README has the details.
+const HELP: &str = "\
+Usage: pd [options] [path...]
+       pd init <shell>
+
+Pick a directory on the path of the current directory (or of each path given,
+in tabs of their own) and print it, for a shell function to cd into.
+
+Options:
+  -h, --help                Print this help and exit
+  -V, --version             Print the version and exit
+      --filter <glob>       Only land on parts whose names match the glob
+      --require-utf8        Exit with an error if the path is not valid UTF-8
+      --shell-quote         Print the path quoted for the shell
+      --cdpath              Resolve a relative selection against CDPATH
+      --echo                Also show the path on the terminal after exiting
+      --git-relative        Print the path relative to the git repository root
+      --relative            Print the selection as ., .., ../.., etc.
+      --relative-within <n> Like --relative, for at most n levels up
+      --mini                Print the path as a styled breadcrumb and exit
+      --json                Print the path and its parts as JSON and exit
+      --dump-keys           Print the key bindings of the keymap and exit
+      --live-fd <n>         Write the selection to fd n whenever it changes
+      --output-fd <n>       Print the path to fd n instead of stdout
+      --print-depth         Also print how many levels up it is, on stderr
+      --verbose             Describe on stderr how pd ended
+      --exit-unchanged      Exit with status 3 if the selection is unchanged
+      --no-newline          Don't print a newline after the path
+  -0, --print0              End the path with a NUL instead of a newline
+      --trailing-slash      End the path with a separator (--no-trailing-slash
+                            to remove it)
+      --uri                 Print the path as a file:// URI
+      --with-origin         Print the start directory on a line before the path
+      --oldpwd              Print the path and the current directory, NUL-ended
+      --pick                Pick one of the paths read from stdin
+      --emit-cd             Print a cd command for the path
+      --emit-cmd <cmd>      Like --emit-cd, with cd or pushd as the command
+      --name-only           Print only the name of the selected directory
+      --exec <cmd>          Run cmd on the path instead of printing it
+      --save-macro <file>   Load macros from file and save them on exit
+      --back, --forward     Print the previous/next PD_DIRSTACK_FILE entry
+
+Keys (Vim keymap; see --dump-keys for the active one):
+  h, l, Left, Right         Move the selection left/right
+  ^, $, Home, End           Move to the first/last part
+  f<char>, F<char>          Jump right/left to a part containing the char
+  Down                      List the subdirectories of the selection
+  Tab                       Zoom in on the selection
+  Enter                     Confirm and print the selected path
+  q, Esc                    Quit without printing anything
+  F1                        Switch to the next keymap
+
+Environment:
+  PD_KEYMAP                 vim (the default), emacs, none or standard; beats
+                            the pde/pdv program names, which beat config.toml
+  PD_INITIAL_POSITION       end (the default), start, middle, or levels up
+  PD_KEY_CONFIRM            The key that confirms, e.g. tab or C-j
+  PD_KEY_QUIT               The key that quits, instead of q and Esc
+  NO_COLOR                  Show the selection in brackets instead of in color
+";
+
+/// Parses the command-line arguments (excluding the program name).
+///
+/// The first other argument is the start path (see `resolve_start`) and any
+/// further ones are opened in tabs. Arguments after `--` are taken as paths
+/// even if they start with `-`.
+/// Unknown options produce an error message suitable for printing to the user.
+fn parse_args<I: IntoIterator<Item = OsString>>(args: I) -> std::result::Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--") => {
+                for arg in args.by_ref() {
+                    if options.start.is_none() {
+                        options.start = Some(arg);
+                    } else {
+                        options.tabs.push(arg);
+                    }
+                }
+            }
+            // Nothing else matters, not even options that would be rejected.
+            Some("--help" | "-h") => {
+                options.help = true;
+                return Ok(options);
+            }
+            Some("--version" | "-V") => {
+                options.version = true;
+                return Ok(options);
+            }
+            Some("--shell-quote") => options.shell_quote = true,
+            Some("--require-utf8") => options.require_utf8 = true,
+            Some("--cdpath") => options.cdpath = true,
+            Some("--echo") => options.echo = true,
+            Some("--git-relative") => options.git_relative = true,
+            Some("--mini") => options.mini = true,
+            Some("--dump-keys") => options.dump_keys = true,
+            Some("--json") => options.json = true,
+            Some("--print-depth") => options.print_depth = true,
+            Some("--verbose") => options.verbose = true,
+            Some("--exit-unchanged") => options.exit_unchanged = true,
+            Some("--oldpwd") => options.oldpwd = true,
+            Some("--pick") => options.pick = true,
+            Some("--no-newline") => options.no_newline = true,
+            Some("--print0" | "-0") => options.print0 = true,
+            Some("--trailing-slash") => options.trailing_slash = Some(true),
+            Some("--no-trailing-slash") => options.trailing_slash = Some(false),
+            Some("--uri") => options.uri = true,
+            Some("--with-origin") => options.with_origin = true,
+            Some("--name-only") => options.name_only = true,
+            Some("--save-macro") => {
+                let file = args.next().ok_or("option '--save-macro' requires a file")?;
+                options.save_macro = Some(PathBuf::from(file));
+            }
+            Some(arg) if arg.starts_with("--save-macro=") => {
+                options.save_macro = Some(PathBuf::from(&arg["--save-macro=".len()..]));
+            }
+            Some("--emit-cd") => options.emit_command = Some("cd"),
+            Some("--emit-cmd") => {
+                let word = args.next().ok_or("option '--emit-cmd' requires a command")?;
+                options.emit_command = Some(parse_command_word(&word.to_string_lossy())?);
+            }
+            Some(arg) if arg.starts_with("--emit-cmd=") => {
+                options.emit_command = Some(parse_command_word(&arg["--emit-cmd=".len()..])?);
+            }
+            Some("--exec") => {
+                let template = args.next().ok_or("option '--exec' requires a command")?;
+                options.exec = Some(parse_exec_template(&template.to_string_lossy())?);
+            }
+            Some(arg) if arg.starts_with("--exec=") => {
+                options.exec = Some(parse_exec_template(&arg["--exec=".len()..])?);
+            }
+            Some("--back") => options.history = Some(HistoryStep::Back),
+            Some("--forward") => options.history = Some(HistoryStep::Forward),
+            Some("--filter") => {
+                let pattern = args.next().ok_or("option '--filter' requires a pattern")?;
+                options.filter = Some(compile_filter(&pattern.to_string_lossy())?);
+            }
+            Some(arg) if arg.starts_with("--filter=") => {
+                options.filter = Some(compile_filter(&arg["--filter=".len()..])?);
+            }
+            // However far up the selection is; other selections stay absolute.
+            Some("--relative") => options.relative_within = Some(usize::MAX),
+            Some("--relative-within") => {
+                let levels = args.next().ok_or("option '--relative-within' requires a number")?;
+                options.relative_within = Some(parse_levels(&levels.to_string_lossy())?);
+            }
+            Some(arg) if arg.starts_with("--relative-within=") => {
+                options.relative_within = Some(parse_levels(&arg["--relative-within=".len()..])?);
+            }
+            Some("--live-fd") => {
+                let fd = args.next().ok_or("option '--live-fd' requires a file descriptor")?;
+                options.live_fd = Some(parse_fd(&fd.to_string_lossy())?);
+            }
+            Some(arg) if arg.starts_with("--live-fd=") => {
+                options.live_fd = Some(parse_fd(&arg["--live-fd=".len()..])?);
+            }
+            Some("--output-fd") => {
+                let fd = args.next().ok_or("option '--output-fd' requires a file descriptor")?;
+                options.output_fd = Some(parse_fd(&fd.to_string_lossy())?);
+            }
+            Some(arg) if arg.starts_with("--output-fd=") => {
+                options.output_fd = Some(parse_fd(&arg["--output-fd=".len()..])?);
+            }
+            _ if arg.to_string_lossy().starts_with('-') => {
+                return Err(format!("unknown option '{}'", arg.to_string_lossy()));
+            }
+            _ if options.start.is_none() => options.start = Some(arg),
+            _ => options.tabs.push(arg),
+        }
+    }
+    if options.emit_command.is_some() {
+        // The command is meant to be run as is, so there is one line to output.
+        for (set, name) in [(options.uri, "--uri"), (options.with_origin, "--with-origin")] {
+            if set {
+                return Err(format!("option '--emit-cmd' cannot be combined with '{}'", name));
+            }
+        }
+    }
+    if options.exec.is_some() {
+        // The command is run instead of printing, so there is nothing to format.
+        for (set, name) in [
+            (options.shell_quote, "--shell-quote"),
+            (options.uri, "--uri"),
+            (options.emit_command.is_some(), "--emit-cmd"),
+            (options.with_origin, "--with-origin"),
+            (options.name_only, "--name-only"),
+        ] {
+            if set {
+                return Err(format!("option '--exec' cannot be combined with '{}'", name));
+            }
+        }
+    }
+    if options.name_only {
+        // A bare name is one line that is not a path, so nothing can be added to
+        // it or made of it.
+        for (set, name) in [
+            (options.uri, "--uri"),
+            (options.emit_command.is_some(), "--emit-cmd"),
+            (options.with_origin, "--with-origin"),
+            (options.cdpath, "--cdpath"),
+            (options.git_relative, "--git-relative"),
+            (options.relative_within.is_some(), "--relative-within"),
+        ] {
+            if set {
+                return Err(format!("option '--name-only' cannot be combined with '{}'", name));
+            }
+        }
+    }
+    if options.pick && (options.start.is_some() || options.mini) {
+        let conflict = if options.mini { "--mini" } else { "a start path" };
+        return Err(format!("option '--pick' cannot be combined with {}", conflict));
+    }
+    if options.oldpwd {
+        // The fields are read as they are, so they must be plain paths.
+        for (set, name) in [
+            (options.shell_quote, "--shell-quote"),
+            (options.uri, "--uri"),
+            (options.emit_command.is_some(), "--emit-cmd"),
+            (options.with_origin, "--with-origin"),
+            (options.name_only, "--name-only"),
+            (options.exec.is_some(), "--exec"),
+        ] {
+            if set {
+                return Err(format!("option '--oldpwd' cannot be combined with '{}'", name));
+            }
+        }
+    }
+    if options.no_newline {
+        // These end each field with a NUL instead, so there is no newline to leave out.
+        for (set, name) in [(options.print0, "--print0"), (options.oldpwd, "--oldpwd")] {
+            if set {
+                return Err(format!("option '--no-newline' cannot be combined with '{}'", name));
+            }
+        }
+    }
+    if options.uri {
+        // A URI needs an absolute path, which these options would take away.
+        for (set, name) in [
+            (options.shell_quote, "--shell-quote"),
+            (options.cdpath, "--cdpath"),
+            (options.git_relative, "--git-relative"),
+            (options.relative_within.is_some(), "--relative-within"),
+        ] {
+            if set {
+                return Err(format!("option '--uri' cannot be combined with '{}'", name));
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Returns the current directory or, if it has been deleted, the nearest of its
+/// ancestors that still exists, with a note saying so.
+///
+/// The path of a deleted directory is only known from `PWD`, which shells keep
+/// up to date, so without it there is nothing to fall back to.
+fn current_dir_or_ancestor() -> std::result::Result<PathBuf, String> {
+    let error = match env::current_dir() {
+        Ok(cwd) => return Ok(cwd),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => e,
+        Err(e) => return Err(format!("cannot determine the current directory: {}", e)),
+    };
+    let pwd = env::var_os("PWD")
+        .map(PathBuf::from)
+        .filter(|pwd| pwd.is_absolute())
+        .ok_or_else(|| format!("cannot determine the current directory: {}", error))?;
+    let Some(ancestor) = pwd.ancestors().skip(1).find(|dir| dir.is_dir()) else {
+        return Err(format!(
+            "the current directory {} and all its parents no longer exist",
+            pwd.display()
+        ));
+    };
+    eprintln!(
+        "Note: The current directory {} no longer exists, using {} instead",
+        pwd.display(),
+        ancestor.display()
+    );
+    Ok(ancestor.to_path_buf())
+}
+
+/// Resolves the start path argument into the absolute path to navigate.
+///
+/// A missing or empty argument (as left by an unset shell variable) means the
+/// current directory, `cwd`. A whitespace-only argument is almost certainly a mistake,
+/// so it is rejected rather than treated as a relative directory name.
+///
+/// A given path must be a directory. A leading `~` and environment variables in
+/// it are expanded first (see `expand_path`), for callers that skip the shell.
+/// `.` and `..` are resolved lexically, so a symlink in it is kept, as for `cd`.
+///
+/// With a custom `PD_SEPARATOR`, the argument is not a filesystem path and is
+/// used as is.
+fn resolve_start(
+    arg: Option<&OsStr>,
+    cwd: &Path,
+    separator: Option<char>,
+) -> std::result::Result<PathBuf, String> {
+    match arg {
+        None => Ok(cwd.to_path_buf()),
+        Some(arg) if arg.is_empty() => Ok(cwd.to_path_buf()),
+        Some(arg) if arg.to_string_lossy().trim().is_empty() => {
+            Err(format!("start path '{}' is blank", arg.to_string_lossy()))
+        }
+        Some(arg) if separator.is_some() => Ok(PathBuf::from(arg)),
+        Some(arg) => {
+            let path = normalize_lexically(&cwd.join(expand_path(arg)));
+            match fs::metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => Ok(path),
+                Ok(_) => Err(format!("start path '{}' is not a directory", arg.display())),
+                Err(e) => Err(format!("cannot open start path '{}': {}", arg.display(), e)),
+            }
+        }
+    }
+}
+
+/// Expands a leading `~` (alone or before a separator) to the home directory,
+/// and `$VAR` and `${VAR}` to the value of the environment variable, as a shell
+/// would.
+///
+/// A variable that is not set expands to nothing, with a warning. `~user`, a `$`
+/// not followed by a name, and paths that are not valid UTF-8 are left as they
+/// are.
+///
+/// # Examples
+/// - `~/src` -> `/home/me/src`
+/// - `$WORK/repo` or `${WORK}/repo` -> `/srv/work/repo`
+pub fn expand_path(input: &OsStr) -> PathBuf {
+    let Some(text) = input.to_str() else {
+        return PathBuf::from(input);
+    };
+    let mut expanded = String::new();
+    let mut rest = text;
+    if let Some(after) = rest.strip_prefix('~')
+        && (after.is_empty() || after.starts_with(std::path::is_separator))
+        && let Some(home) = env::home_dir()
+    {
+        expanded.push_str(&home.to_string_lossy());
+        rest = after;
+    }
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+        let (name, remainder) = match after.strip_prefix('{') {
+            Some(braced) => match braced.split_once('}') {
+                Some((name, remainder)) => (name, remainder),
+                None => ("", after),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        match env::var_os(name) {
+            Some(value) => expanded.push_str(&value.to_string_lossy()),
+            None => eprintln!("Warning: ${} is not set, expanding it to nothing", name),
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    PathBuf::from(expanded)
+}
+
+/// Resolves the symlinks in a start path for `PD_RESOLVE_SYMLINKS`, keeping the
+/// path as it is (with a warning) if that fails.
+fn canonicalize_start(start: PathBuf) -> PathBuf {
+    fs::canonicalize(&start).unwrap_or_else(|e| {
+        eprintln!("Warning: Cannot resolve symlinks in {}: {}", start.display(), e);
+        start
+    })
+}
+
+/// Compiles a `--filter` glob pattern.
+fn compile_filter(pattern: &str) -> std::result::Result<GlobMatcher, String> {
+    Glob::new(pattern)
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| format!("invalid filter '{}': {}", pattern, e))
+}
+
+/// Parses a `--relative-within` number of levels.
+fn parse_levels(value: &str) -> std::result::Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number of levels '{}'", value))
+}
+
+/// Splits an `--exec` command template into words.
+///
+/// Words are separated by whitespace and can be quoted as in a POSIX shell: single
+/// quotes keep everything literally, double quotes allow `\` to escape `"`, `\`,
+/// `$` and `` ` ``, and a bare `\` escapes any character. Nothing else is
+/// interpreted, and the command is run without a shell.
+fn parse_exec_template(template: &str) -> std::result::Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => word.get_or_insert_default().extend(chars.next()),
+            '\'' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in '{}'", template)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => word.extend(['\\', c]),
+                            None => return Err(format!("unterminated quote in '{}'", template)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in '{}'", template)),
+                    }
+                }
+            }
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err("option '--exec' requires a command".to_string());
+    }
+    Ok(words)
+}
+
+/// Builds the `--exec` command from its `words`, with every `{}` replaced by
+/// `path`. If no word contains `{}`, `path` is appended as the last argument.
+///
+/// The path is substituted into the words after they are split, so it is passed
+/// as (part of) a single argument whatever characters it contains.
+fn exec_command(words: &[String], path: &Path) -> std::process::Command {
+    let substitute = |word: &String| {
+        let mut arg = OsString::new();
+        for (i, piece) in word.split("{}").enumerate() {
+            if i > 0 {
+                arg.push(path);
+            }
+            arg.push(piece);
+        }
+        arg
+    };
+    let mut command = std::process::Command::new(substitute(&words[0]));
+    command.args(words[1..].iter().map(substitute));
+    if !words.iter().any(|word| word.contains("{}")) {
+        command.arg(path);
+    }
+    command
+}
+
+/// Parses an `--emit-cmd` command word.
+fn parse_command_word(value: &str) -> std::result::Result<&'static str, String> {
+    match value {
+        "cd" => Ok("cd"),
+        "pushd" => Ok("pushd"),
+        _ => Err(format!("invalid command '{}', expected cd or pushd", value)),
+    }
+}
+
+/// Parses a `--live-fd` or `--output-fd` file descriptor number.
+fn parse_fd(value: &str) -> std::result::Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid file descriptor '{}'", value))
+}
+
+fn get_quiet() -> bool {
+    env::var_os("PD_QUIET").is_some_and(|value| value == "1")
+}
+
+fn get_abbreviate() -> bool {
+    env::var_os("PD_ABBREVIATE").is_some_and(|value| value == "1")
+}
+
+fn get_digit_mode() -> DigitMode {
+    match env::var("PD_DIGITS").as_deref() {
+        Ok("count") => DigitMode::Count,
+        Ok("select") => DigitMode::Select,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_DIGITS value '{}', defaulting to count", other);
+            DigitMode::Count
+        }
+        Err(_) => DigitMode::Count,
+    }
+}
+
+/// Falls back to the `selection` key of the config file's `[style]` table.
+fn get_highlight_mode() -> HighlightMode {
+    let value = env::var("PD_HIGHLIGHT_MODE").ok().or_else(|| config().highlight_mode.clone());
+    match value.as_deref() {
+        Some("reverse") => HighlightMode::Reverse,
+        Some("dim-others") => HighlightMode::DimOthers,
+        Some("dim-descendants") => HighlightMode::DimDescendants,
+        Some(other) => {
+            eprintln!("Warning: Unknown highlight mode '{}', defaulting to reverse", other);
+            HighlightMode::Reverse
+        }
+        None => HighlightMode::Reverse,
+    }
+}
+
+/// Unicode normalization defaults to on for macOS, where its file systems
+/// commonly store names decomposed (NFD).
+fn get_normalize_unicode() -> bool {
+    match env::var("PD_NORMALIZE_UNICODE").as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_NORMALIZE_UNICODE value '{}', expected 1 or 0", other);
+            cfg!(target_os = "macos")
+        }
+        Err(_) => cfg!(target_os = "macos"),
+    }
+}
+
+/// Parses the subset of readline's inputrc syntax that maps to `pd` actions.
+///
+/// Only single-key bindings are supported, written either as a quoted key
+/// sequence (`"\C-f": forward-char`, `"\ef": forward-word`) or as a key name
+/// (`Control-f: forward-char`). Comments and blank lines are ignored; directives
+/// such as `set` or `$if`, multi-key sequences, and readline functions without a
+/// counterpart in `pd` are skipped. Each skipped line produces a warning.
+fn parse_inputrc(text: &str) -> (Vec<(KeyBinding, Action)>, Vec<String>) {
+    let mut bindings = Vec::new();
+    let mut warnings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let warn = |reason: &str| format!("inputrc line {}: {}: {}", number + 1, reason, line);
+        if line.starts_with('$') || line.starts_with("set ") {
+            warnings.push(warn("unsupported directive"));
+            continue;
+        }
+        let Some((keys, function)) = split_inputrc_binding(line) else {
+            warnings.push(warn("not a key binding"));
+            continue;
+        };
+        let Some(binding) = parse_inputrc_keys(keys) else {
+            warnings.push(warn("unsupported key sequence"));
+            continue;
+        };
+        match Action::from_readline_function(function.trim()) {
+            Some(action) => bindings.push((binding, action)),
+            None => warnings.push(warn("unsupported function")),
+        }
+    }
+    (bindings, warnings)
+}
+
+/// Splits an inputrc binding line into its key part and its function name.
+fn split_inputrc_binding(line: &str) -> Option<(&str, &str)> {
+    let Some(rest) = line.strip_prefix('"') else {
+        return line.split_once(':');
+    };
+    // Find the closing quote, skipping escaped characters.
+    let mut escaped = false;
+    for (i, c) in rest.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => {
+                let function = rest[i + 1..].trim_start().strip_prefix(':')?;
+                return Some((&line[..i + 2], function));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses the key part of an inputrc binding into a single `KeyBinding`.
+fn parse_inputrc_keys(keys: &str) -> Option<KeyBinding> {
+    let keys = keys.trim();
+    if let Some(quoted) = keys.strip_prefix('"').and_then(|keys| keys.strip_suffix('"')) {
+        return parse_inputrc_sequence(quoted);
+    }
+    // Key names such as `Control-f`, `Meta-b`, `C-a` or `M-x`.
+    let (modifiers, name) =
+        if let Some(name) = keys.strip_prefix("Control-").or_else(|| keys.strip_prefix("C-")) {
+            (KeyModifiers::CONTROL, name)
+        } else if let Some(name) = keys.strip_prefix("Meta-").or_else(|| keys.strip_prefix("M-")) {
+            (KeyModifiers::ALT, name)
+        } else {
+            (KeyModifiers::NONE, keys)
+        };
+    let code = match name.to_ascii_lowercase().as_str() {
+        "return" | "ret" | "newline" => KeyCode::Enter,
+        "space" | "spc" => KeyCode::Char(' '),
+        "tab" => KeyCode::Tab,
+        "escape" | "esc" => KeyCode::Esc,
+        "rubout" | "del" => KeyCode::Backspace,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(KeyBinding { code, modifiers })
+}
+
+/// Parses a quoted inputrc key sequence, which must describe exactly one key.
+fn parse_inputrc_sequence(sequence: &str) -> Option<KeyBinding> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = sequence;
+    // `\C-` and `\M-` may be combined, and `\e` followed by a key means Meta.
+    loop {
+        if let Some(after) = rest.strip_prefix("\\C-") {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("\\M-") {
+            modifiers |= KeyModifiers::ALT;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("\\e")
+            && !after.is_empty()
+        {
+            modifiers |= KeyModifiers::ALT;
+            rest = after;
+        } else {
+            break;
+        }
+    }
+    let code = match rest {
+        "\\e" => KeyCode::Esc,
+        "\\t" => KeyCode::Tab,
+        "\\r" | "\\n" => KeyCode::Enter,
+        "\\\\" => KeyCode::Char('\\'),
+        "\\\"" => KeyCode::Char('"'),
+        "\\'" => KeyCode::Char('\''),
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c != '\\' => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(KeyBinding { code, modifiers })
+}
+
+/// Loads the bindings from the inputrc file named by `PD_INPUTRC`, if set.
+fn get_custom_bindings() -> Vec<(KeyBinding, Action)> {
+    let Some(file) = env::var_os("PD_INPUTRC") else {
+        return Vec::new();
+    };
+    match fs::read_to_string(&file) {
+        Ok(text) => {
+            let (bindings, warnings) = parse_inputrc(&text);
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            bindings
+        }
+        Err(e) => {
+            eprintln!("Warning: Failed to read PD_INPUTRC: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn get_format_key() -> KeyBinding {
+    match env::var("PD_KEY_FORMAT") {
+        Ok(spec) => KeyBinding::parse(&spec).unwrap_or_else(|| {
+            eprintln!("Warning: Unknown PD_KEY_FORMAT value '{}', defaulting to C-t", spec);
+            KeyBinding::CTRL_T
+        }),
+        Err(_) => KeyBinding::CTRL_T,
+    }
+}
+
+fn get_confirm_key() -> KeyBinding {
+    match env::var("PD_KEY_CONFIRM") {
+        Ok(spec) => KeyBinding::parse(&spec).unwrap_or_else(|| {
+            eprintln!("Warning: Unknown PD_KEY_CONFIRM value '{}', defaulting to enter", spec);
+            KeyBinding::ENTER
+        }),
+        Err(_) => KeyBinding::ENTER,
+    }
+}
+
+/// Reads `PD_KEY_QUIT`. Without it (or with an unknown spec), `q` and `Esc` quit.
+fn get_quit_key() -> Option<KeyBinding> {
+    let spec = env::var("PD_KEY_QUIT").ok()?;
+    let key = KeyBinding::parse(&spec);
+    if key.is_none() {
+        eprintln!("Warning: Unknown PD_KEY_QUIT value '{}', defaulting to q and esc", spec);
+    }
+    key
+}
+
+/// Reads the `PD_ALLOWED_ROOTS` list, separated like `PATH` on this platform.
+///
+/// Roots are canonicalized so that they compare equal to the resolved working
+/// directory; a root that cannot be resolved is kept as given.
+fn get_allowed_roots() -> Vec<PathBuf> {
+    let Some(value) = env::var_os("PD_ALLOWED_ROOTS") else {
+        return Vec::new();
+    };
+    env::split_paths(&value)
+        .filter(|root| !root.as_os_str().is_empty())
+        .map(|root| fs::canonicalize(&root).unwrap_or(root))
+        .collect()
+}
+
+fn get_selection_style() -> Option<Style> {
+    let spec = env::var("PD_STYLE").ok().or_else(|| config().selection.clone())?;
+    let style = Style::parse(&spec);
+    (!style.is_empty()).then_some(style)
+}
+
+fn get_hover_style() -> Option<Attribute> {
+    match env::var("PD_HOVER_STYLE").as_deref() {
+        Ok("underline") => Some(Attribute::Underlined),
+        Ok("bold") => Some(Attribute::Bold),
+        Ok("italic") => Some(Attribute::Italic),
+        Ok("") | Err(_) => None,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_HOVER_STYLE value '{}', ignoring it", other);
+            None
+        }
+    }
+}
+
+fn get_resolve_symlinks() -> bool {
+    env::var_os("PD_RESOLVE_SYMLINKS").is_some_and(|value| value == "1")
+}
+
+fn get_hover_delay() -> Duration {
+    match env::var("PD_HOVER_DELAY").map(|value| value.parse::<u64>()) {
+        Ok(Ok(millis)) => Duration::from_millis(millis),
+        Ok(Err(_)) => {
+            eprintln!("Warning: Invalid PD_HOVER_DELAY value, expected milliseconds");
+            Duration::ZERO
+        }
+        Err(_) => Duration::ZERO,
+    }
+}
+
+fn get_recent_places() -> Option<RecentPlaces> {
+    match env::var("PD_RECENT").as_deref() {
+        Ok("1") if cfg!(all(unix, not(target_os = "macos"))) => Some(RecentPlaces::Xbel),
+        Ok("1") => {
+            eprintln!("Warning: PD_RECENT is not supported on this platform");
+            None
+        }
+        Ok("0") | Err(_) => None,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_RECENT value '{}', expected 1 or 0", other);
+            None
+        }
+    }
+}
+
+fn get_focus_root(start: &Path) -> Option<PathBuf> {
+    match env::var_os("PD_FOCUS_ROOT") {
+        None => None,
+        Some(value) if value == "git" => find_git_root(start).map(Path::to_path_buf),
+        Some(value) if Path::new(&value).is_absolute() => Some(PathBuf::from(value)),
+        Some(value) => {
+            eprintln!(
+                "Warning: PD_FOCUS_ROOT must be 'git' or an absolute path, got '{}'; ignoring it",
+                value.to_string_lossy()
+            );
+            None
+        }
+    }
+}
+
+fn get_range_output() -> RangeOutput {
+    match env::var("PD_RANGE_OUTPUT").as_deref() {
+        Ok("all") => RangeOutput::All,
+        Ok("ends") => RangeOutput::Ends,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_RANGE_OUTPUT value '{}', defaulting to all", other);
+            RangeOutput::All
+        }
+        Err(_) => RangeOutput::All,
+    }
+}
+
+fn get_wrap_settings() -> WrapSettings {
+    let mut settings = WrapSettings::default();
+    let Ok(value) = env::var("PD_WRAP") else {
+        return settings;
+    };
+    for source in value.split(',').map(str::trim).filter(|source| !source.is_empty()) {
+        match source {
+            "arrows" => settings.arrows = Boundary::Wrap,
+            "keys" => settings.keys = Boundary::Wrap,
+            "scroll" => settings.scroll = Boundary::Wrap,
+            "all" => {
+                settings = WrapSettings {
+                    arrows: Boundary::Wrap,
+                    keys: Boundary::Wrap,
+                    scroll: Boundary::Wrap,
+                }
+            }
+            other => eprintln!("Warning: Unknown PD_WRAP source '{}', ignoring it", other),
+        }
+    }
+    settings
+}
+
+/// Reads `PD_STEP`, the number of parts the arrow keys move by, defaulting to 1.
+fn get_step() -> isize {
+    match env::var("PD_STEP").map(|value| value.parse::<isize>()) {
+        Ok(Ok(step)) if step > 0 => step,
+        Ok(_) => {
+            eprintln!("Warning: Invalid PD_STEP value, expected a positive number");
+            1
+        }
+        Err(_) => 1,
+    }
+}
+
+/// Reads `PD_SCROLL_STEP`, defaulting to the `PD_STEP` of the arrow keys.
+fn get_scroll_step(step: isize) -> isize {
+    match env::var("PD_SCROLL_STEP").map(|value| value.parse::<isize>()) {
+        Ok(Ok(step)) if step > 0 => step,
+        Ok(_) => {
+            eprintln!("Warning: Invalid PD_SCROLL_STEP value, expected a positive number");
+            step
+        }
+        Err(_) => step,
+    }
+}
+
+fn get_tabular_width() -> Option<usize> {
+    match env::var("PD_TABULAR_WIDTH").map(|value| value.parse::<usize>()) {
+        Ok(Ok(width)) if width > 0 => Some(width),
+        Ok(_) => {
+            eprintln!("Warning: Invalid PD_TABULAR_WIDTH value, expected a positive number");
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+fn get_selected_min_width() -> usize {
+    match env::var("PD_SELECTED_MIN_WIDTH").map(|value| value.parse::<usize>()) {
+        Ok(Ok(width)) if width > 0 => width,
+        Ok(_) => {
+            eprintln!("Warning: Invalid PD_SELECTED_MIN_WIDTH value, expected a positive number");
+            0
+        }
+        Err(_) => 0,
+    }
+}
+
+fn get_separator() -> Option<char> {
+    let value = env::var("PD_SEPARATOR").ok().filter(|value| !value.is_empty())?;
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(separator), None) => Some(separator),
+        _ => {
+            eprintln!(
+                "Warning: PD_SEPARATOR must be a single character, got '{}'; ignoring it",
+                value
+            );
+            None
+        }
+    }
+}
+
+fn get_layout() -> Layout {
+    match env::var("PD_LAYOUT").as_deref() {
+        Ok("scroll") => Layout::Scroll,
+        Ok("wrap") => Layout::Wrap,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_LAYOUT value '{}', defaulting to scroll", other);
+            Layout::Scroll
+        }
+        Err(_) => Layout::Scroll,
+    }
+}
+
+fn get_status_layout() -> StatusLayout {
+    match env::var("PD_STATUS_SEPARATOR").as_deref() {
+        Ok("inline") => StatusLayout::Inline,
+        Ok("none") => StatusLayout::Line,
+        Ok("blank") => StatusLayout::Blank,
+        Ok("rule") => StatusLayout::Rule,
+        Ok(other) => {
+            eprintln!(
+                "Warning: Unknown PD_STATUS_SEPARATOR value '{}', defaulting to inline",
+                other
+            );
+            StatusLayout::Inline
+        }
+        Err(_) => StatusLayout::Inline,
+    }
+}
+
+/// Reads `PD_JUMP_CASE`, defaulting to smart case with `PD_SMARTCASE` and to
+/// case-sensitive jumps otherwise.
+fn get_jump_case(smartcase: bool) -> JumpCase {
+    let default = if smartcase { JumpCase::Smart } else { JumpCase::Sensitive };
+    match env::var("PD_JUMP_CASE").as_deref() {
+        Ok("sensitive") => JumpCase::Sensitive,
+        Ok("insensitive") => JumpCase::Insensitive,
+        Ok("smart") => JumpCase::Smart,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_JUMP_CASE value '{}', ignoring it", other);
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+fn get_no_tty_fallback() -> NoTtyFallback {
+    match env::var("PD_NO_TTY").as_deref() {
+        Ok("error") => NoTtyFallback::Error,
+        Ok("cwd") => NoTtyFallback::Cwd,
+        Ok("parent") => NoTtyFallback::Parent,
+        Ok(other) => {
+            eprintln!("Warning: Unknown PD_NO_TTY value '{}', defaulting to parent", other);
+            NoTtyFallback::Parent
+        }
+        Err(_) => NoTtyFallback::Parent,
+    }
+}
+
+/// Returns what `run` prints when stderr is not a terminal and the UI cannot be
+/// shown, as chosen by `fallback`.
+fn no_tty_selection(
+    fallback: NoTtyFallback,
+    state: &mut AppState,
+    start: &Path,
+) -> Result<Option<Selection>> {
+    match fallback {
+        NoTtyFallback::Error => Err(std::io::Error::other(
+            "pd needs a terminal on stderr to run interactively \
+             (set PD_NO_TTY=cwd to print the current directory instead)",
+        )),
+        NoTtyFallback::Cwd => Ok(Some(Selection {
+            path: start.to_path_buf(),
+            depth: 0,
+            range: Vec::new(),
+            format: OutputFormat::Absolute,
+            tab: 0,
+            unchanged: true,
+        })),
+        NoTtyFallback::Parent => {
+            state.current_index = state.current_index.saturating_sub(1).max(state.min_index);
+            let path = state.selected_path();
+            Ok(Some(Selection {
+                depth: state.depth(),
+                range: Vec::new(),
+                format: OutputFormat::Absolute,
+                tab: 0,
+                unchanged: path == state.initial_path,
+                path,
+            }))
+        }
+    }
+}
+
+fn get_single_part() -> SinglePart {
+    parse_single_part(env::var("PD_SINGLE_PART").ok().as_deref())
+}
+
+/// Parses a `PD_SINGLE_PART` value, warning about unknown ones.
+fn parse_single_part(value: Option<&str>) -> SinglePart {
+    match value {
+        Some("ui") | None => SinglePart::Interactive,
+        Some("confirm") => SinglePart::Confirm,
+        Some("quit") => SinglePart::Quit,
+        Some(other) => {
+            eprintln!("Warning: Unknown PD_SINGLE_PART value '{}', defaulting to ui", other);
+            SinglePart::Interactive
+        }
+    }
+}
+
+fn get_initial_position() -> InitialPos {
+    match env::var("PD_INITIAL_POSITION").as_deref() {
+        Ok("end") => InitialPos::End,
+        Ok("start") => InitialPos::Start,
+        Ok("middle") => InitialPos::Middle,
+        Ok(other) => match other.parse::<usize>() {
+            Ok(n) => InitialPos::Depth(n),
+            Err(_) => {
+                eprintln!(
+                    "Warning: Unknown PD_INITIAL_POSITION value '{}', defaulting to end",
+                    other
+                );
+                InitialPos::End
+            }
+        },
+        Err(_) => InitialPos::End,
+    }
+}
+
+fn get_size_annotation() -> SizeAnnotation {
+    match env::var("PD_SIZES").as_deref() {
+        Ok("count") => SizeAnnotation::EntryCount,
+        Ok("recursive") => SizeAnnotation::RecursiveSize,
+        Ok(other) => {
+            eprintln!(
+                "Warning: Unknown PD_SIZES value '{}', disabling size annotations",
+                other
+            );
+            SizeAnnotation::None
+        }
+        Err(_) => SizeAnnotation::None,
+    }
+}
+
+/// Maps the name the binary was invoked as to a keymap.
+///
+/// This allows symlinks such as `ln -s pd pde` to select a keymap without any
+/// environment variables. Only the file stem is considered, so `pde.exe` works too.
+fn keymap_from_program_name(arg0: &OsStr) -> Option<Keymap> {
+    match Path::new(arg0).file_stem()?.to_str()? {
+        "pde" => Some(Keymap::Emacs),
+        "pdv" => Some(Keymap::Vim),
+        _ => None,
+    }
+}
+
+/// Determines the active keymap.
+///
+/// `PD_KEYMAP` takes precedence over the program name (see
+/// `keymap_from_program_name`), which takes precedence over the config file,
+/// falling back to Vim.
+fn get_keymap() -> Keymap {
+    let program = env::args_os().next().and_then(|arg0| keymap_from_program_name(&arg0));
+    resolve_keymap(env::var("PD_KEYMAP").ok().as_deref(), program, || config().keymap.clone())
+}
+
+/// Picks the keymap from `PD_KEYMAP`'s value, the program name's keymap and the
+/// config file's value, in that order; see `get_keymap`. The config file is only
+/// loaded when it is needed.
+fn resolve_keymap(
+    env_value: Option<&str>,
+    program: Option<Keymap>,
+    config_value: impl FnOnce() -> Option<String>,
+) -> Keymap {
+    let value = match (env_value, program) {
+        (Some(value), _) => value.to_string(),
+        (None, Some(keymap)) => return keymap,
+        (None, None) => match config_value() {
+            Some(value) => value,
+            None => return Keymap::Vim,
+        },
+    };
+    match value.as_str() {
+        "emacs" => Keymap::Emacs,
+        "vim" => Keymap::Vim,
+        "none" => Keymap::None,
+        "standard" => Keymap::Standard,
+        other => {
+            eprintln!("Warning: Unknown keymap '{}', using the default keymap", other);
+            program.unwrap_or(Keymap::Vim)
+        }
+    }
+}
+
+/// Settings read from `config.toml`, each overridden by its environment variable.
+#[derive(Default)]
+struct Config {
+    /// The `keymap` key, taking the values of `PD_KEYMAP`.
+    keymap: Option<String>,
+    /// The `selection` key of the `[style]` table, taking the values of `PD_STYLE`.
+    selection: Option<String>,
+    /// The `highlight_mode` key of the `[style]` table, taking the values of
+    /// `PD_HIGHLIGHT_MODE`.
+    highlight_mode: Option<String>,
+}
+
+/// Returns the config file's settings, loading them on first use.
+///
+/// A missing file means no settings. A file that cannot be read or parsed is
+/// reported on stderr once and ignored as a whole.
+fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let Some(file) = config_file() else {
+            return Config::default();
+        };
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Config::default(),
+            Err(e) => {
+                eprintln!("Warning: Cannot read {}: {}, ignoring it", file.display(), e);
+                return Config::default();
+            }
+        };
+        parse_config(&text).unwrap_or_else(|message| {
+            eprintln!("Warning: {}: {}, ignoring it", file.display(), message);
+            Config::default()
+        })
+    })
+}
+
+/// Returns `$XDG_CONFIG_HOME/pd/config.toml`, or `~/.config/pd/config.toml`
+/// without it.
+fn config_file() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".config")))?;
+    Some(config_home.join("pd").join("config.toml"))
+}
+
+/// Parses the subset of TOML that the config file uses.
+///
+/// Only `key = "string"` pairs, `[table]` headers, comments and blank lines are
+/// accepted. Unknown keys are warned about and skipped so that a newer config
+/// still works with an older `pd`; anything else is an error naming the line.
+fn parse_config(text: &str) -> std::result::Result<Config, String> {
+    let mut config = Config::default();
+    let mut table = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = || format!("line {} is not valid", number + 1);
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.split_once(']').ok_or_else(error)?;
+            if !header.1.trim().is_empty() && !header.1.trim().starts_with('#') {
+                return Err(error());
+            }
+            table = header.0.trim().to_string();
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(error)?;
+        let value = parse_config_string(value.trim()).ok_or_else(error)?;
+        match (table.as_str(), key.trim()) {
+            ("", "keymap") => config.keymap = Some(value),
+            ("style", "selection") => config.selection = Some(value),
+            ("style", "highlight_mode") => config.highlight_mode = Some(value),
+            (_, key) => eprintln!("Warning: Unknown config key '{}' on line {}", key, number + 1),
+        }
+    }
+    Ok(config)
+}
+
+/// Parses a TOML basic (`"..."`) or literal (`'...'`) string, which may be
+/// followed by a comment.
+fn parse_config_string(value: &str) -> Option<String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let (literal, rest) = rest.split_once('\'')?;
+        return rest_is_comment(rest).then(|| literal.to_string());
+    }
+    let mut string = String::new();
+    let mut chars = value.strip_prefix('"')?.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return rest_is_comment(chars.as_str()).then_some(string),
+            '\\' => match chars.next()? {
+                'n' => string.push('\n'),
+                't' => string.push('\t'),
+                escaped @ ('"' | '\\') => string.push(escaped),
+                _ => return None,
+            },
+            c => string.push(c),
+        }
+    }
+    None
+}
+
+/// Returns whether `rest`, what follows a value, is empty or a comment.
+fn rest_is_comment(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+/// Splits a `Path` into string components suitable for display.
+///
+/// This function handles paths in a specific way to ensure each part includes its
+/// separator, allowing them to be easily joined back into a valid path.
+///
+/// # Examples
+/// - Unix: `/home/user/project` -> `["/", "home/", "user/", "project"]`
+/// - Windows: `C:\Users\Admin` -> `["C:\", "Users\", "Admin"]`
+/// - UNC: `\\server\share\folder` -> `["\\server\share\", "folder"]`
+/// - Verbatim: `\\?\C:\dir` -> `["\\?\C:\", "dir"]`
+/// - `..` is kept: `/a/../b` -> `["/", "a/", "../", "b"]`
+pub fn split_path(path: &Path, separator: Option<char>) -> Vec<OsString> {
+    if let Some(separator) = separator {
+        return split_on(path.as_os_str(), separator);
+    }
+    let mut parts = Vec::new();
+    let mut components = path.components().peekable();
+
+    while let Some(component) = components.next() {
+        let is_last = components.peek().is_none();
+        let part = match component {
+            // The prefix and the root make a single part, so that a share or a
+            // verbatim prefix is selected whole and joins back onto what follows.
+            Component::Prefix(prefix) => {
+                let mut p = prefix.as_os_str().to_owned();
+                let has_root = components.next_if_eq(&Component::RootDir).is_some();
+                let rooted = match prefix.kind() {
+                    // These are always absolute, even when written without the
+                    // root, as `\\server\share` often is.
+                    Prefix::UNC(..) | Prefix::VerbatimUNC(..) | Prefix::VerbatimDisk(_) => true,
+                    // `C:dir` is relative to the drive's current directory, and
+                    // `\\?\name` and `\\.\COM1` only have a root if written with one.
+                    Prefix::Disk(_) | Prefix::Verbatim(_) | Prefix::DeviceNS(_) => has_root,
+                };
+                if rooted {
+                    p.push(std::path::MAIN_SEPARATOR_STR);
+                }
+                p
+            }
+            Component::RootDir => OsString::from(std::path::MAIN_SEPARATOR_STR),
+            // `..` is kept as a part of its own, so that the selected path
+            // still spells out the path that was given.
+            Component::Normal(_) | Component::ParentDir => {
+                let mut p = component.as_os_str().to_owned();
+                if !is_last {
+                    p.push(std::path::MAIN_SEPARATOR_STR);
+                }
+                p
+            }
+            Component::CurDir => continue,
+        };
+        parts.push(part);
+    }
+
+    if parts.is_empty() {
+        parts.push(OsString::from("."));
+    }
+
+    parts
+}
+
+/// Splits `text` after each `separator`, keeping the separator at the end of
+/// each part the same way `split_path` keeps path separators.
+///
+/// Text that is not valid UTF-8 is split after lossy conversion.
+fn split_on(text: &OsStr, separator: char) -> Vec<OsString> {
+    let mut parts: Vec<OsString> = text
+        .to_string_lossy()
+        .split_inclusive(separator)
+        .map(OsString::from)
+        .collect();
+
+    if parts.is_empty() {
+        parts.push(OsString::from("."));
+    }
+
+    parts
+}
+
+/// Determines whether a directory "branches", i.e. does not have exactly one subdirectory.
+///
+/// Directories that cannot be read (e.g. permission denied) are treated as
+/// branching, so that motions stop at them rather than skipping past silently.
+fn is_branching_dir(path: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(path) else {
+        return true;
+    };
+    let subdirs = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .take(2)
+        .count();
+    subdirs != 1
+}
+
+/// Fails with `InvalidData` if any path component is not valid UTF-8.
+///
+/// This is used by `--require-utf8` for consumers that cannot handle arbitrary
+/// bytes; by default such components are displayed lossily and output verbatim.
+fn ensure_utf8(parts: &[OsString]) -> Result<()> {
+    match parts.iter().find(|part| part.to_str().is_none()) {
+        Some(part) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "path component '{}' is not valid UTF-8",
+                part.to_string_lossy()
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Resolves a path against `CDPATH` the way POSIX `cd` does.
+///
+/// Absolute paths and paths starting with `.` or `..` are returned unchanged.
+/// Otherwise each `CDPATH` entry is tried in order (an empty entry meaning the
+/// current directory), and the first one containing `path` as a directory wins.
+/// If none does, `path` is returned unchanged.
+fn resolve_cdpath(path: &Path, cdpath: Option<&OsStr>) -> PathBuf {
+    let is_explicit = matches!(
+        path.components().next(),
+        Some(Component::CurDir | Component::ParentDir)
+    );
+    let Some(cdpath) = cdpath else {
+        return path.to_path_buf();
+    };
+    if path.is_absolute() || path.has_root() || is_explicit {
+        return path.to_path_buf();
+    }
+
+    env::split_paths(cdpath)
+        .map(|base| {
+            if base.as_os_str().is_empty() {
+                PathBuf::from(".").join(path)
+            } else {
+                base.join(path)
+            }
+        })
+        .find(|candidate| candidate.is_dir())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Finds the root of the git repository containing `dir`, if any.
+///
+/// This walks up from `dir` looking for a `.git` entry, which may be a directory
+/// or, for worktrees and submodules, a file.
+fn find_git_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors().find(|ancestor| ancestor.join(".git").exists())
+}
+
+/// Expresses `path` relative to the git repository root enclosing `start`.
+///
+/// The root itself becomes `.`. If `start` is not inside a repository, or `path`
+/// is above the repository root, `path` is returned unchanged with a warning.
+fn git_relative_path(path: PathBuf, start: &Path) -> PathBuf {
+    let Some(root) = find_git_root(start) else {
+        eprintln!("Warning: Not inside a git repository, printing the absolute path");
+        return path;
+    };
+    match path.strip_prefix(root) {
+        Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => {
+            eprintln!("Warning: Selection is above the git repository root, printing the absolute path");
+            path
+        }
+    }
+}
+
+/// Returns the command that lists the status of the repository at `dir`, as
+/// read by `summarize_git_status`.
+fn git_status_command(dir: &Path) -> std::process::Command {
+    let mut command = std::process::Command::new("git");
+    command
+        .arg("-C")
+        .arg(dir)
+        .args(["status", "--porcelain"])
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    command
+}
+
+/// Summarizes the output of `git status --porcelain`, e.g. `git: 1 staged,
+/// 2 modified, 1 untracked`, or `git: clean` if there are no entries.
+///
+/// An entry counts as staged if its index column (the first) shows a change,
+/// and as modified if its worktree column (the second) does, so a file can
+/// count as both.
+fn summarize_git_status(porcelain: &str) -> String {
+    let (mut staged, mut modified, mut untracked) = (0, 0, 0);
+    for line in porcelain.lines() {
+        let mut columns = line.chars();
+        match (columns.next(), columns.next()) {
+            (Some('?'), Some('?')) => untracked += 1,
+            (Some(index), Some(worktree)) => {
+                staged += usize::from(index != ' ');
+                modified += usize::from(worktree != ' ');
+            }
+            _ => {}
+        }
+    }
+    let counts: Vec<String> = [(staged, "staged"), (modified, "modified"), (untracked, "untracked")]
+        .into_iter()
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, name)| format!("{} {}", count, name))
+        .collect();
+    if counts.is_empty() {
+        "git: clean".to_string()
+    } else {
+        format!("git: {}", counts.join(", "))
+    }
+}
+
+/// Expresses the absolute `path` relative to the absolute directory `base`,
+/// going up with `..` as far as needed, e.g. `../../src` or `.` for `base` itself.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let ups = base.components().count() - common;
+    let relative: PathBuf = std::iter::repeat_n(Component::ParentDir, ups)
+        .chain(path.components().skip(common))
+        .collect();
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+/// Expresses `path` as a chain of `..` relative to `start` if it is an ancestor
+/// of `start` (or `start` itself) at most `max_levels` up.
+fn dotdot_relative_path(path: &Path, start: &Path, max_levels: usize) -> Option<PathBuf> {
+    if !start.starts_with(path) {
+        return None;
+    }
+    let levels = start.components().count() - path.components().count();
+    match levels {
+        0 => Some(PathBuf::from(".")),
+        _ if levels <= max_levels => Some((0..levels).map(|_| "..").collect()),
+        _ => None,
+    }
+}
+
+/// Applies the path-shaping options to a confirmed path.
+///
+/// A selection close enough for `--relative-within` is printed as a chain of `..`,
+/// and left alone by the other options.
+fn output_path(path: PathBuf, start: &Path, options: &Options) -> PathBuf {
+    if let Some(max_levels) = options.relative_within
+        && let Some(relative) = dotdot_relative_path(&path, start, max_levels)
+    {
+        return relative;
+    }
+    let path = if options.git_relative {
+        git_relative_path(path, start)
+    } else {
+        path
+    };
+    if options.cdpath {
+        resolve_cdpath(&path, env::var_os("CDPATH").as_deref())
+    } else {
+        path
+    }
+}
+
+/// Adds a separator to the end of `path` if `trailing` is set, or removes it
+/// otherwise, for `--trailing-slash` and `--no-trailing-slash`.
+///
+/// A root directory always keeps its separator, so it is never doubled or lost.
+fn set_trailing_separator(path: PathBuf, trailing: bool) -> PathBuf {
+    let separator = std::path::MAIN_SEPARATOR_STR;
+    let ends_with_separator = path.as_os_str().as_encoded_bytes().ends_with(separator.as_bytes());
+    if trailing == ends_with_separator || path.parent().is_none() {
+        return path;
+    }
+    if !trailing {
+        // Rebuilding from components drops the trailing separator.
+        return path.components().collect();
+    }
+    let mut text = path.into_os_string();
+    text.push(separator);
+    PathBuf::from(text)
+}
+
+/// The maximum number of entries kept in the `PD_DIRSTACK_FILE`.
+const DIRSTACK_MAX_ENTRIES: usize = 100;
+
+/// Appends `path` to the directory stack file, keeping at most `DIRSTACK_MAX_ENTRIES`.
+///
+/// The file holds one path per line, newest last. It is rewritten atomically by
+/// writing a temporary file next to it and renaming it into place, so a reader
+/// never observes a partially written stack. Paths containing a newline cannot
+/// be represented in this format and are rejected.
+fn push_dirstack(file: &Path, path: &Path) -> Result<()> {
+    let entry = path.as_os_str().as_encoded_bytes();
+    if entry.contains(&b'\n') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "path contains a newline",
+        ));
+    }
+
+    let existing = match fs::read(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let mut entries: Vec<&[u8]> = existing
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .collect();
+    entries.push(entry);
+    let skip = entries.len().saturating_sub(DIRSTACK_MAX_ENTRIES);
+
+    let mut contents = Vec::new();
+    for line in &entries[skip..] {
+        contents.extend_from_slice(line);
+        contents.push(b'\n');
+    }
+    write_atomically(file, &contents)?;
+    // A new entry starts the history over from the newest one.
+    match fs::remove_file(history_cursor_file(file)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the file next to the directory stack `file` that holds the position
+/// of `--back` and `--forward` in it.
+fn history_cursor_file(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".pos");
+    PathBuf::from(name)
+}
+
+/// Moves one step back or forward through the directory stack `file`, like a
+/// browser's history, and returns the directory stepped to.
+///
+/// The position is kept in `history_cursor_file` between invocations; without
+/// one, it is the newest entry, which is usually the current directory.
+/// Directories that no longer exist are skipped. Returns `None` if there is no
+/// directory further in that direction.
+fn step_history(file: &Path, step: HistoryStep) -> Result<Option<PathBuf>> {
+    let contents = match fs::read(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let entries: Vec<&[u8]> = contents
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .collect();
+    let Some(newest) = entries.len().checked_sub(1) else {
+        return Ok(None);
+    };
+
+    let cursor_file = history_cursor_file(file);
+    let cursor = fs::read_to_string(&cursor_file)
+        .ok()
+        .and_then(|text| text.trim().parse::<usize>().ok())
+        .map_or(newest, |cursor| cursor.min(newest));
+    let mut candidates: Box<dyn Iterator<Item = usize>> = match step {
+        HistoryStep::Back => Box::new((0..cursor).rev()),
+        HistoryStep::Forward => Box::new(cursor + 1..=newest),
+    };
+    let Some(index) = candidates.find(|&i| Path::new(&bytes_to_os_string(entries[i])).is_dir())
+    else {
+        return Ok(None);
+    };
+    write_atomically(&cursor_file, format!("{}\n", index).as_bytes())?;
+    Ok(Some(PathBuf::from(bytes_to_os_string(entries[index]))))
+}
+
+/// Replaces the contents of `file` by writing a temporary file next to it and
+/// renaming it into place, so a reader never observes a partially written file.
+fn write_atomically(file: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp_name = file.as_os_str().to_owned();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = PathBuf::from(temp_name);
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, file).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+/// A desktop's list of recently used places that confirmed directories can be
+/// added to, so they show up in the file manager's quick access.
+#[derive(Clone, Copy)]
+enum RecentPlaces {
+    /// The freedesktop.org `recently-used.xbel` file used by GNOME (GTK) and
+    /// other desktops built on it.
+    Xbel,
+}
+
+impl RecentPlaces {
+    /// Adds `path`, which must be absolute, to the recent places.
+    fn add(self, path: &Path) -> Result<()> {
+        match self {
+            RecentPlaces::Xbel => {
+                let data_home = env::var_os("XDG_DATA_HOME")
+                    .filter(|dir| !dir.is_empty())
+                    .map(PathBuf::from)
+                    .or_else(|| env::home_dir().map(|home| home.join(".local/share")))
+                    .ok_or_else(|| std::io::Error::other("cannot find the home directory"))?;
+                add_recent_xbel(&data_home.join("recently-used.xbel"), path)
+            }
+        }
+    }
+}
+
+/// Adds `path` to a `recently-used.xbel` file as a directory visited now.
+///
+/// The file is shared with every other application that uses it, so it is only
+/// edited textually, and as little as possible. An existing entry for `path`
+/// keeps everything other applications recorded in it: only its `modified` and
+/// `visited` times are updated, and `pd`'s own application record is updated or
+/// added. Otherwise a new entry is inserted before the closing `</xbel>` tag,
+/// and a file without one is left alone.
+fn add_recent_xbel(file: &Path, path: &Path) -> Result<()> {
+    let mut text = match fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if text.trim().is_empty() {
+        text = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<xbel version=\"1.0\"\n",
+            "      xmlns:bookmark=\"http://www.freedesktop.org/standards/desktop-bookmarks\"\n",
+            "      xmlns:mime=\"http://www.freedesktop.org/standards/shared-mime-info\"\n",
+            ">\n",
+            "</xbel>\n"
+        )
+        .to_string();
+    }
+
+    // `file_uri` percent-encodes everything XML would need escaped.
+    let href = file_uri(path);
+    let now = utc_timestamp(SystemTime::now());
+    let opening = format!("<bookmark href=\"{}\"", href);
+    if let Some(start) = text.find(&opening)
+        && let Some(length) = text[start..].find("</bookmark>")
+    {
+        let end = start + length;
+        let bookmark = update_recent_bookmark(&text[start..end], &now);
+        text.replace_range(start..end, &bookmark);
+        return write_atomically(file, text.as_bytes());
+    }
+
+    let Some(closing) = text.rfind("</xbel>") else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not a recognizable xbel file",
+        ));
+    };
+    let bookmark = format!(
+        concat!(
+            "  <bookmark href=\"{href}\" added=\"{now}\" modified=\"{now}\" visited=\"{now}\">\n",
+            "    <info>\n",
+            "      <metadata owner=\"http://freedesktop.org\">\n",
+            "        <mime:mime-type type=\"inode/directory\"/>\n",
+            "        <bookmark:applications>\n",
+            "          {application}\n",
+            "        </bookmark:applications>\n",
+            "      </metadata>\n",
+            "    </info>\n",
+            "  </bookmark>\n"
+        ),
+        href = href,
+        now = now,
+        application = recent_application(&now, 1)
+    );
+    text.insert_str(closing, &bookmark);
+    write_atomically(file, text.as_bytes())
+}
+
+/// Returns `pd`'s `<bookmark:application>` record for a `recently-used.xbel`
+/// entry that `pd` has registered `count` times, last at `now`.
+fn recent_application(now: &str, count: u64) -> String {
+    format!(
+        "<bookmark:application name=\"pd\" exec=\"&apos;pd&apos;\" modified=\"{}\" count=\"{}\"/>",
+        now, count
+    )
+}
+
+/// Updates an existing `recently-used.xbel` entry, given from its opening tag up
+/// to its closing `</bookmark>` tag, for a visit by `pd` at `now` (see
+/// `add_recent_xbel`). An entry without a `<bookmark:applications>` list only
+/// gets its times updated.
+fn update_recent_bookmark(bookmark: &str, now: &str) -> String {
+    let tag_end = bookmark.find('>').map_or(bookmark.len(), |i| i + 1);
+    let mut tag = bookmark[..tag_end].to_string();
+    for name in ["modified", "visited"] {
+        tag = set_xml_attribute(&tag, name, now);
+    }
+    let mut body = bookmark[tag_end..].to_string();
+    if let Some(start) = body.find("<bookmark:application name=\"pd\"") {
+        let end = body[start..].find('>').map_or(body.len(), |i| start + i + 1);
+        let count = xml_attribute(&body[start..end], "count")
+            .and_then(|count| count.parse::<u64>().ok())
+            .unwrap_or(0);
+        body.replace_range(start..end, &recent_application(now, count + 1));
+    } else if let Some(closing) = body.find("</bookmark:applications>") {
+        // Indent the record one level deeper than the closing tag, if it has a line of its own.
+        let line_start = body[..closing].rfind('\n').map_or(0, |i| i + 1);
+        let indent = &body[line_start..closing];
+        let record = if indent.trim().is_empty() {
+            format!("  {}\n{}", recent_application(now, 1), indent)
+        } else {
+            recent_application(now, 1)
+        };
+        body.insert_str(closing, &record);
+    }
+    tag + &body
+}
+
+/// Returns the value of attribute `name` in the XML start tag `tag`.
+fn xml_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!(" {}=\"", name);
+    let value = tag.find(&key)? + key.len();
+    let length = tag[value..].find('"')?;
+    Some(&tag[value..value + length])
+}
+
+/// Returns the XML start tag `tag` with attribute `name` set to `value`,
+/// replacing its value or adding the attribute at the end.
+fn set_xml_attribute(tag: &str, name: &str, value: &str) -> String {
+    let key = format!(" {}=\"", name);
+    if let Some(start) = tag.find(&key).map(|i| i + key.len())
+        && let Some(length) = tag[start..].find('"')
+    {
+        return format!("{}{}{}", &tag[..start], value, &tag[start + length..]);
+    }
+    let end = tag.strip_suffix("/>").or_else(|| tag.strip_suffix('>')).unwrap_or(tag);
+    format!("{} {}=\"{}\"{}", end.trim_end(), name, value, &tag[end.len()..])
+}
+
+/// Formats `time` as an ISO 8601 UTC timestamp, e.g. `2024-01-31T12:00:00Z`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, rest) = (seconds / 86_400, seconds % 86_400);
+    // Convert days since 1970-01-01 to a civil date (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// [Linux-only] Reads the mount points of the current process's mount namespace.
+///
+/// Any failure to read `/proc/self/mountinfo` results in an empty set.
+#[cfg(target_os = "linux")]
+fn read_mount_points() -> HashSet<PathBuf> {
+    use std::os::unix::ffi::OsStringExt as _;
+
+    let Ok(contents) = fs::read("/proc/self/mountinfo") else {
+        return HashSet::new();
+    };
+    contents
+        .split(|&b| b == b'\n')
+        // The mount point is the fifth space-separated field.
+        .filter_map(|line| line.split(|&b| b == b' ').nth(4))
+        .map(|field| PathBuf::from(OsString::from_vec(unescape_mountinfo(field))))
+        .collect()
+}
+
+/// Mount points are unknown on platforms other than Linux.
+#[cfg(not(target_os = "linux"))]
+fn read_mount_points() -> HashSet<PathBuf> {
+    HashSet::new()
+}
+
+/// [Linux-only] Decodes the octal escapes (e.g. `\040` for a space) used in `mountinfo`.
+#[cfg(target_os = "linux")]
+fn unescape_mountinfo(field: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(field.len());
+    let mut i = 0;
+    while i < field.len() {
+        let escape = field.get(i + 1..i + 4).filter(|digits| {
+            field[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d))
+        });
+        match escape {
+            Some(digits) => {
+                decoded.push(digits.iter().fold(0u8, |n, d| n.wrapping_mul(8) + (d - b'0')));
+                i += 4;
+            }
+            None => {
+                decoded.push(field[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Returns the names of all subdirectories of `path`, sorted by name.
+///
+/// Entries that cannot be inspected are skipped, and an unreadable directory
+/// simply yields an empty list rather than an error.
+fn list_subdirectories(path: &Path) -> Vec<OsString> {
+    let Ok(entries) = fs::read_dir(path) else {
+        return Vec::new();
+    };
+    let mut names: Vec<OsString> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Returns the number of subdirectories of `path`, or `None` if it cannot be
+/// read (e.g. for lack of permission).
+fn count_subdirectories(path: &Path) -> Option<usize> {
+    let entries = fs::read_dir(path).ok()?;
+    Some(entries.filter_map(|entry| entry.ok()).filter(|entry| entry.path().is_dir()).count())
+}
+
+/// Writes `n` in superscript digits, e.g. `12` as `¹²`.
+fn superscript(n: usize) -> String {
+    const DIGITS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+    n.to_string().chars().map(|digit| DIGITS[digit as usize - '0' as usize]).collect()
+}
+
+/// The contents of a previewed directory (see `read_preview`).
+enum DirPreview {
+    /// The names of the entries, sorted, with a separator after directories.
+    Entries(Vec<String>),
+    /// A placeholder such as `(permission denied)` for a directory that cannot
+    /// be read.
+    Unreadable(&'static str),
+}
+
+/// Reads the entries of `path` for a preview, like `ls` would list them.
+fn read_preview(path: &Path) -> DirPreview {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            return DirPreview::Unreadable("(permission denied)");
+        }
+        Err(_) => return DirPreview::Unreadable("(cannot read directory)"),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir() {
+                name.push(std::path::MAIN_SEPARATOR);
+            }
+            name
+        })
+        .collect();
+    names.sort();
+    DirPreview::Entries(names)
+}
+
+/// Returns the first few entries of a preview as a single line for `PD_PREVIEW_DIR`.
+fn preview_line(preview: &DirPreview) -> String {
+    let names = match preview {
+        DirPreview::Entries(names) if names.is_empty() => return "(empty)".to_string(),
+        DirPreview::Entries(names) => names,
+        DirPreview::Unreadable(placeholder) => return placeholder.to_string(),
+    };
+    let more = names.len().saturating_sub(PREVIEW_ENTRIES);
+    let mut line = names[..names.len() - more].join("  ");
+    if more > 0 {
+        line.push_str(&format!("  (+{} more)", more));
+    }
+    line
+}
+
+/// Returns the directories that complete the last component of `partial`.
+///
+/// `partial` is a path as typed by the user and is interpreted relative to `base`
+/// unless it is absolute. Each returned string is `partial` with its final
+/// component completed and a trailing separator appended. Hidden directories are
+/// only offered when the typed component itself starts with a `.`.
+fn complete_path(base: &Path, partial: &str) -> Vec<String> {
+    let (dir_part, prefix) = match partial.rfind(std::path::is_separator) {
+        Some(i) => partial.split_at(i + 1),
+        None => ("", partial),
+    };
+    list_subdirectories(&base.join(dir_part))
+        .into_iter()
+        .filter_map(|name| name.into_string().ok())
+        .filter(|name| name.starts_with(prefix) && (!name.starts_with('.') || prefix.starts_with('.')))
+        .map(|name| format!("{}{}{}", dir_part, name, std::path::MAIN_SEPARATOR))
+        .collect()
+}
+
+/// Lexically resolves `.` and `..` components without touching the filesystem.
+///
+/// Unlike `fs::canonicalize`, symlinks are preserved, matching how a shell's
+/// `cd` treats the logical path. A `..` at the root is dropped.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(normalized.components().next_back(), Some(Component::Normal(_))) {
+                    normalized.pop();
+                } else if !normalized.has_root() {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Computes the annotation displayed next to a directory in a `ChildList`.
+///
+/// `EntryCount` is a shallow `read_dir` count, while `RecursiveSize` walks the
+/// whole subtree without following symlinks. Unreadable directories yield `?`.
+fn annotate_dir(path: &Path, kind: SizeAnnotation) -> String {
+    match kind {
+        SizeAnnotation::None => String::new(),
+        SizeAnnotation::EntryCount => match fs::read_dir(path) {
+            Ok(entries) => entries.count().to_string(),
+            Err(_) => "?".to_string(),
+        },
+        SizeAnnotation::RecursiveSize => match dir_size(path) {
+            Ok(size) => format_size(size),
+            Err(_) => "?".to_string(),
+        },
+    }
+}
+
+/// Sums the sizes of all files below `path`, ignoring unreadable subdirectories.
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(path)?.filter_map(|entry| entry.ok()) {
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            total += dir_size(&entry.path()).unwrap_or(0);
+        } else {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Formats a byte count in a short human-readable form, like `ls -h`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
+/// The status message shown when a motion finds no components matching the filter.
+const NO_LANDABLE_MESSAGE: &str = "no components match the filter";
+
+/// Returns the name of a path component without its trailing separator.
+///
+/// Components without a name, such as the root (`/` on Unix), yield an empty string.
+fn component_name(part: &OsStr) -> &OsStr {
+    Path::new(part).file_name().unwrap_or_default()
+}
+
+/// Returns the name of a path component with its trailing separator (or the
+/// custom `separator`) stripped. The root and prefixes have no name and are
+/// returned as is.
+fn part_name(part: &OsStr, separator: Option<char>) -> OsString {
+    if let Some(separator) = separator {
+        let part = part.to_string_lossy();
+        return OsString::from(part.strip_suffix(separator).unwrap_or(&part));
+    }
+    match component_name(part) {
+        name if name.is_empty() => part.to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+/// Shortens a path component for display to the first character of its name,
+/// keeping its trailing separator, so that `/usr/local/bin/` becomes `/u/l/b/`
+/// as in fish's prompt. Hidden directories keep their dot as well (`.config/`
+/// becomes `.c/`), and the root and prefixes are left as they are.
+fn abbreviate_part(part: &OsStr) -> String {
+    let name = component_name(part);
+    if name.is_empty() {
+        return display_part(part);
+    }
+    let mut text = abbreviate_name(&display_part(name));
+    if ends_with_separator(part) {
+        text.push(std::path::MAIN_SEPARATOR);
+    }
+    text
+}
+
+/// Returns the first character of a displayed `name`, or the first two if it
+/// starts with a dot.
+fn abbreviate_name(name: &str) -> String {
+    let length = if name.starts_with('.') { 2 } else { 1 };
+    name.chars().take(length).collect()
+}
+
+/// Returns true if the path component ends with a path separator.
+fn ends_with_separator(part: &OsStr) -> bool {
+    part.to_string_lossy()
+        .chars()
+        .last()
+        .is_some_and(std::path::is_separator)
+}
+
+/// Converts an absolute path to a `file://` URI.
+///
+/// Every byte other than ASCII letters, digits and `-._~` is percent-encoded,
+/// so names that are not valid UTF-8 survive as their raw bytes. Windows drive
+/// paths become `file:///C:/...`, and UNC paths put the server in the authority
+/// (`file://server/share/...`).
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    let mut needs_separator = false;
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+                    uri.push('/');
+                    uri.push(char::from(letter));
+                    uri.push(':');
+                }
+                Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+                    push_percent_encoded(&mut uri, server);
+                    uri.push('/');
+                    push_percent_encoded(&mut uri, share);
+                }
+                _ => push_percent_encoded(&mut uri, prefix.as_os_str()),
+            },
+            Component::RootDir => {
+                uri.push('/');
+                needs_separator = false;
+            }
+            component => {
+                if needs_separator {
+                    uri.push('/');
+                }
+                push_percent_encoded(&mut uri, component.as_os_str());
+                needs_separator = true;
+            }
+        }
+    }
+    uri
+}
+
+/// Appends `text` to `uri`, percent-encoding everything but unreserved characters.
+fn push_percent_encoded(uri: &mut String, text: &OsStr) {
+    for &byte in text.as_encoded_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+}
+
+/// Returns a ready-to-run `word` command (`cd` or `pushd`) for `path`, quoted
+/// for POSIX shells.
+///
+/// A path starting with `-` is preceded by `--` so that the command does not
+/// take it for an option.
+fn shell_command(word: &str, path: &Path) -> OsString {
+    let quoted = shell_quote_path(path);
+    let mut command = OsString::from(word);
+    command.push(" ");
+    if quoted.as_encoded_bytes().starts_with(b"-") {
+        command.push("-- ");
+    }
+    command.push(quoted);
+    command
+}
+
+/// Quotes a string for POSIX shells, if necessary.
+///
+/// Strings consisting only of characters that are never special to the shell are
+/// returned unchanged. Anything else is wrapped in single quotes, with embedded
+/// single quotes written as `'\''`. Arbitrary bytes are preserved.
+#[cfg(unix)]
+fn quote_posix(bytes: &[u8]) -> Vec<u8> {
+    let is_safe = |b: &u8| b.is_ascii_alphanumeric() || b"/._-+,:@%=".contains(b);
+    if !bytes.is_empty() && bytes.iter().all(is_safe) {
+        return bytes.to_vec();
+    }
+
+    let mut quoted = vec![b'\''];
+    for &b in bytes {
+        if b == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// Quotes a string for PowerShell.
+///
+/// The string is wrapped in single quotes, which disable all expansion in
+/// PowerShell; embedded single quotes are doubled.
+#[cfg(not(unix))]
+fn quote_powershell(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+/// Quotes a path for the platform's usual shell (POSIX on Unix, PowerShell elsewhere).
+fn shell_quote(path: &OsStr) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt as _;
+        OsString::from_vec(quote_posix(path.as_encoded_bytes()))
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(quote_powershell(&path.to_string_lossy()))
+    }
+}
+
+/// Quotes `path` like `shell_quote`, but leaves a leading `~` component unquoted
+/// so that the shell still expands it to the home directory.
+fn shell_quote_path(path: &Path) -> OsString {
+    let mut components = path.components();
+    if components.next() != Some(Component::Normal(OsStr::new("~"))) {
+        return shell_quote(path.as_os_str());
+    }
+    let mut quoted = OsString::from("~");
+    let rest = components.as_path();
+    if !rest.as_os_str().is_empty() {
+        quoted.push(std::path::MAIN_SEPARATOR_STR);
+        quoted.push(shell_quote(rest.as_os_str()));
+    }
+    quoted
+}
+
+/// Converts bytes read back from a file written with `as_encoded_bytes` into an
+/// `OsString`: exactly on Unix, lossily elsewhere.
+fn bytes_to_os_string(bytes: &[u8]) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt as _;
+        OsString::from_vec(bytes.to_vec())
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Encodes bytes as standard base64 with padding.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Copies bytes to the system clipboard.
+///
+/// With the `clipboard` feature, the clipboard is set directly. Without it, or
+/// when that fails (e.g. in a headless session or over SSH), the terminal is asked
+/// to with the OSC 52 escape sequence, which is supported by most modern terminal
+/// emulators (and tmux with `set-clipboard on`). Terminals without support silently
+/// ignore it. When the UI is not on a terminal either, a warning is printed instead.
+fn copy_to_clipboard(bytes: &[u8]) -> Result<()> {
+    #[cfg(feature = "clipboard")]
+    if copy_to_system_clipboard(bytes) {
+        return Ok(());
+    }
+    if TERM_DEVICE.get().is_none() && !stderr().is_terminal() {
+        eprintln!("Warning: No clipboard is available, nothing was copied");
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "no clipboard is available",
+        ));
+    }
+    let mut out = term_out();
+    write!(out, "\x1b]52;c;{}\x07", base64_encode(bytes))?;
+    out.flush()
+}
+
+/// Sets the system clipboard to `bytes`, returning whether it worked. Bytes that are
+/// not UTF-8 are left to OSC 52, which copies them exactly.
+///
+/// The clipboard is kept open for the rest of the session, since on X11 and Wayland
+/// the process that set the contents is the one that hands them out.
+#[cfg(feature = "clipboard")]
+fn copy_to_system_clipboard(bytes: &[u8]) -> bool {
+    use std::sync::Mutex;
+
+    static CLIPBOARD: OnceLock<Option<Mutex<arboard::Clipboard>>> = OnceLock::new();
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return false;
+    };
+    let clipboard = CLIPBOARD.get_or_init(|| arboard::Clipboard::new().ok().map(Mutex::new));
+    clipboard
+        .as_ref()
+        .and_then(|clipboard| clipboard.lock().ok())
+        .is_some_and(|mut clipboard| clipboard.set_text(text).is_ok())
+}
+
+/// Writes the bindings of `keymap` for `--dump-keys`, one `key<TAB>action` pair
+/// per line: the confirm, quit and format keys first, then the keymap's own
+/// bindings, then the `SHARED_KEYS`. The keymap's name comes first, on a
+/// `# keymap: <name>` line. With a `quit_key`, the default quit keys are left out.
+fn dump_keys<W: Write>(
+    out: &mut W,
+    keymap: Keymap,
+    confirm_key: &KeyBinding,
+    quit_key: Option<&KeyBinding>,
+    format_key: &KeyBinding,
+) -> Result<()> {
+    writeln!(out, "# keymap: {}", keymap.name())?;
+    writeln!(out, "{}\tconfirm", confirm_key.spec())?;
+    if let Some(quit_key) = quit_key {
+        writeln!(out, "{}\tquit", quit_key.spec())?;
+    }
+    writeln!(out, "{}\tnext-output-format", format_key.spec())?;
+    for (key, action) in keymap.key_table().iter().chain(SHARED_KEYS) {
+        if quit_key.is_some() && *action == "quit" {
+            continue;
+        }
+        writeln!(out, "{}\t{}", key, action)?;
+    }
+    out.flush()
+}
+
+/// Builds the command that opens the file manager `manager` at `path`.
+///
+/// `manager` is split at whitespace, so it may carry arguments (e.g. `nnn -d`),
+/// and `path` is appended as the last argument. Returns `None` for a blank value.
+fn filemanager_command(manager: &str, path: &Path) -> Option<std::process::Command> {
+    let mut words = manager.split_whitespace();
+    let mut command = std::process::Command::new(words.next()?);
+    command.args(words).arg(path);
+    Some(command)
+}
+
+/// Runs the file manager `command` in the terminal and waits for it to exit.
+///
+/// Its stdin and stderr are inherited. Its stdout is too, unless that is not a
+/// terminal (as in `cd "$(pd)"`), in which case it draws to the terminal `pd`
+/// draws to instead.
+fn run_filemanager(mut command: std::process::Command) -> Result<std::process::ExitStatus> {
+    if !std::io::stdout().is_terminal() {
+        let stdout = match TERM_DEVICE.get() {
+            Some(file) => std::process::Stdio::from(file.try_clone()?),
+            None => std::process::Stdio::from(stderr()),
+        };
+        command.stdout(stdout);
+    }
+    command.status()
+}
+
+/// The commands tried in order to read the system clipboard, with their arguments.
+///
+/// OSC 52 can only be used to set the clipboard reliably, so reading it relies
+/// on the platform's clipboard tools.
+const PASTE_COMMANDS: &[&[&str]] = if cfg!(target_os = "macos") {
+    &[&["pbpaste"]]
+} else if cfg!(windows) {
+    &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard"]]
+} else {
+    &[
+        &["wl-paste", "--no-newline"],
+        &["xclip", "-selection", "clipboard", "-o"],
+        &["xsel", "--clipboard", "--output"],
+    ]
+};
+
+/// Reads the system clipboard as text using the first of `PASTE_COMMANDS` that works.
+fn read_clipboard() -> Result<String> {
+    for command in PASTE_COMMANDS {
+        let output = std::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output();
+        if let Ok(output) = output
+            && output.status.success()
+        {
+            return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "no clipboard tool available",
+    ))
+}
+
+/// Converts a path component into a string that is safe to print on a single line.
+///
+/// Directory names may legally contain newlines, tabs, and other control
+/// characters, which would break the single-line UI. These are replaced with
+/// caret notation (e.g. `\n` becomes `^J`, DEL becomes `^?`), while the original
+/// bytes are kept untouched in `path_parts` so the emitted path is still exact.
+///
+/// Names that are not valid Unicode (invalid UTF-8 on Unix, unpaired surrogates
+/// on Windows) are shown with one U+FFFD per invalid sequence. Every width used
+/// for mouse hit-testing is measured on this same text, so a click maps to the
+/// part drawn under it however lossy the conversion was.
+fn display_part(part: &OsStr) -> String {
+    let mut display = String::new();
+    for c in part.to_string_lossy().chars() {
+        match c {
+            '\u{7f}' => display.push_str("^?"),
+            c if c.is_ascii_control() => {
+                display.push('^');
+                display.push((c as u8 + b'@') as char);
+            }
+            c => display.push(c),
+        }
+    }
+    display
+}
+
+/// Where `render` draws: a writer, and the region of the screen to draw in.
+///
+/// The breadcrumb is drawn on the first row of the region and any further lines
+/// (caret, preview, listing, status) on the rows below it. Those lines are cut
+/// to `width` columns so that they never wrap.
+///
+/// Without an `origin`, the region starts at column 0 of the line the cursor is
+/// on. Lines below are cleared as they are drawn, and the cursor is put back on
+/// the breadcrumb line afterwards. This is how `pd` draws on its own terminal.
+///
+/// With an `origin` (column, row), the cursor is moved there absolutely, and only
+/// `width` columns of each row are cleared before drawing, so that `pd` can be
+/// drawn into part of a larger TUI. Rows the previous frame used but this one
+/// doesn't are left for the caller to repaint.
+struct RenderTarget<'a, W: Write> {
+    writer: &'a mut W,
+    origin: Option<(u16, u16)>,
+    width: usize,
+    /// The row being drawn, counted from the breadcrumb.
+    row: u16,
+}
+
+impl<'a, W: Write> RenderTarget<'a, W> {
+    /// Draws on the cursor's line of the terminal, as wide as the terminal.
+    fn inline(writer: &'a mut W) -> Self {
+        Self {
+            writer,
+            origin: None,
+            width: terminal_width(),
+            row: 0,
+        }
+    }
+
+    /// Moves to the start of the breadcrumb row.
+    fn start(&mut self) -> Result<()> {
+        self.row = 0;
+        self.move_to_row()
+    }
+
+    /// Moves to the start of the next row, cleared, clearing the rest of this one
+    /// as it goes.
+    fn next_line(&mut self) -> Result<()> {
+        self.row += 1;
+        match self.origin {
+            None => execute!(
+                self.writer,
+                Clear(ClearType::UntilNewLine),
+                Print("\r\n"),
+                Clear(ClearType::CurrentLine)
+            ),
+            Some(_) => self.move_to_row(),
+        }
+    }
+
+    /// Moves to the start of the current row, clearing it first if the region
+    /// has an origin.
+    fn move_to_row(&mut self) -> Result<()> {
+        match self.origin {
+            None => execute!(self.writer, cursor::MoveToColumn(0)),
+            Some((column, row)) => execute!(
+                self.writer,
+                cursor::MoveTo(column, row + self.row),
+                Print(" ".repeat(self.width)),
+                cursor::MoveTo(column, row + self.row)
+            ),
+        }
+    }
+
+    /// Clears what the previous frame left below the last row and puts the
+    /// cursor back at the start of the breadcrumb row.
+    ///
+    /// Leaving the cursor in the first column means that it stays at the start
+    /// of the frame when a narrower terminal rewraps the breadcrumb.
+    fn finish(&mut self) -> Result<()> {
+        if self.origin.is_none() {
+            execute!(self.writer, Clear(ClearType::FromCursorDown))?;
+            if self.row > 0 {
+                execute!(self.writer, cursor::MoveUp(self.row))?;
+            }
+            execute!(self.writer, cursor::MoveToColumn(0))?;
+        }
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Write for RenderTarget<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Renders the interactive path selection UI to `out`.
+///
+/// This function redraws the breadcrumb line and then the lines below it. The
+/// currently selected component is highlighted with a reverse attribute.
+///
+/// # Arguments
+/// * `out`: Where to draw (see `RenderTarget`), typically on `stderr`.
+/// * `state`: The current state of the application.
+fn render<W: Write>(out: &mut RenderTarget<'_, W>, state: &AppState) -> Result<()> {
+    if state.clear_screen && out.origin.is_none() {
+        execute!(out, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+    } else if state.resized && out.origin.is_none() {
+        // The cursor was left at the start of the breadcrumb, which is where a
+        // rewrapped frame starts too.
+        execute!(out, cursor::MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+    }
+    out.start()?;
+    let viewport = state.viewport();
+    if viewport.clipped_left {
+        execute!(out, SetAttribute(Attribute::Dim), Print("…"), SetAttribute(Attribute::Reset))?;
+    }
+    // With `Layout::Wrap`, these parts start the rows after the first.
+    let row_starts: Vec<usize> =
+        state.part_rows().iter().skip(1).filter_map(|row| row.first().copied()).collect();
+    for i in viewport.order {
+        if row_starts.contains(&i) {
+            out.next_line()?;
+        }
+        let display_part = state.part_text(i, &state.path_parts[i]);
+        let selected = i == state.current_index || state.in_range(i);
+        let attribute = match (state.highlight_mode, selected) {
+            (HighlightMode::Reverse | HighlightMode::DimDescendants, true)
+                if state.selection_style.is_none() && !state.no_color =>
+            {
+                Some(Attribute::Reverse) // Set reverse video for selection
+            }
+            (HighlightMode::DimOthers, false) => Some(Attribute::Dim),
+            (HighlightMode::DimDescendants, false) if i > state.current_index => {
+                Some(Attribute::Dim)
+            }
+            (_, false)
+                if state.is_outside_focus(i)
+                    || !state.matches_narrow(i)
+                    || !state.matches_filter_query(i) =>
+            {
+                Some(Attribute::Dim)
+            }
+            _ => None,
+        };
+        let prefix = state.index_prefix(i);
+        if !prefix.is_empty() {
+            execute!(
+                out,
+                SetAttribute(Attribute::Dim),
+                Print(prefix),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        let jump_match = state.jump_highlight.is_some_and(|target| {
+            state.is_landable(i) && state.part_contains(&state.path_parts[i], target)
+        });
+        if jump_match || (state.show_mounts && state.is_mount_point(i)) {
+            execute!(out, SetAttribute(Attribute::Underlined))?;
+        }
+        // The emphasis is set before, and so combines with, the selection highlight.
+        if state.bold_last && i + 1 == state.path_parts.len() {
+            execute!(out, SetAttribute(Attribute::Bold))?;
+        }
+        if let Some(style) = state.hover_style
+            && state.hover_index == Some(i)
+            && !selected
+        {
+            execute!(out, SetAttribute(style))?;
+        }
+        // A selection style's own colors take precedence.
+        if let Some(color) = state.part_types.get(&i).and_then(|part_type| part_type.color())
+            && !state.no_color
+        {
+            execute!(out, SetForegroundColor(color))?;
+        }
+        if selected
+            && !state.no_color
+            && let Some(style) = &state.selection_style
+        {
+            style.apply(out)?;
+        }
+        if let Some(attribute) = attribute {
+            execute!(
+                out,
+                SetAttribute(attribute),
+                Print(display_part),
+                SetAttribute(Attribute::Reset) // Reset attributes
+            )?;
+        } else {
+            execute!(out, Print(display_part), SetAttribute(Attribute::Reset))?;
+        }
+    }
+    if viewport.clipped_right {
+        execute!(out, SetAttribute(Attribute::Dim), Print("…"), SetAttribute(Attribute::Reset))?;
+    }
+
+    if state.show_dotdots {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(state.dotdots()),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    if let Some(count) = state.sticky_count {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(format!("*{}", count)),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    if !state.narrow.is_empty() {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(format!("/{}", state.narrow)),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    if state.output_format != OutputFormat::Absolute {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(format!("[{}]", state.output_format.name())),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    if !state.tabs.is_empty() {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(format!("tab {}/{}", state.tab + 1, state.tabs.len() + 1)),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    if let Some((register, _)) = &state.recording {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(format!("recording @{}", register)),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    // A count being typed and a pending jump show what they are waiting for,
+    // e.g. `12` or `3f_`, like Vim's `showcmd`.
+    let prompt = match &state.input_mode {
+        InputMode::WaitForNextKey(prompt, _) => prompt,
+        _ => "",
+    };
+    if !state.count_input.is_empty() || !prompt.is_empty() {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(&state.count_input),
+            Print(prompt),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    if let Some(message) = &state.status_message
+        && state.status_layout == StatusLayout::Inline
+    {
+        execute!(
+            out,
+            Print("  "),
+            SetAttribute(Attribute::Dim),
+            Print(message),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    if state.show_caret {
+        let (column, width) = state.part_span(state.current_index);
+        let caret = format!("{}{}", " ".repeat(column), "^".repeat(width));
+        let caret = truncate_to_width(&caret, out.width);
+        out.next_line()?;
+        execute!(out, Print(caret))?;
+    }
+
+    if state.show_path_line {
+        let mut position = format!(" [{}/{}]", state.current_index + 1, state.path_parts.len());
+        if !state.count_input.is_empty() {
+            position.push_str(&format!(" {}", state.count_input));
+        }
+        // The path gives way first, so that the position always fits.
+        let path = state.selected_path().display().to_string();
+        let path = truncate_to_width(&path, out.width.saturating_sub(display_width(&position)));
+        out.next_line()?;
+        execute!(
+            out,
+            Print(path),
+            SetAttribute(Attribute::Dim),
+            Print(position),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    let preview = state.previews.get(&state.current_index);
+    if state.preview_pane && matches!(state.input_mode, InputMode::Normal) {
+        render_preview_pane(out, preview)?;
+    } else if state.preview_dir && matches!(state.input_mode, InputMode::Normal) {
+        let preview = preview.map(preview_line).unwrap_or_default();
+        let preview = truncate_to_width(&preview, out.width);
+        out.next_line()?;
+        execute!(
+            out,
+            SetAttribute(Attribute::Dim),
+            Print(preview),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+
+    match &state.input_mode {
+        InputMode::Descend(list) => render_child_list(out, state, list)?,
+        InputMode::Command(line) => {
+            out.next_line()?;
+            execute!(
+                out,
+                Print(":"),
+                Print(&line.input),
+                SetAttribute(Attribute::Reverse), // Draw a block cursor
+                Print(" "),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        InputMode::Filter(filter) => {
+            out.next_line()?;
+            execute!(
+                out,
+                Print("/"),
+                Print(&filter.query),
+                SetAttribute(Attribute::Reverse),
+                Print(" "),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        InputMode::Fuzzy(fuzzy) => {
+            out.next_line()?;
+            execute!(
+                out,
+                Print("fuzzy: "),
+                Print(&fuzzy.query),
+                SetAttribute(Attribute::Reverse),
+                Print(" "),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        _ => {}
+    }
+
+    if let Some(message) = &state.status_message
+        && state.status_layout != StatusLayout::Inline
+    {
+        render_status_line(out, state.status_layout, message)?;
+    }
+    out.finish()
+}
+
+/// The side channel opened by `--live-fd`, fed with the selection as it changes.
+struct LiveFeed {
+    file: fs::File,
+    last: Option<PathBuf>,
+}
+
+impl LiveFeed {
+    /// Opens file descriptor `fd`, which the parent process must have set up.
+    fn open(fd: u32) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(format!("/dev/fd/{}", fd))
+            .map_err(|e| std::io::Error::new(e.kind(), format!("--live-fd {}: {}", fd, e)))?;
+        Ok(Self { file, last: None })
+    }
+
+    /// Writes `path` followed by a NUL byte, unless it was the last path written.
+    fn emit(&mut self, path: &Path) -> Result<()> {
+        if self.last.as_deref() == Some(path) {
+            return Ok(());
+        }
+        self.file.write_all(path.as_os_str().as_encoded_bytes())?;
+        self.file.write_all(b"\0")?;
+        self.file.flush()?;
+        self.last = Some(path.to_path_buf());
+        Ok(())
+    }
+}
+
+/// Renders a one-line breadcrumb for `--mini`, for embedding in a shell prompt.
+///
+/// The last part is set in bold unless `color` is false; nothing else is
+/// styled, and no cursor movement or clearing sequences are written. With
+/// `abbreviate`, the parts before the last are shortened (see `abbreviate_part`).
+fn render_mini<W: Write>(
+    out: &mut W,
+    path_parts: &[OsString],
+    color: bool,
+    abbreviate: bool,
+) -> Result<()> {
+    let last = path_parts.len().saturating_sub(1);
+    for (i, part) in path_parts.iter().enumerate() {
+        if color && i == last {
+            queue!(
+                out,
+                SetAttribute(Attribute::Bold),
+                Print(display_part(part)),
+                SetAttribute(Attribute::Reset)
+            )?;
+        } else if abbreviate && i != last {
+            queue!(out, Print(abbreviate_part(part)))?;
+        } else {
+            queue!(out, Print(display_part(part)))?;
+        }
+    }
+    queue!(out, Print("\n"))?;
+    out.flush()
+}
+
+/// Prints `path` for `--json` as a single-line JSON object, e.g.
+/// `{"path":"/home/me","components":["/","home/","me"],"selected":2}`.
+///
+/// The components are the parts the UI shows (see `split_path`), and `selected`
+/// is the index of the part that would be selected initially. Parts that are not
+/// valid UTF-8 are converted lossily.
+fn render_json<W: Write>(
+    out: &mut W,
+    path: &Path,
+    path_parts: &[OsString],
+    selected: usize,
+) -> Result<()> {
+    let components: Vec<String> = path_parts
+        .iter()
+        .map(|part| json_string(&part.to_string_lossy()))
+        .collect();
+    writeln!(
+        out,
+        "{{\"path\":{},\"components\":[{}],\"selected\":{}}}",
+        json_string(&path.to_string_lossy()),
+        components.join(","),
+        selected
+    )?;
+    out.flush()
+}
+
+/// Quotes `text` as a JSON string, escaping quotes, backslashes and control
+/// characters.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the width of the terminal in columns, or 80 if it is not known.
+fn terminal_width() -> usize {
+    match terminal::size() {
+        Ok((columns, _)) if columns > 0 => columns as usize,
+        _ => 80,
+    }
+}
+
+/// Returns the number of terminal columns `text` takes up. Wide characters such
+/// as CJK ideographs and most emoji take two, and combining marks none.
+///
+/// Everything that positions text on the screen, including mouse hit-testing,
+/// measures with this rather than by counting characters.
+fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// Shortens `text` to at most `width` columns, ending it with `…` if anything
+/// was cut off. Even a one-column terminal gets the `…`; only a width of zero
+/// yields nothing. A wide character that would straddle the limit is dropped.
+fn truncate_to_width(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let c_width = UnicodeWidthChar::width(c).unwrap_or(0);
+        if used + c_width > width - 1 {
+            break;
+        }
+        truncated.push(c);
+        used += c_width;
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// Pads `text` with spaces on the right to `width` columns, like `{:<width$}`
+/// but counting columns rather than characters.
+fn pad_to_width(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// Renders `message` on a line of its own below everything else, preceded by
+/// the separator `layout` calls for.
+fn render_status_line<W: Write>(
+    out: &mut RenderTarget<'_, W>,
+    layout: StatusLayout,
+    message: &str,
+) -> Result<()> {
+    match layout {
+        StatusLayout::Inline | StatusLayout::Line => {}
+        StatusLayout::Blank => out.next_line()?,
+        StatusLayout::Rule => {
+            let rule = "─".repeat(out.width);
+            out.next_line()?;
+            execute!(
+                out,
+                SetAttribute(Attribute::Dim),
+                Print(rule),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+    }
+    let message = truncate_to_width(message, out.width);
+    out.next_line()?;
+    execute!(
+        out,
+        SetAttribute(Attribute::Dim),
+        Print(message),
+        SetAttribute(Attribute::Reset)
+    )
+}
+
+/// Renders the entries of a `ChildList` on the lines below the breadcrumb.
+///
+/// At most `MAX_LISTING_ROWS` entries are shown, scrolled so that the
+/// highlighted entry is always visible. Annotations are right-aligned after the
+/// longest visible name. Returns the number of lines drawn.
+fn render_child_list<W: Write>(
+    out: &mut RenderTarget<'_, W>,
+    state: &AppState,
+    list: &ChildList,
+) -> Result<()> {
+    if list.entries.is_empty() {
+        let placeholder = truncate_to_width("  (no subdirectories)", out.width);
+        out.next_line()?;
+        return execute!(out, Print(placeholder));
+    }
+
+    let first = list.selected.saturating_sub(MAX_LISTING_ROWS - 1);
+    let last = (first + MAX_LISTING_ROWS).min(list.entries.len());
+    let names: Vec<String> = list.entries[first..last]
+        .iter()
+        .map(|name| format!("{}{}", display_part(name), std::path::MAIN_SEPARATOR))
+        .collect();
+    let name_width = names.iter().map(|name| display_width(name)).max().unwrap_or(0);
+    let parent = state.path_at(list.parent_index);
+
+    for (offset, name) in names.iter().enumerate() {
+        let i = first + offset;
+        let annotation = list.annotations[i]
+            .get_or_init(|| annotate_dir(&parent.join(&list.entries[i]), state.size_annotation));
+        let row = format!("  {} {:>6}", pad_to_width(name, name_width), annotation);
+        let row = truncate_to_width(row.trim_end(), out.width);
+        out.next_line()?;
+        if i == list.selected {
+            execute!(
+                out,
+                SetAttribute(Attribute::Reverse),
+                Print(row),
+                SetAttribute(Attribute::Reset)
+            )?;
+        } else {
+            execute!(out, Print(row))?;
+        }
+    }
+    Ok(())
+}
+
+/// Draws the preview pane: the entries of the selected directory, one per line
+/// below the path, up to `PREVIEW_PANE_ROWS` and a `…` for the rest.
+///
+/// Nothing is drawn until the directory has been read.
+fn render_preview_pane<W: Write>(
+    out: &mut RenderTarget<'_, W>,
+    preview: Option<&DirPreview>,
+) -> Result<()> {
+    let rows: Vec<&str> = match preview {
+        None => return Ok(()),
+        Some(DirPreview::Entries(names)) if names.is_empty() => vec!["(empty)"],
+        Some(DirPreview::Entries(names)) => {
+            let mut rows: Vec<&str> =
+                names.iter().take(PREVIEW_PANE_ROWS).map(String::as_str).collect();
+            if names.len() > PREVIEW_PANE_ROWS {
+                rows.push("…");
+            }
+            rows
+        }
+        Some(DirPreview::Unreadable(placeholder)) => vec![placeholder],
+    };
+    for row in rows {
+        let row = truncate_to_width(&format!("  {}", row), out.width);
+        out.next_line()?;
+        execute!(out, SetAttribute(Attribute::Dim), Print(row), SetAttribute(Attribute::Reset))?;
+    }
+    Ok(())
+}
+
+/// Performs a bound `Action`.
+fn run_action(action: Action, state: &mut AppState) -> EventAction {
+    match action {
+        Action::MoveLeft => state.move_by(-1, state.wrap.keys),
+        Action::MoveRight => state.move_by(1, state.wrap.keys),
+        Action::MoveToStart => state.move_to_start(),
+        Action::MoveToEnd => state.move_to_end(),
+        Action::Confirm => return state.confirm(),
+        Action::JumpForward | Action::JumpBackward => {
+            let direction = match action {
+                Action::JumpForward => JumpDirection::Forward,
+                _ => JumpDirection::Backward,
+            };
+            let count = std::mem::take(&mut state.count_input);
+            let prompt = match direction {
+                JumpDirection::Forward => "f_",
+                JumpDirection::Backward => "F_",
+            };
+            let action = char_jump_action(direction, false, count, prompt);
+            state.input_mode = InputMode::WaitForNextKey(prompt, action);
+        }
+        Action::Reload => state.reload(),
+    }
+    EventAction::Continue
+}
+
+/// Returns the pending action of a character jump (`f`, `F`, `t` or `T`), which
+/// jumps with `count`, the count typed before the jump key, once the target
+/// character is typed.
+///
+/// A count cannot be typed between the jump key and its character, as in Vim:
+/// after a count, digits are ignored and the jump keeps waiting, so `3f7x`
+/// selects the third part with an `x`. Without one, a digit is the target,
+/// so that `f7` still finds `2017`.
+fn char_jump_action(
+    direction: JumpDirection,
+    till: bool,
+    count: String,
+    prompt: &'static str,
+) -> PendingAction {
+    Box::new(move |next_key, current_state| {
+        // This closure will be executed with the next key press.
+        match next_key.code {
+            KeyCode::Char(c) if c.is_ascii_digit() && !count.is_empty() => {
+                let action = char_jump_action(direction, till, count, prompt);
+                current_state.input_mode = InputMode::WaitForNextKey(prompt, action);
+            }
+            KeyCode::Char(c) => {
+                // Restore the captured count before executing the jump.
+                current_state.count_input = count;
+                if till {
+                    current_state.till_char(direction, c);
+                } else {
+                    current_state.jump_to_char(direction, c);
+                }
+            }
+            // If any other key is pressed (e.g., Esc), the closure does nothing,
+            // effectively canceling the jump command.
+            _ => {}
+        }
+    })
+}
+
+/// Processes Vim-style key bindings to navigate the path components.
+///
+/// This function updates the application state based on Vim key bindings. For multi-key
+/// sequences like `f` or `F`, it sets the application's `input_mode` to
+/// `InputMode::WaitForNextKey` with a closure that defines the subsequent action.
+///
+/// # Arguments
+/// * `key`: The keyboard event to process.
+/// * `state`: Mutable reference to the current application state.
+fn handle_vim_keys(key: KeyEvent, state: &mut AppState) {
+    match key.code {
+        // C-d, C-u
+        KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            state.move_half(JumpDirection::Forward)
+        }
+        KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            state.move_half(JumpDirection::Backward)
+        }
+        // C-r
+        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => state.jump_forward(),
+        // Other Ctrl combinations are shared keys, not Vim motions.
+        KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => {}
+
+        // State-changing Motions
+        KeyCode::Char(letter @ ('f' | 'F' | 't' | 'T')) => {
+            let direction = if letter.is_lowercase() {
+                JumpDirection::Forward
+            } else {
+                JumpDirection::Backward
+            };
+            let till = letter.eq_ignore_ascii_case(&'t');
+
+            // Capture the current count now, as it will be used by the closure.
+            let count_for_jump = state.count_input.clone();
+            state.count_input.clear();
+
+            let prompt = match letter {
+                'f' => "f_",
+                'F' => "F_",
+                't' => "t_",
+                _ => "T_",
+            };
+            let action = char_jump_action(direction, till, count_for_jump, prompt);
+            // Set the application to wait for the next key.
+            state.input_mode = InputMode::WaitForNextKey(prompt, action);
+        }
+
+        KeyCode::Char('g') => {
+            state.count_input.clear();
+            let action: PendingAction = Box::new(|next_key, current_state| {
+                match next_key.code {
+                    KeyCode::Char('g') => current_state.move_to_start(),
+                    KeyCode::Char('/') => current_state.start_fuzzy(),
+                    _ => {}
+                }
+            });
+            state.input_mode = InputMode::WaitForNextKey("g_", action);
+        }
+
+        // Macros
+        KeyCode::Char('Q') if state.recording.is_some() => state.stop_recording(),
+        KeyCode::Char('Q') => {
+            let action: PendingAction = Box::new(|next_key, current_state| {
+                if let KeyCode::Char(c) = next_key.code
+                    && c.is_ascii_alphabetic()
+                {
+                    current_state.recording = Some((c, Vec::new()));
+                }
+            });
+            state.input_mode = InputMode::WaitForNextKey("Q_", action);
+        }
+        KeyCode::Char('@') => {
+            let action: PendingAction = Box::new(|next_key, current_state| {
+                if let KeyCode::Char(c) = next_key.code {
+                    current_state.replay_register(c);
+                }
+            });
+            state.input_mode = InputMode::WaitForNextKey("@_", action);
+        }
+
+        // Marks
+        KeyCode::Char('m') => {
+            let action: PendingAction = Box::new(|next_key, current_state| {
+                if let KeyCode::Char(c) = next_key.code
+                    && c.is_ascii_alphabetic()
+                {
+                    current_state.set_mark(c);
+                }
+            });
+            state.input_mode = InputMode::WaitForNextKey("m_", action);
+        }
+        KeyCode::Char('`' | '\'') => {
+            let action: PendingAction = Box::new(|next_key, current_state| {
+                if let KeyCode::Char(c) = next_key.code
+                    && c.is_ascii_alphabetic()
+                {
+                    current_state.jump_to_mark(c);
+                }
+            });
+            state.input_mode = InputMode::WaitForNextKey("`_", action);
+        }
+
+        // Immediate Motions
+        KeyCode::Char(';') => state.repeat_jump(false),
+        KeyCode::Char(',') => state.repeat_jump(true),
+        KeyCode::Char('h' | 'k' | 'b') => state.move_by(-1, state.wrap.keys),
+        // There is nothing further right of the deepest part but its children.
+        KeyCode::Char('l') if state.at_deepest_part() => state.open_child_list(),
+        KeyCode::Char('l' | 'j' | 'w') => state.move_by(1, state.wrap.keys),
+        KeyCode::Char('^' | 'H') => state.move_to_start(),
+        KeyCode::Char('$' | 'L') => state.move_to_end(),
+        KeyCode::Char('G') => state.move_to_count_or_end(),
+        KeyCode::Char('M') => state.move_to_middle(),
+        KeyCode::Char('%') => state.move_to_percent(),
+        KeyCode::Char('D') => state.move_to_mount_point(),
+        KeyCode::Char('|') => state.move_to_index(),
+        KeyCode::Char('R') => state.reload(),
+        KeyCode::Char('=') => state.resolve_symlinks(),
+        KeyCode::Char('c') if key.modifiers.is_empty() => state.copy_quoted_path(),
+        KeyCode::Char('y') => state.copy_selected_path(),
+        KeyCode::Char('Y') => state.copy_selected_name(),
+        KeyCode::Char('S') => state.show_git_status(),
+        KeyCode::Char('*') => state.set_sticky_count(),
+        KeyCode::Char('#') => state.cycle_same_name(),
+        KeyCode::Char('v') => state.toggle_anchor(),
+        KeyCode::Char('p') => state.jump_to_clipboard(),
+        KeyCode::Char('P') => state.toggle_preview_pane(),
+        KeyCode::Char('~') => state.toggle_home(),
+        KeyCode::Char('u') => state.jump_back(),
+        KeyCode::Char(':') => state.input_mode = InputMode::Command(CommandLine::default()),
+        KeyCode::Char('/') => state.start_filter(),
+        KeyCode::Char('(') => state.move_to_parity(Parity::Even, JumpDirection::Backward),
+        KeyCode::Char(')') => state.move_to_parity(Parity::Odd, JumpDirection::Backward),
+        KeyCode::Char('{') => state.move_to_branch(JumpDirection::Backward),
+        KeyCode::Char('}') => state.move_to_branch(JumpDirection::Forward),
+
+        KeyCode::Char(c) if state.digit_mode == DigitMode::Select && c.is_ascii_digit() => {
+            state.select_digit(c as usize - '0' as usize, Instant::now());
+        }
+
+        // Count Accumulation
+        KeyCode::Char(c) if c.is_ascii_digit() => {
+            if c == '0' && state.count_input.is_empty() {
+                state.move_to_start();
+            } else {
+                state.count_input.push(c);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Processes Emacs-style key bindings to navigate the path components.
+///
+/// This function updates the application state based on Emacs key bindings such as
+/// `Ctrl-b`, `Ctrl-f`, `Alt-b`, `Alt-f` for navigation.
+///
+/// # Arguments
+/// * `key`: The keyboard event to process.
+/// * `state`: Mutable reference to the current application state.
+fn handle_emacs_keys(key: KeyEvent, state: &mut AppState) {
+    const CTRL: KeyModifiers = KeyModifiers::CONTROL;
+    const ALT: KeyModifiers = KeyModifiers::ALT;
+
+    match key.code {
+        KeyCode::Char(']') if key.modifiers.contains(CTRL) => {
+            let action: PendingAction = Box::new(move |next_key, current_state| {
+                // This closure will be executed with the next key press.
+                if let KeyCode::Char(c) = next_key.code {
+                    current_state.jump_to_char(JumpDirection::Forward, c);
+                }
+                // If any other key is pressed (e.g., Esc), the closure does nothing,
+                // effectively canceling the jump command.
+            });
+            // Set the application to wait for the next key.
+            state.input_mode = InputMode::WaitForNextKey("C-]_", action);
+        }
+        // C-b, Alt-b
+        KeyCode::Char('b') if key.modifiers.contains(CTRL) => state.move_by(-1, state.wrap.keys),
+        KeyCode::Char('b') if key.modifiers.contains(ALT) => state.move_by(-1, state.wrap.keys),
+        // C-f, Alt-f
+        KeyCode::Char('f') if key.modifiers.contains(CTRL) => state.move_by(1, state.wrap.keys),
+        KeyCode::Char('f') if key.modifiers.contains(ALT) => state.move_by(1, state.wrap.keys),
+        // M-{, M-}
+        KeyCode::Char('{') if key.modifiers.contains(ALT) => {
+            state.move_to_branch(JumpDirection::Backward)
+        }
+        KeyCode::Char('}') if key.modifiers.contains(ALT) => {
+            state.move_to_branch(JumpDirection::Forward)
+        }
+        // C-n
+        KeyCode::Char('n') if key.modifiers.contains(CTRL) => state.open_child_list(),
+        // M-q
+        KeyCode::Char('q') if key.modifiers.contains(ALT) => state.copy_quoted_path(),
+        // M-w, M-W
+        KeyCode::Char('w') if key.modifiers.contains(ALT) => state.copy_selected_path(),
+        KeyCode::Char('W') if key.modifiers.contains(ALT) => state.copy_selected_name(),
+        // M-g
+        KeyCode::Char('g') if key.modifiers.contains(ALT) => state.show_git_status(),
+        // M-s
+        KeyCode::Char('s') if key.modifiers.contains(ALT) => state.cycle_same_name(),
+        // C-SPC
+        KeyCode::Char(' ') if key.modifiers.contains(CTRL) => state.toggle_anchor(),
+        // C-y
+        KeyCode::Char('y') if key.modifiers.contains(CTRL) => state.jump_to_clipboard(),
+        // M-m
+        KeyCode::Char('m') if key.modifiers.contains(ALT) => state.move_to_mount_point(),
+        // M-r
+        KeyCode::Char('r') if key.modifiers.contains(ALT) => state.resolve_symlinks(),
+        // M-p
+        KeyCode::Char('p') if key.modifiers.contains(ALT) => state.toggle_preview_pane(),
+        // M-~
+        KeyCode::Char('~') if key.modifiers.contains(ALT) => state.toggle_home(),
+        // C-/, which most terminals send as C-_ (and crossterm reports as C-7)
+        KeyCode::Char('/' | '_' | '7') if key.modifiers.contains(CTRL) => state.jump_back(),
+        // M-_
+        KeyCode::Char('_') if key.modifiers.contains(ALT) => state.jump_forward(),
+        // M-x
+        KeyCode::Char('x') if key.modifiers.contains(ALT) => {
+            state.input_mode = InputMode::Command(CommandLine::default())
+        }
+        KeyCode::Char('/') if !key.modifiers.intersects(CTRL | ALT) => state.start_filter(),
+        // C-s
+        KeyCode::Char('s') if key.modifiers.contains(CTRL) => state.start_fuzzy(),
+        // C-a
+        KeyCode::Char('a') if key.modifiers.contains(CTRL) => state.move_to_start(),
+        // C-e
+        KeyCode::Char('e') if key.modifiers.contains(CTRL) => state.move_to_end(),
+        // C-u
+        KeyCode::Char('u') if key.modifiers.contains(CTRL) => state.universal_argument(),
+        // C-v, M-v
+        KeyCode::Char('v') if key.modifiers.contains(CTRL) => {
+            state.move_half(JumpDirection::Forward)
+        }
+        KeyCode::Char('v') if key.modifiers.contains(ALT) => {
+            state.move_half(JumpDirection::Backward)
+        }
+        KeyCode::Char(c) if c.is_ascii_digit() && key.modifiers.is_empty() => {
+            state.universal_argument_digit(c)
+        }
+        _ => {}
+    }
+}
+
+/// Processes key events while a `ChildList` is displayed.
+///
+/// The listing is navigated with the arrow keys, `j`/`k`, or `Ctrl-n`/`Ctrl-p`
+/// regardless of the keymap. `Enter` or `Right` descends into the highlighted
+/// directory; `Esc`, `q`, or `Left` closes the listing without changes, and
+/// `Ctrl-c` quits the application.
+fn handle_descend_keys(key: KeyEvent, state: &mut AppState, mut list: ChildList) -> EventAction {
+    const CTRL: KeyModifiers = KeyModifiers::CONTROL;
+
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(CTRL) => return EventAction::Quit,
+        KeyCode::Char('p') if key.modifiers.contains(CTRL) => {
+            list.selected = list.selected.saturating_sub(1);
+        }
+        KeyCode::Char('n') if key.modifiers.contains(CTRL) => {
+            list.selected = (list.selected + 1).min(list.entries.len().saturating_sub(1));
+        }
+        KeyCode::Up | KeyCode::Char('k') => {
+            list.selected = list.selected.saturating_sub(1);
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            list.selected = (list.selected + 1).min(list.entries.len().saturating_sub(1));
+        }
+        KeyCode::Enter | KeyCode::Right => {
+            if let Some(child) = list.entries.get(list.selected) {
+                state.descend_into(list.parent_index, child);
+            }
+            return EventAction::Continue;
+        }
+        // Tab keeps the listing open, one level further down.
+        KeyCode::Tab => {
+            if let Some(child) = list.entries.get(list.selected) {
+                state.descend_into(list.parent_index, child);
+                state.open_child_list();
+            }
+            return EventAction::Continue;
+        }
+        KeyCode::Esc | KeyCode::Left | KeyCode::Char('q') => return EventAction::Continue,
+        _ => {}
+    }
+    state.input_mode = InputMode::Descend(list);
+    EventAction::Continue
+}
+
+/// Processes key events for the `none` keymap, returning true if the key was used.
+///
+/// Printable characters narrow the path (see `AppState::narrow_by`) and
+/// `Backspace` widens it again. Other keys are left to the shared keybindings.
+fn handle_narrow_keys(key: KeyEvent, state: &mut AppState) -> bool {
+    match key.code {
+        KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            state.narrow_by(c);
+        }
+        KeyCode::Backspace if state.narrow.pop().is_some() => {}
+        _ => return false,
+    }
+    true
+}
+
+/// Processes key events for the `standard` keymap, returning true if the key was used.
+///
+/// `PageUp` and `PageDown` move by half the path (see `AppState::move_half`).
+/// Printable characters are used up without doing anything, so that a stray
+/// letter never moves the selection or quits; the arrows and the other shared
+/// keys do the rest.
+fn handle_standard_keys(key: KeyEvent, state: &mut AppState) -> bool {
+    const CTRL: KeyModifiers = KeyModifiers::CONTROL;
+    const ALT: KeyModifiers = KeyModifiers::ALT;
+
+    match key.code {
+        KeyCode::PageDown => state.move_half(JumpDirection::Forward),
+        KeyCode::PageUp => state.move_half(JumpDirection::Backward),
+        KeyCode::Char(_) if !key.modifiers.intersects(CTRL | ALT) => {}
+        _ => return false,
+    }
+    true
+}
+
+/// Processes key events while a filter query is being typed.
+///
+/// Each printable character extends the query and moves the selection to the
+/// shallowest component containing it; a character that would leave no match
+/// is rejected. `Backspace` shortens the query, `Enter` keeps the selection and
+/// `Esc` restores the one from before filtering began.
+fn handle_filter_keys(key: KeyEvent, state: &mut AppState, mut filter: FilterQuery) {
+    match key.code {
+        KeyCode::Enter => return,
+        KeyCode::Esc => {
+            state.current_index = filter.previous_index;
+            return;
+        }
+        KeyCode::Backspace => {
+            filter.query.pop();
+            if filter.query.is_empty() || !state.select_first_containing(&filter.query) {
+                state.current_index = filter.previous_index;
+            }
+        }
+        KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            filter.query.push(c);
+            if !state.select_first_containing(&filter.query) {
+                state.status_message = Some(format!("no component contains '{}'", filter.query));
+                filter.query.pop();
+            }
+        }
+        _ => {}
+    }
+    state.input_mode = InputMode::Filter(filter);
+}
+
+/// Processes key events while a fuzzy jump is being typed.
+///
+/// Like `handle_filter_keys`, but each character moves the selection to the
+/// shallowest component containing the query as a subsequence (see
+/// `AppState::fuzzy_find`). `Enter` accepts the selection and `Esc` restores the
+/// one from before.
+fn handle_fuzzy_keys(key: KeyEvent, state: &mut AppState, mut fuzzy: FilterQuery) {
+    match key.code {
+        KeyCode::Enter => return,
+        KeyCode::Esc => {
+            state.current_index = fuzzy.previous_index;
+            return;
+        }
+        KeyCode::Backspace => {
+            fuzzy.query.pop();
+            let found = state.fuzzy_find(&fuzzy.query).filter(|_| !fuzzy.query.is_empty());
+            state.current_index = found.unwrap_or(fuzzy.previous_index);
+        }
+        KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            fuzzy.query.push(c);
+            match state.fuzzy_find(&fuzzy.query) {
+                Some(index) => state.current_index = index,
+                None => {
+                    state.status_message = Some(format!("no component matches '{}'", fuzzy.query));
+                    fuzzy.query.pop();
+                }
+            }
+        }
+        _ => {}
+    }
+    state.input_mode = InputMode::Fuzzy(fuzzy);
+}
+
+/// Processes key events while a command is being typed.
+///
+/// Printable characters are appended to the command, `Backspace` deletes the last
+/// one (leaving command mode once the line is empty), `Tab` completes the path
+/// argument, `Enter` runs the command, and `Esc` cancels it.
+fn handle_command_keys(key: KeyEvent, state: &mut AppState, mut line: CommandLine) {
+    if key.code != KeyCode::Tab {
+        line.completion = None;
+    }
+
+    match key.code {
+        KeyCode::Enter => return state.run_command(&line.input),
+        KeyCode::Esc => return,
+        KeyCode::Backspace if line.input.pop().is_none() => return,
+        KeyCode::Tab => line.complete(&state.selected_path()),
+        KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            line.input.push(c);
+        }
+        _ => {}
+    }
+    state.input_mode = InputMode::Command(line);
+}
+
+/// [Unix-only] Handles the suspend signal (Ctrl+Z).
+///
+/// It first restores the terminal mode, then sends the `SIGTSTP` signal to itself
+/// to suspend the process. When the process is resumed (e.g., with `fg`),
+/// it re-enables raw mode.
+#[cfg(unix)]
+fn handle_suspend() -> Result<()> {
+    let _ = restore_terminal_mode();
+    signal::raise(Signal::SIGTSTP).expect("Failed to send SIGTSTP");
+    // ... The process is suspended here ...
+    // Code execution resumes here after the process is brought to the foreground.
+    set_terminal_mode()
+}
+
+/// [Unix-only] Returns true if Ctrl+Z suspend has been disabled with `PD_NO_SUSPEND=1`.
+///
+/// Some environments (e.g. certain IDE terminals) don't restore raw mode properly
+/// on resume, so this provides a way to make Ctrl+Z a no-op instead.
+#[cfg(unix)]
+fn suspend_disabled() -> bool {
+    env::var_os("PD_NO_SUSPEND").is_some_and(|value| value == "1")
+}
+
+/// [Unix-only] Handles the interrupt signal (Ctrl+C).
+///
+/// It restores the terminal and then re-raises the `SIGINT` signal to allow
+/// the process to terminate gracefully as if it received the signal directly.
+#[cfg(unix)]
+fn handle_interrupt() {
+    let _ = restore_terminal_mode();
+
+    signal::raise(Signal::SIGINT).expect("Failed to send SIGINT");
+    // `raise(SIGINT)` will terminate the process, so this is unreachable.
+    unreachable!();
+}
+
+/// Processes key events when the application is in the `Normal` input mode.
+///
+/// This function acts as the standard input handler. It first delegates the key
+/// event to the active keymap-specific handler (`handle_vim_keys` or
+/// `handle_emacs_keys`), which may perform an action or transition the application
+/// into the `WaitForNextKey` mode. Afterwards, it processes a set of shared
+/// keybindings (like arrow keys, Enter, Esc) that behave consistently across all keymaps.
+///
+/// # Arguments
+/// * `key`: The keyboard event to process.
+/// * `state`: Mutable reference to the current application state, including the
+///   active `keymap`.
+///
+/// # Returns
+/// * `Result<EventAction>`: The resulting action to be taken by the main event loop.
+fn handle_normal_inputmode(key: KeyEvent, state: &mut AppState) -> Result<EventAction> {
+    const CTRL: KeyModifiers = KeyModifiers::CONTROL;
+
+    // The confirm key is checked first so that it can be bound to any key.
+    if state.confirm_key.matches(&key) {
+        return Ok(state.confirm_or_drill(false));
+    }
+    if state.drill_down
+        && key.code == state.confirm_key.code
+        && key.modifiers == state.confirm_key.modifiers | KeyModifiers::ALT
+    {
+        return Ok(state.confirm_or_drill(true));
+    }
+    if state.quit_key.is_some_and(|quit_key| quit_key.matches(&key)) {
+        return Ok(EventAction::Quit);
+    }
+    // Custom bindings take precedence over the keymap.
+    let custom = state.custom_bindings.iter().find(|(binding, _)| binding.matches(&key));
+    if let Some(&(_, action)) = custom {
+        return Ok(run_action(action, state));
+    }
+
+    if state.format_key.matches(&key) {
+        state.cycle_output_format();
+        return Ok(EventAction::Continue);
+    }
+
+    // F1 switches keymaps, so it must work the same in all of them.
+    if key.code == KeyCode::F(1) {
+        state.cycle_keymap();
+        return Ok(EventAction::Continue);
+    }
+    if !state.tabs.is_empty() && matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+        state.switch_tab(key.code == KeyCode::Tab);
+        return Ok(EventAction::Continue);
+    }
+
+    // Browsing ends the event loop, which the keymap handlers cannot signal.
+    let browse = key.code == KeyCode::Char('o')
+        && match state.keymap {
+            Keymap::Vim => key.modifiers.is_empty(),
+            Keymap::Emacs => key.modifiers.contains(KeyModifiers::ALT),
+            Keymap::None | Keymap::Standard => false,
+        };
+    if browse {
+        return Ok(EventAction::Browse(state.selected_path()));
+    }
+
+    let keymap = state.keymap;
+    match keymap {
+        Keymap::Vim => handle_vim_keys(key, state),
+        Keymap::Emacs => handle_emacs_keys(key, state),
+        // Typed letters must not fall through to shared keys such as `q`.
+        Keymap::None if handle_narrow_keys(key, state) => return Ok(EventAction::Continue),
+        Keymap::None => {}
+        Keymap::Standard if handle_standard_keys(key, state) => {
+            return Ok(EventAction::Continue);
+        }
+        Keymap::Standard => {}
+    }
+
+    match key.code {
+        // Shared Keys
+        KeyCode::Left => state.move_by(-state.step, state.wrap.arrows),
+        KeyCode::Right => state.move_by(state.step, state.wrap.arrows),
+        KeyCode::Home => state.move_to_start(),
+        KeyCode::End => state.move_to_end(),
+        KeyCode::Down => state.open_child_list(),
+        // With several tabs, Tab switches between them instead (see above).
+        KeyCode::Tab => state.zoom_in(),
+        KeyCode::Char('l') if key.modifiers.contains(CTRL) => state.reload(),
+        KeyCode::Char('o') if key.modifiers.contains(CTRL) => state.jump_back(),
+        // Esc first discards a count being typed, and only quits once there is none.
+        KeyCode::Esc if !state.count_input.is_empty() => state.count_input.clear(),
+        KeyCode::Esc if state.anchor.is_some() => state.anchor = None,
+        KeyCode::Esc if !state.narrow.is_empty() => state.narrow.clear(),
+        KeyCode::Char('q') | KeyCode::Esc if state.quit_key.is_none() => {
+            // If waiting for a jump char, Esc should just cancel the wait.
+            // Our logic above handles this by doing nothing in the closure,
+            // so this only triggers in Normal mode.
+            return Ok(EventAction::Quit);
+        }
+        KeyCode::Char('c') if key.modifiers.contains(CTRL) => {
+            // On Unix, emulate a true Ctrl+C interrupt.
+            #[cfg(unix)]
+            handle_interrupt();
+
+            // On Windows, treat Ctrl+C as a "quit" action.
+            #[cfg(not(unix))]
+            return Ok(EventAction::Quit);
+        }
+        #[cfg(unix)]
+        KeyCode::Char('z') if key.modifiers.contains(CTRL) && !suspend_disabled() => {
+            // Ctrl+Z suspend is a Unix-only feature, and can be disabled.
+            let _ = handle_suspend();
+        }
+        _ => {}
+    }
+
+    Ok(EventAction::Continue)
+}
+
+/// Serves as the primary dispatcher for all keyboard events.
+///
+/// This function implements a state machine based on `state.input_mode`. It
+/// determines whether to execute a pending multi-key action or to process the
+/// key event through the normal input handler.
+///
+/// - If the mode is `InputMode::WaitForNextKey`, this function executes the
+///   stored closure with the current `key`. The key is considered "consumed,"
+///   and the mode is reset to `Normal`. `Enter` and `Esc` are never forwarded to
+///   the closure: they cancel the pending sequence, and in particular `Enter`
+///   does *not* confirm the selection. A second `Enter` is required for that.
+///   `Esc` leaves a status message confirming the cancellation.
+/// - If the mode is `InputMode::Normal`, it delegates the key event to
+///   `handle_normal_inputmode` for standard processing.
+///
+/// This design centralizes state management, allowing any keymap to implement
+/// multi-key sequences without needing its own internal state machine.
+///
+/// # Arguments
+/// * `key`: The keyboard event to process.
+/// * `state`: Mutable reference to the current application state.
+///
+/// # Returns
+/// * `Result<EventAction>`: Indicates the action to take (`Continue`, `Confirm`, or `Quit`).
+pub fn handle_key_event(key: KeyEvent, state: &mut AppState) -> Result<EventAction> {
+    // Repeats are reported separately when the kitty keyboard protocol is active.
+    if let KeyEventKind::Press | KeyEventKind::Repeat = key.kind {
+        // Status messages are only shown until the next key press, and so are
+        // the matches of a jump unless the key repeats it.
+        state.status_message = None;
+        state.jump_highlight = None;
+
+        if let Some((_, keys)) = &mut state.recording {
+            keys.push(key);
+        }
+
+        // Take ownership of the current input mode, replacing it with Normal.
+        // This ensures that the state is always reset after a pending action.
+        let current_mode = std::mem::replace(&mut state.input_mode, InputMode::Normal);
+
+        // If we were waiting for another key, execute the stored action.
+        match current_mode {
+            InputMode::WaitForNextKey(prompt, action) => {
+                // Enter and Esc only cancel the pending sequence.
+                match key.code {
+                    KeyCode::Esc => {
+                        let message = match prompt {
+                            "f_" | "F_" | "t_" | "T_" | "C-]_" => "jump cancelled",
+                            _ => "cancelled",
+                        };
+                        state.status_message = Some(message.to_string());
+                    }
+                    KeyCode::Enter => {}
+                    _ => action(key, state),
+                }
+                if let Some(path) = state.pending_confirm.take() {
+                    return Ok(EventAction::Confirm(path));
+                }
+                // The key has been consumed by the pending action, so we stop further processing.
+                return Ok(EventAction::Continue);
+            }
+            InputMode::Descend(list) => {
+                return Ok(handle_descend_keys(key, state, list));
+            }
+            InputMode::Command(line) => {
+                handle_command_keys(key, state, line);
+                return Ok(EventAction::Continue);
+            }
+            InputMode::Filter(filter) => {
+                handle_filter_keys(key, state, filter);
+                return Ok(EventAction::Continue);
+            }
+            InputMode::Fuzzy(fuzzy) => {
+                handle_fuzzy_keys(key, state, fuzzy);
+                return Ok(EventAction::Continue);
+            }
+            InputMode::Normal => {
+                // If there was no pending action, process the key using the keymap.
+                return handle_normal_inputmode(key, state);
+            }
+        }
+    }
+
+    Ok(EventAction::Continue)
+}
+
+/// Handles mouse events and updates the application state.
+///
+/// This function processes mouse movements, clicks, and scroll events to navigate or select
+/// path components.
+///
+/// # Arguments
+/// * `mouse`: The mouse event to process.
+/// * `state`: Mutable reference to the current application state.
+///
+/// # Returns
+/// * `Result<EventAction>`: Indicates the action to take (`Continue`, `Confirm`, or `Quit`).
+fn handle_mouse_event(mouse: MouseEvent, state: &mut AppState) -> Result<EventAction> {
+    match mouse.kind {
+        MouseEventKind::Moved => {
+            state.hover_at(mouse.column, mouse.row, Instant::now());
+        }
+        // Clicks act on the component under the mouse, even if a hover is still pending.
+        // A click only selects; a double click confirms. Dragging between the press
+        // and the release selects a range (see `AppState::drag_start`).
+        MouseEventKind::Down(MouseButton::Left) => {
+            let now = Instant::now();
+            state.pending_hover = None;
+            state.select_part_at(mouse.column, mouse.row);
+            if let Some((time, index)) = state.last_click.take()
+                && index == state.current_index
+                && now.duration_since(time) <= DOUBLE_CLICK_TIME
+            {
+                state.drag_start = None;
+                return Ok(state.confirm());
+            }
+            state.last_click = Some((now, state.current_index));
+            state.drag_start = Some(state.current_index);
+        }
+        MouseEventKind::Drag(MouseButton::Left) => {
+            if let Some(start) = state.drag_start {
+                state.select_part_at(mouse.column, mouse.row);
+                state.anchor = (state.current_index != start).then_some(start);
+            }
+        }
+        // Releasing after a drag confirms the range it selected.
+        MouseEventKind::Up(MouseButton::Left) => {
+            let dragged = state.drag_start.take().is_some() && state.anchor.is_some();
+            if dragged {
+                state.select_part_at(mouse.column, mouse.row);
+                return Ok(state.confirm());
+            }
+        }
+        #[cfg(windows)]
+        MouseEventKind::Up(MouseButton::Right) => {
+            return Ok(EventAction::Quit);
+        }
+        // FIXME: 'Up' event is unexpectedly reserved in WIN32 after exit.
+        #[cfg(unix)]
+        MouseEventKind::Down(MouseButton::Right) => {
+            return Ok(EventAction::Quit);
+        }
+        MouseEventKind::ScrollUp
+        | MouseEventKind::ScrollLeft
+        | MouseEventKind::ScrollDown
+        | MouseEventKind::ScrollRight => {
+            // Events in quick succession mean the wheel is being spun.
+            let now = Instant::now();
+            let spinning = state
+                .last_scroll
+                .replace(now)
+                .is_some_and(|last| now.duration_since(last) < SCROLL_MOMENTUM_TIME);
+            let step = if spinning {
+                state.scroll_step.saturating_mul(2)
+            } else {
+                state.scroll_step
+            };
+            let up = matches!(mouse.kind, MouseEventKind::ScrollUp | MouseEventKind::ScrollLeft);
+            state.move_by(if up { -step } else { step }, state.wrap.scroll);
+        }
+        _ => {}
+    }
+
+    Ok(EventAction::Continue)
+}
+
+/// Handles events (keyboard or mouse) and updates the application state accordingly.
+///
+/// This function processes a single event, delegates to specific handlers based on the event type,
+/// and returns an action indicating whether to continue, confirm a path, or quit.
+///
+/// # Arguments
+/// * `event`: The input event (key press or mouse action) to process.
+/// * `state`: Mutable reference to the current application state.
+///
+/// # Returns
+/// * `Result<EventAction>`: Indicates the action to take (`Continue`, `Confirm`, or `Quit`).
+fn handle_event(event: Event, state: &mut AppState) -> Result<EventAction> {
+    match event {
+        Event::Key(key) => {
+            // Only moves within the same path count; a new path or another tab
+            // starts a history of its own.
+            let (tab, parts, index) = (state.tab, state.path_parts.clone(), state.current_index);
+            let action = handle_key_event(key, state);
+            if state.tab == tab && state.path_parts == parts {
+                state.record_history(index);
+            }
+            // Every key ends a pending sequence, so one that is pending now has
+            // just been started.
+            state.pending_since = matches!(state.input_mode, InputMode::WaitForNextKey(..))
+                .then(Instant::now);
+            return action;
+        }
+        Event::Mouse(mouse) => return handle_mouse_event(mouse, state),
+        // The next frame is drawn for the new width (see `draw_frame`).
+        Event::Resize(..) => state.resized = true,
+        _ => {}
+    }
+
+    Ok(EventAction::Continue)
+}
+
+/// A source of terminal events for the event loop.
+///
+/// The real terminal is read through `TerminalEvents`; other implementations can
+/// feed the loop a scripted sequence of events instead.
+trait EventSource {
+    /// Blocks until the next event is available and returns it.
+    fn read(&mut self) -> Result<Event>;
+
+    /// Waits up to `timeout` for an event, returning whether one is available.
+    fn poll(&mut self, timeout: Duration) -> Result<bool>;
+}
+
+/// Reads events from the terminal via crossterm.
+struct TerminalEvents;
+
+impl EventSource for TerminalEvents {
+    fn read(&mut self) -> Result<Event> {
+        event::read()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> Result<bool> {
+        event::poll(timeout)
+    }
+}
+
+/// Creates the state for navigating `start`, with every setting read from the
+/// environment and `options`.
+fn configured_state(start: &Path, separator: Option<char>, options: &Options) -> Result<AppState> {
+    let path_parts = split_path(start, separator);
+    if options.require_utf8 {
+        ensure_utf8(&path_parts)?;
+    }
+    let mut state = AppState::new(path_parts, get_initial_position());
+    state.keymap = get_keymap();
+    state.size_annotation = get_size_annotation();
+    state.filter = options.filter.clone();
+    state.highlight_mode = get_highlight_mode();
+    state.selection_style = get_selection_style();
+    state.no_color = env::var_os("NO_COLOR").is_some();
+    state.mount_points = read_mount_points();
+    state.show_mounts = env::var_os("PD_SHOW_MOUNTS").is_some_and(|value| value == "1");
+    state.hover_delay = get_hover_delay();
+    state.hover_style = get_hover_style();
+    state.numbered = env::var_os("PD_NUMBERED").is_some_and(|value| value == "1");
+    state.cwd = start.to_path_buf();
+    state.allowed_roots = get_allowed_roots();
+    state.confirm_key = get_confirm_key();
+    state.quit_key = get_quit_key();
+    state.format_key = get_format_key();
+    state.normalize_unicode = get_normalize_unicode();
+    state.smartcase = env::var_os("PD_SMARTCASE").is_some_and(|value| value == "1");
+    state.jump_case = get_jump_case(state.smartcase);
+    state.show_symlinks = env::var_os("PD_SHOW_SYMLINKS").is_some_and(|value| value == "1");
+    state.refresh_symlinks();
+    state.colorize_types = separator.is_none()
+        && env::var_os("PD_COLORIZE_TYPES").is_some_and(|value| value == "1");
+    state.refresh_part_types();
+    // Custom separators split text that is not on the filesystem.
+    state.show_child_counts = separator.is_none()
+        && env::var_os("PD_CHILD_COUNTS").is_some_and(|value| value == "1");
+    state.refresh_child_counts();
+    state.skip_root = env::var_os("PD_SKIP_ROOT").is_some_and(|value| value == "1");
+    state.update_min_index();
+    state.focus_root = get_focus_root(start);
+    state.update_focus_index();
+    state.keep_breadcrumb = env::var_os("PD_KEEP_BREADCRUMB").is_some_and(|value| value == "1");
+    state.show_caret = env::var_os("PD_CARET").is_some_and(|value| value == "1");
+    state.show_path_line = env::var_os("PD_PATH_LINE").is_some_and(|value| value == "1");
+    state.custom_bindings = get_custom_bindings();
+    state.show_dotdots = env::var_os("PD_SHOW_DOTDOTS").is_some_and(|value| value == "1");
+    state.reverse = env::var_os("PD_REVERSE").is_some_and(|value| value == "1");
+    state.bold_last = env::var_os("PD_BOLD_LAST").is_some_and(|value| value == "1");
+    state.status_layout = get_status_layout();
+    state.layout = get_layout();
+    state.separator = separator;
+    state.tilde = env::var_os("PD_TILDE").is_some_and(|value| value == "1");
+    state.update_home_index();
+    state.preview_dir = env::var_os("PD_PREVIEW_DIR").is_some_and(|value| value == "1");
+    state.preview_pane = env::var_os("PD_PREVIEW").is_some_and(|value| value == "1");
+    state.step = get_step();
+    state.scroll_step = get_scroll_step(state.step);
+    state.cell_width = get_tabular_width();
+    state.selected_min_width = get_selected_min_width();
+    state.digit_mode = get_digit_mode();
+    state.abbreviate = get_abbreviate();
+    state.drill_down = env::var_os("PD_DRILL_DOWN").is_some_and(|value| value == "1");
+    state.filemanager = env::var("PD_FILEMANAGER").ok().filter(|value| !value.trim().is_empty());
+    state.wrap = get_wrap_settings();
+    state.batch_render = env::var_os("PD_BATCH_RENDER").is_some_and(|value| value == "1");
+    state.git_status = env::var_os("PD_GIT_STATUS").is_some_and(|value| value == "1");
+    state.range_output = get_range_output();
+    state.initial_path = state.selected_path();
+    Ok(state)
+}
+
+/// Runs the main interactive event loop.
+///
+/// This function sets up the environment, listens for user input (keyboard and mouse),
+/// updates the application state, and re-renders the UI. The loop itself is in
+/// `event_loop`.
+///
+/// The first of `starts` (of which there must be at least one) is shown, while
+/// the others wait in tabs.
+///
+/// # Returns
+/// - `Ok(Some(Selection))`: If the user selects a path and presses Enter.
+/// - `Ok(None)`: If the user quits with `q` or `Esc`.
+/// - `Err(e)`: If an I/O error occurs during the process.
+fn run_interactive_selector(
+    starts: &[PathBuf],
+    separator: Option<char>,
+    options: &Options,
+) -> Result<Option<Selection>> {
+    let start = &starts[0];
+    let mut state = configured_state(start, separator, options)?;
+    if options.pick {
+        let items = read_pick_items(&mut std::io::stdin())?;
+        if items.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--pick: no paths on stdin",
+            ));
+        }
+        state.pick = true;
+        state.drill_down = false;
+        state.set_path_parts(items, 0);
+        state.initial_path = state.selected_path();
+    } else if starts.len() == 1 && state.path_parts.len() <= 1 {
+        match get_single_part() {
+            SinglePart::Interactive => {}
+            SinglePart::Confirm => {
+                return Ok(Some(Selection {
+                    path: start.to_path_buf(),
+                    depth: 0,
+                    range: Vec::new(),
+                    format: OutputFormat::Absolute,
+                    tab: 0,
+                    unchanged: true,
+                }));
+            }
+            SinglePart::Quit => return Ok(None),
+        }
+    }
+    for (tab, tab_start) in starts.iter().enumerate().skip(1) {
+        let mut tab_state = configured_state(tab_start, separator, options)?;
+        tab_state.tab = tab;
+        state.tabs.push_back(tab_state);
+    }
+    let live_feed = options.live_fd.map(LiveFeed::open).transpose()?;
+    if let Some(device) = env::var_os("PD_TERM_OUT") {
+        open_term_device(Path::new(&device))?;
+    } else if !stderr().is_terminal() {
+        return no_tty_selection(get_no_tty_fallback(), &mut state, start);
+    }
+    if let Some(file) = bookmarks_file() {
+        state.bookmarks = load_bookmarks(&file).unwrap_or_else(|e| {
+            eprintln!("Warning: Failed to read the bookmarks from {}: {}", file.display(), e);
+            HashMap::new()
+        });
+    }
+    if let Some(file) = &options.save_macro {
+        state.registers = load_registers(file).unwrap_or_else(|e| {
+            eprintln!("Warning: Failed to read the macros from {}: {}", file.display(), e);
+            HashMap::new()
+        });
+    }
+    // `cleanup` ensures the terminal is restored when this function returns.
+    let cleanup = TermCleanup;
+    set_terminal_mode()?;
+    if state.layout == Layout::Wrap {
+        state.frame_row = query_cursor_row();
+    }
+
+    let result = event_loop(&mut state, &mut TerminalEvents, &mut term_out(), live_feed);
+    // Restore the terminal first, so that a warning is printed normally.
+    drop(cleanup);
+    if let Some(file) = &options.save_macro
+        && let Err(e) = save_registers(file, &state.registers)
+    {
+        eprintln!("Warning: Failed to save the macros to {}: {}", file.display(), e);
+    }
+    result
+}
+
+/// Reads the items for `--pick` from `input`: NUL-separated if there is any NUL
+/// (as from `find -print0`), and one per line otherwise. Empty items are skipped.
+fn read_pick_items(input: &mut impl std::io::Read) -> Result<Vec<OsString>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let delimiter = if bytes.contains(&0) { 0 } else { b'\n' };
+    let items = bytes
+        .split(|&byte| byte == delimiter)
+        .map(|item| item.strip_suffix(b"\r").filter(|_| delimiter == b'\n').unwrap_or(item))
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStringExt as _;
+                OsString::from_vec(item.to_vec())
+            }
+            #[cfg(not(unix))]
+            OsString::from(String::from_utf8_lossy(item).into_owned())
+        })
+        .collect();
+    Ok(items)
+}
+
+/// Reads the macros saved by `save_registers`. A missing file has none.
+///
+/// Each line holds a register letter, a tab, and the keys of its macro as key
+/// specs (as for `PD_KEY_CONFIRM`) separated by spaces. Lines that cannot be
+/// read are skipped.
+fn load_registers(file: &Path) -> Result<HashMap<char, Vec<KeyEvent>>> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut registers = HashMap::new();
+    for line in contents.lines() {
+        let Some((register, keys)) = line.split_once('\t') else {
+            continue;
+        };
+        let mut letters = register.chars();
+        let (Some(register), None) = (letters.next(), letters.next()) else {
+            continue;
+        };
+        let keys: Option<Vec<KeyEvent>> = keys
+            .split_whitespace()
+            .map(|spec| KeyBinding::parse(spec).map(|key| KeyEvent::new(key.code, key.modifiers)))
+            .collect();
+        if let Some(keys) = keys {
+            registers.insert(register, keys);
+        }
+    }
+    Ok(registers)
+}
+
+/// Writes `registers` to `file` in the format read by `load_registers`, sorted
+/// by register. Keys that have no key spec are left out.
+fn save_registers(file: &Path, registers: &HashMap<char, Vec<KeyEvent>>) -> Result<()> {
+    let mut sorted: Vec<_> = registers.iter().collect();
+    sorted.sort_by_key(|&(register, _)| *register);
+    let mut contents = String::new();
+    for (register, keys) in sorted {
+        let specs: Vec<String> = keys
+            .iter()
+            .filter_map(|key| {
+                let modifiers = match key.code {
+                    KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+                    _ => key.modifiers,
+                };
+                let binding = KeyBinding { code: key.code, modifiers };
+                let spec = binding.spec();
+                (KeyBinding::parse(&spec) == Some(binding)).then_some(spec)
+            })
+            .collect();
+        contents.push_str(&format!("{}\t{}\n", register, specs.join(" ")));
+    }
+    write_atomically(file, contents.as_bytes())
+}
+
+/// Returns the file that bookmarks are kept in: `$XDG_DATA_HOME/pd/bookmarks`,
+/// or `~/.local/share/pd/bookmarks` without it.
+fn bookmarks_file() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".local/share")))?;
+    Some(data_home.join("pd").join("bookmarks"))
+}
+
+/// Reads the bookmarks saved by `save_bookmarks`. A missing file has none.
+///
+/// Each line holds a bookmark letter, a tab, and the path. Bookmarks of
+/// directories that no longer exist are left out with a warning, and lines
+/// that cannot be read are skipped.
+fn load_bookmarks(file: &Path) -> Result<HashMap<char, PathBuf>> {
+    let contents = match fs::read(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut bookmarks = HashMap::new();
+    for line in contents.split(|&b| b == b'\n') {
+        let Some((&letter, path)) = line.split_first() else {
+            continue;
+        };
+        let Some(path) = path.strip_prefix(b"\t") else {
+            continue;
+        };
+        if !letter.is_ascii_uppercase() {
+            continue;
+        }
+        let path = PathBuf::from(bytes_to_os_string(path));
+        if path.is_dir() {
+            bookmarks.insert(char::from(letter), path);
+        } else {
+            eprintln!(
+                "Warning: Skipping bookmark '{}', {} is not a directory",
+                char::from(letter),
+                path.display()
+            );
+        }
+    }
+    Ok(bookmarks)
+}
+
+/// Writes `bookmarks` to `file` in the format read by `load_bookmarks`, sorted
+/// by letter, creating the directory it is in if needed.
+fn save_bookmarks(file: &Path, bookmarks: &HashMap<char, PathBuf>) -> Result<()> {
+    let mut sorted: Vec<_> = bookmarks.iter().collect();
+    sorted.sort_by_key(|&(letter, _)| *letter);
+    let mut contents = Vec::new();
+    for (letter, path) in sorted {
+        contents.extend_from_slice(format!("{}\t", letter).as_bytes());
+        contents.extend_from_slice(path.as_os_str().as_encoded_bytes());
+        contents.push(b'\n');
+    }
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomically(file, &contents)
+}
+
+/// Draws the current state to `out`, after the per-frame bookkeeping: starting
+/// any directory reads it needs, and reporting the selection to the live feed.
+fn draw_frame<W: Write>(
+    state: &mut AppState,
+    out: &mut W,
+    live_feed: &mut Option<LiveFeed>,
+) -> Result<()> {
+    state.prefetch_children();
+    state.schedule_preview(Instant::now());
+    // Mouse clicks until the next frame are mapped onto what this one draws.
+    state.update_viewport(terminal_width());
+    if let Some(feed) = live_feed
+        && feed.emit(&state.selected_path()).is_err()
+    {
+        // The reader has gone away; keep running without the feed.
+        *live_feed = None;
+    }
+    let rows = if state.batch_render {
+        // The frame is the same bytes either way, but arrives in one write,
+        // so the terminal never shows it half drawn.
+        let mut frame = Vec::new();
+        let mut target = RenderTarget::inline(&mut frame);
+        render(&mut target, state)?;
+        let rows = target.row + 1;
+        out.write_all(&frame)?;
+        out.flush()?;
+        rows
+    } else {
+        let mut target = RenderTarget::inline(out);
+        render(&mut target, state)?;
+        target.row + 1
+    };
+    // A frame that runs past the bottom of the screen scrolls everything up.
+    if let Some(row) = state.frame_row
+        && let Ok((_, height)) = terminal::size()
+    {
+        state.frame_row = Some(row.min(height.saturating_sub(rows)));
+    }
+    state.clear_screen = false;
+    state.resized = false;
+    Ok(())
+}
+
+/// Renders `state` to `out` and applies events from `events` until the user
+/// confirms or quits. The terminal is expected to be set up already.
+///
+/// At most `MAX_EVENTS_PER_FRAME` events that arrive together are applied
+/// before the next frame is drawn, and under such a flood, frames are drawn at
+/// most every `MIN_FRAME_INTERVAL`.
+fn event_loop<E: EventSource, W: Write>(
+    state: &mut AppState,
+    events: &mut E,
+    out: &mut W,
+    mut live_feed: Option<LiveFeed>,
+) -> Result<Option<Selection>> {
+    // The number of macro keys replayed since the last real event.
+    let mut replayed = 0;
+    // The events handled since the last frame, and when that frame was drawn.
+    let mut batched = 0;
+    let mut last_frame = Instant::now();
+    loop {
+        // Events that are already waiting (e.g. from a paste) are handled before
+        // drawing again, so that a flood of them costs one frame per batch.
+        let waiting = !state.replay.is_empty() || events.poll(Duration::ZERO)?;
+        if waiting && batched > 0 && batched < MAX_EVENTS_PER_FRAME {
+            batched += 1;
+        } else {
+            if waiting && batched > 0 {
+                std::thread::sleep(MIN_FRAME_INTERVAL.saturating_sub(last_frame.elapsed()));
+            }
+            draw_frame(state, out, &mut live_feed)?;
+            last_frame = Instant::now();
+            batched = 1;
+        }
+        let event = if let Some(key) = state.replay.pop_front() {
+            replayed += 1;
+            if replayed > MAX_REPLAYED_KEYS {
+                // A macro that replays itself would otherwise never end.
+                state.replay.clear();
+                state.status_message = Some("macro stopped: too many keys".to_string());
+                continue;
+            }
+            Event::Key(key)
+        } else {
+            replayed = 0;
+            // Wait for the next event, but wake up for any time-based state change.
+            if let Some(deadline) = state.next_deadline()
+                && !events.poll(deadline.saturating_duration_since(Instant::now()))?
+            {
+                state.tick(Instant::now());
+                continue;
+            }
+            events.read()?
+        };
+        match handle_event(event, state)? {
+            EventAction::Continue => {}
+            EventAction::Confirm(path) if !state.is_path_allowed(&path) => {
+                state.status_message =
+                    Some("refusing a path outside the allowed roots".to_string());
+            }
+            EventAction::Confirm(path) => {
+                if state.keep_breadcrumb {
+                    let _ = FINAL_LINE.set(state.plain_breadcrumb());
+                }
+                let depth = state.depth();
+                let range = state.range_paths();
+                let (format, tab) = (state.output_format, state.tab);
+                let unchanged = range.is_empty() && path == state.initial_path;
+                return Ok(Some(Selection { path, depth, range, format, tab, unchanged }));
+            }
+            EventAction::Browse(path) if !state.is_path_allowed(&path) => {
+                state.status_message =
+                    Some("refusing a path outside the allowed roots".to_string());
+            }
+            EventAction::Browse(path) => {
+                let manager = state.filemanager.clone().unwrap_or_default();
+                let Some(command) = filemanager_command(&manager, &path) else {
+                    state.status_message = Some("PD_FILEMANAGER is not set".to_string());
+                    continue;
+                };
+                execute!(out, cursor::MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+                restore_terminal_mode()?;
+                match run_filemanager(command) {
+                    // `pd` is done once the file manager is; nothing is printed.
+                    Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+                    Err(e) => {
+                        set_terminal_mode()?;
+                        state.status_message = Some(format!("cannot run '{}': {}", manager, e));
+                    }
+                }
+            }
+            EventAction::Quit => {
+                // Wipe the breadcrumb line explicitly before the generic cleanup. On
+                // terminals without auto-wrap, the cursor can be left parked in the last
+                // column after a long line, and clearing only from the cursor down would
+                // then leave the start of the line behind on screen.
+                execute!(out, cursor::MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Writes `lines` to `out` (see `output_writer`), one per line, and flushes it.
+///
+/// On Unix the bytes are written exactly and the last line is followed by a
+/// newline unless `no_newline` is set. On other platforms (like Windows), the
+/// lossy strings are printed, without a final newline.
+fn print_lines(out: &mut dyn Write, lines: &[OsString], no_newline: bool) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt as _;
+        for (i, line) in lines.iter().enumerate() {
+            out.write_all(line.as_os_str().as_bytes())?;
+            if i + 1 < lines.len() || !no_newline {
+                out.write_all(b"\n")?;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = no_newline;
+        let lines: Vec<_> = lines.iter().map(|line| line.to_string_lossy()).collect();
+        write!(out, "{}", lines.join("\n"))?;
+    }
+
+    // Make sure the path has been delivered (e.g. to a capturing shell)
+    // rather than relying on the buffer being flushed at exit.
+    out.flush()
+}
+
+/// Writes `fields` to `out`, each followed by a NUL, and flushes it.
+///
+/// Unlike `print_lines`, this is the same on every platform, since no field can
+/// contain a NUL: the bytes on Unix, and the lossy strings elsewhere.
+fn print_fields(out: &mut dyn Write, fields: &[OsString]) -> Result<()> {
+    for field in fields {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt as _;
+            out.write_all(field.as_bytes())?;
+        }
+        #[cfg(not(unix))]
+        write!(out, "{}", field.to_string_lossy())?;
+        out.write_all(b"\0")?;
+    }
+    out.flush()
+}
+
+/// Prints the confirmed `lines` to `out` in the form the options ask for.
+///
+/// With `--oldpwd` only the first line is printed, followed by `cwd`, since
+/// for `cd -` the wrapper also needs the directory it is leaving.
+fn print_output(
+    out: &mut dyn Write,
+    lines: &[OsString],
+    cwd: &Path,
+    options: &Options,
+) -> Result<()> {
+    if options.oldpwd {
+        print_fields(out, &[lines[0].clone(), cwd.as_os_str().to_owned()])
+    } else if options.print0 {
+        print_fields(out, lines)
+    } else {
+        print_lines(out, lines, options.no_newline)
+    }
+}
+
+/// Returns where the confirmed path is printed: file descriptor `fd` for
+/// `--output-fd`, which the parent process must have set up, or stdout.
+///
+/// Like `--live-fd`, this relies on `/dev/fd`. Elsewhere `fd` is ignored with a
+/// warning.
+fn output_writer(fd: Option<u32>) -> Result<Box<dyn Write>> {
+    let Some(fd) = fd else {
+        return Ok(Box::new(std::io::stdout()));
+    };
+    if cfg!(not(unix)) {
+        eprintln!("Warning: --output-fd is not supported on this platform, using stdout");
+        return Ok(Box::new(std::io::stdout()));
+    }
+    let file = fs::OpenOptions::new()
+        .append(true)
+        .open(format!("/dev/fd/{}", fd))
+        .map_err(|e| std::io::Error::new(e.kind(), format!("--output-fd {}: {}", fd, e)))?;
+    Ok(Box::new(file))
+}
+
+/// Returns the line that `--verbose` prints once `pd` is done: the confirmed
+/// path and how many levels up it is, e.g. `confirmed /home (2 levels up)`, or
+/// `cancelled` without a selection.
+fn summary_line(selection: Option<(&Path, usize)>) -> String {
+    match selection {
+        None => "cancelled".to_string(),
+        Some((path, 0)) => format!("confirmed {} (current directory)", path.display()),
+        Some((path, 1)) => format!("confirmed {} (1 level up)", path.display()),
+        Some((path, depth)) => format!("confirmed {} ({} levels up)", path.display(), depth),
+    }
+}
+
+/// The `ud` wrapper function printed by `pd init bash` and `pd init zsh`.
+///
+/// Status 1 means `pd` was quit, and 3 that nothing changed (`--exit-unchanged`),
+/// so neither is passed on as a failure.
+const POSIX_INIT: &str = r#"ud() {
+    local dir code
+    dir=$(command pd "$@")
+    code=$?
+    case $code in
+        0) cd -- "$dir" ;;
+        1 | 3) ;;
+        *) return "$code" ;;
+    esac
+}
+"#;
+
+/// The `ud` wrapper function printed by `pd init fish`.
+const FISH_INIT: &str = r#"function ud
+    set -l dir (command pd $argv)
+    set -l code $status
+    switch $code
+        case 0
+            cd -- $dir
+        case 1 3
+        case '*'
+            return $code
+    end
+end
+"#;
+
+/// Returns the wrapper function that `pd init <shell>` prints for `shell`.
+fn shell_init(shell: &OsStr) -> std::result::Result<&'static str, String> {
+    match shell.to_str() {
+        Some("bash" | "zsh") => Ok(POSIX_INIT),
+        Some("fish") => Ok(FISH_INIT),
+        _ => Err(format!(
+            "unknown shell '{}' for init, expected bash, zsh or fish",
+            shell.to_string_lossy()
+        )),
+    }
+}
+
+/// Runs `pd` with the process's arguments and environment, and exits.
+///
+/// This calls the interactive selector and handles its result: on success, the
+/// chosen path is printed to `stdout`; on quit or error, `pd` exits with a
+/// non-zero status code.
+pub fn run() {
+    // `pd init <shell>` prints a wrapper function to eval. A directory named
+    // `init` can still be navigated as `./init`.
+    let mut args = env::args_os().skip(1);
+    if args.next().is_some_and(|arg| arg == "init") {
+        match shell_init(&args.next().unwrap_or_default()) {
+            Ok(script) => print!("{}", script),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+
+    let options = match parse_args(env::args_os().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if options.help {
+        print!("{}", HELP);
+        return;
+    }
+    if options.version {
+        println!("pd {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+
+    if options.dump_keys {
+        let (confirm_key, quit_key) = (get_confirm_key(), get_quit_key());
+        let format_key = get_format_key();
+        let dumped = dump_keys(
+            &mut std::io::stdout(),
+            get_keymap(),
+            &confirm_key,
+            quit_key.as_ref(),
+            &format_key,
+        );
+        if let Err(e) = dumped {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if let Some(step) = options.history {
+        let Some(file) = env::var_os("PD_DIRSTACK_FILE") else {
+            eprintln!("Error: --back and --forward need PD_DIRSTACK_FILE to be set");
+            std::process::exit(2);
+        };
+        match step_history(Path::new(&file), step) {
+            Ok(Some(path)) => {
+                let line = if options.shell_quote {
+                    shell_quote(path.as_os_str())
+                } else {
+                    path.into_os_string()
+                };
+                let printed = output_writer(options.output_fd).and_then(|mut out| {
+                    if options.print0 {
+                        print_fields(&mut out, &[line])
+                    } else {
+                        print_lines(&mut out, &[line], options.no_newline)
+                    }
+                });
+                if let Err(e) = printed {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(2);
+                }
+            }
+            Ok(None) => {
+                eprintln!("No further directory in the history");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: Failed to read PD_DIRSTACK_FILE: {}", e);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+
+    let separator = get_separator();
+    let args = std::iter::once(options.start.as_deref())
+        .chain(options.tabs.iter().map(|arg| Some(arg.as_os_str())));
+    let starts = current_dir_or_ancestor()
+        .and_then(|cwd| args.map(|arg| resolve_start(arg, &cwd, separator)).collect());
+    let starts: Vec<PathBuf> = match starts {
+        Ok(dirs) => dirs,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let starts = if separator.is_none() && get_resolve_symlinks() {
+        starts.into_iter().map(canonicalize_start).collect()
+    } else {
+        starts
+    };
+    let start = starts[0].clone();
+
+    if options.mini {
+        let color = env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+        let abbreviate = separator.is_none() && get_abbreviate();
+        let parts = split_path(&start, separator);
+        if let Err(e) = render_mini(&mut std::io::stdout(), &parts, color, abbreviate) {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if options.json {
+        let rendered = configured_state(&start, separator, &options).and_then(|state| {
+            render_json(&mut std::io::stdout(), &start, &state.path_parts, state.current_index)
+        });
+        if let Err(e) = rendered {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    let verbose = options.verbose && !get_quiet();
+    match run_interactive_selector(&starts, separator, &options) {
+        Ok(Some(Selection { path, depth, range, format, tab, unchanged })) => {
+            // Relative output and `--with-origin` refer to the tab's own start.
+            let start = starts[tab].clone();
+            // A path split on a custom separator is not a place on the filesystem.
+            if separator.is_none()
+                && let Some(recent) = get_recent_places()
+                && let Err(e) = recent.add(&path)
+            {
+                eprintln!("Warning: Failed to add to the recent places: {}", e);
+            }
+
+            let name = split_path(&path, separator)
+                .last()
+                .map_or_else(OsString::new, |part| part_name(part, separator));
+            let path = output_path(path, &start, &options);
+
+            // The terminal has already been restored and cleared at this point,
+            // so the echoed line stays visible.
+            if options.echo {
+                eprintln!("→ {}", path.display());
+            }
+            if options.print_depth {
+                eprintln!("{}", depth);
+            }
+
+            if let Some(file) = env::var_os("PD_DIRSTACK_FILE")
+                && let Err(e) = push_dirstack(Path::new(&file), &path)
+            {
+                eprintln!("Warning: Failed to update PD_DIRSTACK_FILE: {}", e);
+            }
+            if verbose {
+                eprintln!("{}", summary_line(Some((&path, depth))));
+            }
+
+            // The terminal has been restored, so the command can take it over.
+            if let Some(words) = &options.exec {
+                match exec_command(words, &path).status() {
+                    Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+                    Err(e) => {
+                        eprintln!("Error: cannot run '{}': {}", words[0], e);
+                        std::process::exit(2);
+                    }
+                }
+            }
+
+            // A confirmed visual range prints each of its paths, one per line,
+            // unless the output is a single `cd` command or name.
+            let mut paths = if options.name_only {
+                vec![PathBuf::from(name)]
+            } else if range.is_empty() || options.emit_command.is_some() || options.oldpwd {
+                vec![path]
+            } else {
+                range.into_iter().map(|path| output_path(path, &start, &options)).collect()
+            };
+            if options.with_origin {
+                paths.insert(0, start.clone());
+            }
+            let cwd = env::current_dir().unwrap_or_else(|_| start.clone());
+            let home = env::home_dir();
+            let lines: Vec<OsString> = paths
+                .into_iter()
+                .map(|path| {
+                    // Only absolute paths are reformatted; a URI needs one, and a
+                    // bare name or a path already made relative is left alone.
+                    let path = if path.is_absolute() && !options.uri && !options.name_only {
+                        format.apply(path, &cwd, home.as_deref())
+                    } else {
+                        path
+                    };
+                    let path = match options.trailing_slash {
+                        Some(trailing) if !options.name_only => {
+                            set_trailing_separator(path, trailing)
+                        }
+                        _ => path,
+                    };
+                    if options.uri {
+                        OsString::from(file_uri(&path))
+                    } else if let Some(word) = options.emit_command {
+                        shell_command(word, &path)
+                    } else if options.shell_quote {
+                        shell_quote_path(&path)
+                    } else {
+                        path.into_os_string()
+                    }
+                })
+                .collect();
+
+            let printed = output_writer(options.output_fd)
+                .and_then(|mut out| print_output(&mut out, &lines, &cwd, &options));
+            if let Err(e) = printed {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+            if options.exit_unchanged && unchanged {
+                std::process::exit(3);
+            }
+        }
+        Ok(None) => {
+            if verbose {
+                eprintln!("{}", summary_line(None));
+            }
+            // User quit; exit with a non-zero status code.
+            std::process::exit(1);
+        }
+        Err(e) => {
+            // Ensure terminal is restored before printing the error.
+            let _ = restore_terminal_mode();
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(path: &str) -> AppState {
+        AppState::new(split_path(Path::new(path), None), InitialPos::End)
+    }
+
+    /// Presses `code` without modifiers, as the Vim keymap reads it.
+    fn press(state: &mut AppState, code: KeyCode) -> EventAction {
+        let key = KeyEvent::new(code, KeyModifiers::NONE);
+        handle_key_event(key, state).expect("keys are handled")
+    }
+
+    /// Types `keys` one after the other.
+    fn type_keys(state: &mut AppState, keys: &str) {
+        for c in keys.chars() {
+            press(state, KeyCode::Char(c));
+        }
+    }
+
+    /// Renders `state` `width` columns wide and returns the frame, escape
+    /// sequences and all.
+    fn frame(state: &AppState, width: usize) -> String {
+        let mut buffer = Vec::new();
+        let mut target = RenderTarget { writer: &mut buffer, origin: None, width, row: 0 };
+        render(&mut target, state).expect("rendering to a buffer works");
+        String::from_utf8(buffer).expect("the frame is UTF-8")
+    }
+
+    /// Returns what `frame` draws, with the escape sequences left out.
+    fn rendered(state: &AppState, width: usize) -> String {
+        let mut text = String::new();
+        let frame = frame(state, width);
+        let mut chars = frame.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => match chars.next() {
+                    Some('[') => while chars.next().is_some_and(|c| !('@'..='~').contains(&c)) {},
+                    Some(']') => while chars.next().is_some_and(|c| c != '\x07') {},
+                    _ => {}
+                },
+                c => text.push(c),
+            }
+        }
+        text
+    }
+
+    /// Creates an empty directory named after a test in the temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pd-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("the temporary directory can be created");
+        dir
+    }
+
+    #[test]
+    fn arrows_move_by_pd_step_and_h_and_l_by_one() {
+        let mut state = state("/a/b/c/d/e");
+        state.step = 2;
+        press(&mut state, KeyCode::Left);
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/"));
+        press(&mut state, KeyCode::Char('h'));
+        assert_eq!(state.selected_path(), Path::new("/a/b/"));
+        press(&mut state, KeyCode::Right);
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/d/"));
+        press(&mut state, KeyCode::Char('l'));
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/d/e"));
+    }
+
+    #[test]
+    fn a_huge_step_times_a_count_saturates() {
+        let mut state = state("/a/b/c");
+        state.step = isize::MAX;
+        type_keys(&mut state, "9");
+        press(&mut state, KeyCode::Left);
+        assert_eq!(state.selected_path(), Path::new("/"));
+        type_keys(&mut state, "9000000000000000000");
+        state.move_by(isize::MAX, Boundary::Wrap);
+        // The target saturates at isize::MAX, which is 3 modulo the 4 parts.
+        assert_eq!(state.selected_path(), Path::new("/a/b/c"));
+    }
+
+    #[test]
+    fn enter_cancels_a_pending_jump_instead_of_confirming() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "F");
+        assert!(matches!(press(&mut state, KeyCode::Enter), EventAction::Continue));
+        assert!(matches!(state.input_mode, InputMode::Normal));
+        assert_eq!(state.selected_path(), Path::new("/a/b/c"));
+        // The sequence is over, so the next Enter confirms.
+        assert!(matches!(press(&mut state, KeyCode::Enter), EventAction::Confirm(_)));
+    }
+
+    #[test]
+    fn esc_names_the_cancelled_sequence() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "f");
+        press(&mut state, KeyCode::Esc);
+        assert_eq!(state.status_message.as_deref(), Some("jump cancelled"));
+        type_keys(&mut state, "m");
+        press(&mut state, KeyCode::Esc);
+        assert_eq!(state.status_message.as_deref(), Some("cancelled"));
+    }
+
+    #[test]
+    fn parse_config_reads_the_keymap_and_the_style_table() {
+        let config = parse_config(
+            "# pd\nkeymap = \"emacs\"\n\n[style]\nselection = 'bold,fg=green' # look\n\
+             highlight_mode = \"dim-others\"\n",
+        )
+        .expect("the config is valid");
+        assert_eq!(config.keymap.as_deref(), Some("emacs"));
+        assert_eq!(config.selection.as_deref(), Some("bold,fg=green"));
+        assert_eq!(config.highlight_mode.as_deref(), Some("dim-others"));
+    }
+
+    #[test]
+    fn parse_config_rejects_a_malformed_file() {
+        assert_eq!(parse_config("keymap = emacs").err().as_deref(), Some("line 1 is not valid"));
+        assert_eq!(parse_config("\n[style").err().as_deref(), Some("line 2 is not valid"));
+        assert_eq!(parse_config("keymap").err().as_deref(), Some("line 1 is not valid"));
+        assert_eq!(
+            parse_config("keymap = \"vim\" trailing").err().as_deref(),
+            Some("line 1 is not valid")
+        );
+    }
+
+    #[test]
+    fn resolve_keymap_prefers_the_env_then_the_program_name_then_the_config() {
+        let config = || Some("emacs".to_string());
+        assert!(resolve_keymap(Some("none"), Some(Keymap::Vim), config) == Keymap::None);
+        assert!(resolve_keymap(None, Some(Keymap::Vim), config) == Keymap::Vim);
+        assert!(resolve_keymap(None, None, config) == Keymap::Emacs);
+        assert!(resolve_keymap(None, None, || None) == Keymap::Vim);
+        // An unknown value falls back to the program name's keymap.
+        assert!(resolve_keymap(Some("vi"), Some(Keymap::Emacs), config) == Keymap::Emacs);
+    }
+
+    #[test]
+    fn the_home_directory_can_be_expanded_and_collapsed_again() {
+        let mut state = state("/home/me/src");
+        state.home_index = Some(2);
+        assert_eq!(rendered(&state, 80).trim_end(), "~/src");
+        type_keys(&mut state, "~");
+        assert_eq!(rendered(&state, 80).trim_end(), "/home/me/src");
+        assert_eq!(state.selected_path(), Path::new("/home/me/src"));
+        type_keys(&mut state, "~");
+        assert_eq!(rendered(&state, 80).trim_end(), "~/src");
+        assert_eq!(state.selected_path(), Path::new("/home/me/src"));
+    }
+
+    #[test]
+    fn a_narrow_cell_keeps_the_selected_min_width_of_the_selection() {
+        let mut state = state("/alpha/bravo/charlie");
+        state.cell_width = Some(3);
+        state.selected_min_width = 5;
+        assert_eq!(rendered(&state, 80).trim_end(), "/  al…br…charl…");
+        // On a narrow line, the neighbors are dropped rather than the selection cut.
+        state.update_viewport(12);
+        assert_eq!(rendered(&state, 12).trim_end(), "…br…charl…");
+        type_keys(&mut state, "h");
+        state.update_viewport(12);
+        assert_eq!(rendered(&state, 12).trim_end(), "…bravo/ch…");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clicks_map_to_parts_with_invalid_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/a/\xff\xfeb/c"));
+        let state = AppState::new(split_path(path, None), InitialPos::End);
+        // Each invalid byte is drawn as a one-column U+FFFD.
+        assert_eq!(rendered(&state, 80).trim_end(), "/a/\u{FFFD}\u{FFFD}b/c");
+        assert_eq!(state.part_at(2, 0), Some(1));
+        assert_eq!(state.part_at(3, 0), Some(2));
+        assert_eq!(state.part_at(6, 0), Some(2));
+        assert_eq!(state.part_at(7, 0), Some(3));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn clicks_map_to_parts_with_an_unpaired_surrogate() {
+        use std::os::windows::ffi::OsStringExt;
+
+        let wide: Vec<u16> =
+            "C:\\a\\".encode_utf16().chain([0xD800]).chain("b\\c".encode_utf16()).collect();
+        let path = PathBuf::from(OsString::from_wide(&wide));
+        let state = AppState::new(split_path(&path, None), InitialPos::End);
+        // The surrogate is drawn as a one-column U+FFFD.
+        assert_eq!(rendered(&state, 80).trim_end(), "C:\\a\\\u{FFFD}b\\c");
+        assert_eq!(state.part_at(4, 0), Some(1));
+        assert_eq!(state.part_at(5, 0), Some(2));
+        assert_eq!(state.part_at(7, 0), Some(2));
+        assert_eq!(state.part_at(8, 0), Some(3));
+    }
+
+    #[test]
+    fn display_part_escapes_control_characters() {
+        assert_eq!(display_part(OsStr::new("a\nb\tc\u{7f}/")), "a^Jb^Ic^?/");
+        assert_eq!(display_part(OsStr::new("plain/")), "plain/");
+    }
+
+    #[test]
+    fn control_characters_are_escaped_on_screen_but_kept_in_the_path() {
+        let state = state("/a\nb/c");
+        assert_eq!(rendered(&state, 80).trim_end(), "/a^Jb/c");
+        assert_eq!(state.selected_path(), Path::new("/a\nb/c"));
+    }
+
+    #[test]
+    fn is_branching_dir_counts_the_subdirectories() {
+        let dir = temp_dir("is_branching_dir");
+        assert!(is_branching_dir(&dir));
+        fs::create_dir(dir.join("one")).expect("the directory can be created");
+        fs::write(dir.join("file"), "").expect("the file can be written");
+        assert!(!is_branching_dir(&dir));
+        fs::create_dir(dir.join("two")).expect("the directory can be created");
+        assert!(is_branching_dir(&dir));
+        // An unreadable directory is a place to stop at, too.
+        assert!(is_branching_dir(&dir.join("missing")));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn branch_motions_skip_single_child_chains() {
+        let dir = temp_dir("branch_motions");
+        fs::create_dir_all(dir.join("one/two/three")).expect("the tree can be created");
+        fs::create_dir(dir.join("other")).expect("the directory can be created");
+        let path = dir.join("one/two/three");
+        let mut state = AppState::new(split_path(&path, None), InitialPos::End);
+        type_keys(&mut state, "{");
+        assert_eq!(state.selected_path(), dir);
+        type_keys(&mut state, "}");
+        assert_eq!(state.selected_path(), path);
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn format_size_is_short_like_ls_h() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1024), "1.0K");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0M");
+    }
+
+    #[test]
+    fn annotate_dir_counts_entries_or_sums_sizes() {
+        let dir = temp_dir("annotate_dir");
+        fs::create_dir(dir.join("sub")).expect("the directory can be created");
+        fs::write(dir.join("a"), [0; 1000]).expect("the file can be written");
+        fs::write(dir.join("sub/b"), [0; 1048]).expect("the file can be written");
+        assert_eq!(dir_size(&dir).expect("the tree is readable"), 2048);
+        assert_eq!(annotate_dir(&dir, SizeAnnotation::EntryCount), "2");
+        assert_eq!(annotate_dir(&dir, SizeAnnotation::RecursiveSize), "2.0K");
+        assert_eq!(annotate_dir(&dir, SizeAnnotation::None), "");
+        assert_eq!(annotate_dir(&dir.join("missing"), SizeAnnotation::EntryCount), "?");
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn keymap_from_program_name_uses_the_file_stem() {
+        assert!(keymap_from_program_name(OsStr::new("pde")) == Some(Keymap::Emacs));
+        assert!(keymap_from_program_name(OsStr::new("/usr/local/bin/pdv")) == Some(Keymap::Vim));
+        assert!(keymap_from_program_name(OsStr::new("pde.exe")) == Some(Keymap::Emacs));
+        assert!(keymap_from_program_name(OsStr::new("pd")).is_none());
+        assert!(keymap_from_program_name(OsStr::new("pdemo")).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quote_posix_quotes_only_when_needed() {
+        assert_eq!(quote_posix(b"/usr/local/bin"), b"/usr/local/bin");
+        assert_eq!(quote_posix(b"a b"), b"'a b'");
+        assert_eq!(quote_posix(b"it's"), b"'it'\\''s'");
+        assert_eq!(quote_posix(b"$HOME"), b"'$HOME'");
+        assert_eq!(quote_posix(b""), b"''");
+        assert_eq!(quote_posix(b"\xff x"), b"'\xff x'");
+    }
+
+    #[cfg(not(unix))]
+    #[test]
+    fn quote_powershell_doubles_single_quotes() {
+        assert_eq!(quote_powershell(r"C:\a b"), r"'C:\a b'");
+        assert_eq!(quote_powershell("it's"), "'it''s'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ensure_utf8_names_the_first_invalid_part() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let parts = split_path(Path::new(OsStr::from_bytes(b"/ok/\xffbad/c")), None);
+        let error = ensure_utf8(&parts).expect_err("the path is not UTF-8");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "path component '\u{FFFD}bad/' is not valid UTF-8");
+        assert!(ensure_utf8(&split_path(Path::new("/ok/c"), None)).is_ok());
+    }
+
+    #[test]
+    fn resolve_cdpath_tries_each_entry_in_order() {
+        let dir = temp_dir("resolve_cdpath");
+        fs::create_dir_all(dir.join("first/proj")).expect("the tree can be created");
+        fs::create_dir_all(dir.join("second/proj")).expect("the tree can be created");
+        fs::create_dir_all(dir.join("second/other")).expect("the tree can be created");
+        let cdpath = env::join_paths([dir.join("first"), dir.join("second")])
+            .expect("the entries can be joined");
+        let resolve = |path: &str| resolve_cdpath(Path::new(path), Some(&cdpath));
+        assert_eq!(resolve("proj"), dir.join("first/proj"));
+        assert_eq!(resolve("other"), dir.join("second/other"));
+        assert_eq!(resolve("missing"), Path::new("missing"));
+        // Explicitly relative and absolute paths skip CDPATH, as does no CDPATH.
+        assert_eq!(resolve("./proj"), Path::new("./proj"));
+        assert_eq!(resolve("../proj"), Path::new("../proj"));
+        assert_eq!(resolve_cdpath(Path::new("proj"), None), Path::new("proj"));
+        let absolute = dir.join("second/other");
+        assert_eq!(resolve_cdpath(&absolute, Some(&cdpath)), absolute);
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn dim_others_dims_every_part_but_the_selection() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "h");
+        assert!(frame(&state, 80).contains("\x1b[7mb/"));
+        state.highlight_mode = HighlightMode::DimOthers;
+        let frame = frame(&state, 80);
+        assert!(frame.contains("\x1b[2ma/\x1b[0m"));
+        assert!(frame.contains("\x1b[2mc\x1b[0m"));
+        assert!(frame.contains("\x1b[0mb/\x1b[0m"));
+        assert!(!frame.contains("\x1b[7m"));
+    }
+
+    #[test]
+    fn move_to_parity_counts_parts_of_that_parity() {
+        let mut state = state("/a/b/c/d/e");
+        state.move_to_parity(Parity::Even, JumpDirection::Backward);
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/d/"));
+        type_keys(&mut state, "2");
+        state.move_to_parity(Parity::Odd, JumpDirection::Backward);
+        assert_eq!(state.selected_path(), Path::new("/a/"));
+        // Fewer matches than the count stop at the last one.
+        type_keys(&mut state, "9");
+        state.move_to_parity(Parity::Odd, JumpDirection::Forward);
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/d/e"));
+        // No match at all leaves the selection where it is.
+        state.move_to_parity(Parity::Even, JumpDirection::Forward);
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/d/e"));
+    }
+
+    #[test]
+    fn complete_path_offers_matching_subdirectories() {
+        let dir = temp_dir("complete_path");
+        for sub in ["src/bin", "src/lib", "sample", ".hidden"] {
+            fs::create_dir_all(dir.join(sub)).expect("the tree can be created");
+        }
+        fs::write(dir.join("script"), "").expect("the file can be written");
+        let sep = std::path::MAIN_SEPARATOR;
+        assert_eq!(complete_path(&dir, "sr"), [format!("src{}", sep)]);
+        assert_eq!(complete_path(&dir, "s"), [format!("sample{}", sep), format!("src{}", sep)]);
+        assert_eq!(
+            complete_path(&dir, &format!("src{}l", sep)),
+            [format!("src{}lib{}", sep, sep)]
+        );
+        assert_eq!(complete_path(&dir, "."), [format!(".hidden{}", sep)]);
+        assert!(complete_path(&dir, "x").is_empty());
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn tab_cycles_through_ambiguous_completions() {
+        let dir = temp_dir("tab_completion");
+        fs::create_dir_all(dir.join("alpha")).expect("the directory can be created");
+        fs::create_dir_all(dir.join("also")).expect("the directory can be created");
+        let sep = std::path::MAIN_SEPARATOR;
+        let mut command = CommandLine { input: "cd al".to_string(), ..CommandLine::default() };
+        command.complete(&dir);
+        assert_eq!(command.input, format!("cd alpha{}", sep));
+        command.complete(&dir);
+        assert_eq!(command.input, format!("cd also{}", sep));
+        command.complete(&dir);
+        assert_eq!(command.input, format!("cd alpha{}", sep));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn normalize_lexically_resolves_dots_without_the_filesystem() {
+        assert_eq!(normalize_lexically(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize_lexically(Path::new("/..")), Path::new("/"));
+        assert_eq!(normalize_lexically(Path::new("../a")), Path::new("../a"));
+    }
+
+    #[test]
+    fn moving_to_the_start_twice_toggles_to_the_first_directory() {
+        let mut state = state("/a/b");
+        type_keys(&mut state, "^");
+        assert_eq!(state.selected_path(), Path::new("/"));
+        type_keys(&mut state, "^");
+        assert_eq!(state.selected_path(), Path::new("/a/"));
+        press(&mut state, KeyCode::Home);
+        assert_eq!(state.selected_path(), Path::new("/"));
+        // A relative path has no root to skip.
+        let relative = AppState::new(split_path(Path::new("a/b"), None), InitialPos::End);
+        assert_eq!(relative.first_normal_index(), 0);
+    }
+
+    #[test]
+    fn git_relative_path_is_relative_to_the_repository_root() {
+        let dir = temp_dir("git_relative_path");
+        let repo = dir.join("repo");
+        fs::create_dir_all(repo.join(".git")).expect("the tree can be created");
+        fs::create_dir_all(repo.join("src/deep")).expect("the tree can be created");
+        let start = repo.join("src/deep");
+        assert_eq!(find_git_root(&start), Some(repo.as_path()));
+        assert_eq!(git_relative_path(repo.join("src"), &start), Path::new("src"));
+        assert_eq!(git_relative_path(repo.clone(), &start), Path::new("."));
+        // Above the root, the path is kept absolute.
+        assert_eq!(git_relative_path(dir.clone(), &start), dir);
+        // A `.git` file, as in a worktree, marks a root too.
+        fs::create_dir_all(repo.join("src/worktree")).expect("the directory can be created");
+        fs::write(repo.join("src/worktree/.git"), "gitdir: elsewhere").expect("can be written");
+        let worktree = repo.join("src/worktree");
+        assert_eq!(find_git_root(&worktree), Some(worktree.as_path()));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn reload_drops_the_caches_and_follows_a_renamed_working_directory() {
+        let cwd = env::current_dir().expect("the working directory is readable");
+        let name = cwd.file_name().expect("the working directory has a name");
+        let old = Path::new("/renamed-away").join(name);
+        let mut state = AppState::new(split_path(&old, None), InitialPos::End);
+        state.cwd = old.clone();
+        state.branching_cache.insert(old.clone(), true);
+        type_keys(&mut state, "R");
+        assert!(state.branching_cache.is_empty());
+        assert!(state.clear_screen);
+        // The selection stays on the part with the same name.
+        assert_eq!(state.selected_path(), cwd);
+        assert_eq!(state.cwd, cwd);
+    }
+
+    #[test]
+    fn set_path_parts_clamps_the_selection_and_drops_stale_state() {
+        let mut state = state("/a/b/c/d");
+        type_keys(&mut state, "hhmx");
+        state.set_path_parts(split_path(Path::new("/x"), None), 4);
+        assert_eq!(state.selected_path(), Path::new("/x"));
+        assert!(state.marks.is_empty());
+        assert!(state.index_history.is_empty());
+        state.set_path_parts(Vec::new(), 3);
+        assert_eq!(state.current_index, 0);
+    }
+
+    #[test]
+    fn numbered_parts_are_drawn_and_selected_by_index() {
+        let mut state = state("/a/b");
+        state.numbered = true;
+        assert_eq!(rendered(&state, 80).trim_end(), "0:/ 1:a/ 2:b");
+        type_keys(&mut state, "1|");
+        assert_eq!(state.selected_path(), Path::new("/a/"));
+        // A click on an index label selects its part.
+        assert_eq!(state.part_at(4, 0), Some(1));
+        assert_eq!(state.part_at(9, 0), Some(2));
+        type_keys(&mut state, "9|");
+        assert_eq!(state.selected_path(), Path::new("/a/b"));
+    }
+
+    #[test]
+    fn allowed_roots_confine_the_motions() {
+        let mut state = state("/srv/app/src/bin");
+        state.allowed_roots = vec![PathBuf::from("/srv/app")];
+        assert!(state.is_path_allowed(Path::new("/srv/app/src")));
+        assert!(!state.is_path_allowed(Path::new("/srv/apps")));
+        assert_eq!(state.allowed_start(), Some(2));
+        type_keys(&mut state, "9h");
+        assert_eq!(state.selected_path(), Path::new("/srv/app/"));
+        type_keys(&mut state, "^");
+        assert_eq!(state.selected_path(), Path::new("/srv/app/"));
+        assert_eq!(state.part_at(0, 0), Some(2));
+    }
+
+    #[test]
+    fn render_mini_bolds_only_the_last_part() {
+        let parts = split_path(Path::new("/usr/local/bin"), None);
+        let mut out = Vec::new();
+        render_mini(&mut out, &parts, true, false).expect("rendering to a buffer works");
+        assert_eq!(out, b"/usr/local/\x1b[1mbin\x1b[0m\n");
+        let mut out = Vec::new();
+        render_mini(&mut out, &parts, false, false).expect("rendering to a buffer works");
+        assert_eq!(out, b"/usr/local/bin\n");
+        let mut out = Vec::new();
+        render_mini(&mut out, &parts, false, true).expect("rendering to a buffer works");
+        assert_eq!(out, b"/u/l/bin\n");
+    }
+
+    #[test]
+    fn key_specs_parse_and_round_trip() {
+        for spec in ["enter", "space", "tab", "C-j", "M-x", "f5", "x"] {
+            let key = KeyBinding::parse(spec).expect("the spec is valid");
+            assert_eq!(key.spec(), spec);
+        }
+        assert!(KeyBinding::parse("Return") == Some(KeyBinding::ENTER));
+        assert!(KeyBinding::parse("f13").is_none());
+        assert!(KeyBinding::parse("C-").is_none());
+        assert!(KeyBinding::parse("xy").is_none());
+        // Shift is part of the character already.
+        let shifted = KeyEvent::new(KeyCode::Char('X'), KeyModifiers::SHIFT);
+        assert!(KeyBinding::parse("X").expect("the spec is valid").matches(&shifted));
+    }
+
+    #[test]
+    fn a_configured_confirm_key_replaces_enter() {
+        let mut state = state("/a/b");
+        state.confirm_key = KeyBinding::parse("C-j").expect("the spec is valid");
+        let key = KeyEvent::new(KeyCode::Char('j'), KeyModifiers::CONTROL);
+        let action = handle_key_event(key, &mut state).expect("keys are handled");
+        assert!(matches!(action, EventAction::Confirm(path) if path == Path::new("/a/b")));
+        assert!(!matches!(press(&mut state, KeyCode::Enter), EventAction::Confirm(_)));
+    }
+
+    #[test]
+    fn jumps_match_decomposed_names_with_normalize_unicode() {
+        // The name is "café" spelled with a combining accent, as HFS+ stores it.
+        let mut state = state("/cafe\u{301}/b/c");
+        state.normalize_unicode = false;
+        type_keys(&mut state, "Fé");
+        assert_eq!(state.selected_path(), Path::new("/cafe\u{301}/b/c"));
+        state.normalize_unicode = true;
+        type_keys(&mut state, "Fé");
+        assert_eq!(state.selected_path(), Path::new("/cafe\u{301}/"));
+    }
+
+    #[test]
+    fn selected_name_strips_the_separator() {
+        let mut state = state("/a/bc/d");
+        type_keys(&mut state, "h");
+        assert_eq!(state.selected_name(), "bc");
+        type_keys(&mut state, "$");
+        assert_eq!(state.selected_name(), "d");
+        type_keys(&mut state, "0");
+        assert_eq!(state.selected_name(), "/");
+    }
+
+    #[test]
+    fn resolve_start_rejects_blank_and_missing_paths() {
+        let dir = temp_dir("resolve_start");
+        fs::create_dir(dir.join("sub")).expect("the directory can be created");
+        fs::write(dir.join("file"), "").expect("the file can be written");
+        let resolve = |arg: &str| resolve_start(Some(OsStr::new(arg)), &dir, None);
+        assert_eq!(resolve_start(None, &dir, None), Ok(dir.clone()));
+        assert_eq!(resolve(""), Ok(dir.clone()));
+        assert_eq!(resolve(" \t"), Err("start path ' \t' is blank".to_string()));
+        assert_eq!(resolve("sub/../sub"), Ok(dir.join("sub")));
+        assert_eq!(resolve("file"), Err("start path 'file' is not a directory".to_string()));
+        assert!(resolve("missing").is_err_and(|e| e.starts_with("cannot open start path")));
+        // With a custom separator the argument is not a directory at all.
+        let path = resolve_start(Some(OsStr::new("a.b.c")), &dir, Some('.'));
+        assert_eq!(path, Ok(PathBuf::from("a.b.c")));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn a_sticky_count_repeats_until_cleared() {
+        let mut state = state("/a/b/c/d/e/f/g");
+        type_keys(&mut state, "2*hh");
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/"));
+        // A typed count still overrides it once.
+        type_keys(&mut state, "1l");
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/d/"));
+        type_keys(&mut state, "*l");
+        assert_eq!(state.sticky_count, None);
+        assert_eq!(state.selected_path(), Path::new("/a/b/c/d/e/"));
+        assert!(rendered(&state, 80).trim_end().ends_with("/a/b/c/d/e/f/g"));
+        type_keys(&mut state, "3*");
+        assert!(rendered(&state, 80).trim_end().ends_with("  *3"));
+    }
+
+    #[test]
+    fn depth_counts_the_levels_above_the_deepest_part() {
+        let mut state = state("/a/b/c");
+        assert_eq!(state.depth(), 0);
+        type_keys(&mut state, "2h");
+        assert_eq!(state.depth(), 2);
+        type_keys(&mut state, "^");
+        assert_eq!(state.depth(), 3);
+    }
+
+    #[test]
+    fn smart_case_jumps_ignore_case_for_lowercase_targets_only() {
+        let mut lowercase = state("/Bx/y/c");
+        type_keys(&mut lowercase, "Fb");
+        assert_eq!(lowercase.selected_path(), Path::new("/Bx/y/c"));
+        lowercase.jump_case = JumpCase::Smart;
+        type_keys(&mut lowercase, "Fb");
+        assert_eq!(lowercase.selected_path(), Path::new("/Bx/"));
+        let mut uppercase = state("/Bx/b/c");
+        uppercase.jump_case = JumpCase::Smart;
+        type_keys(&mut uppercase, "FB");
+        assert_eq!(uppercase.selected_path(), Path::new("/Bx/"));
+        // The filter query folds the same way with `smartcase`.
+        uppercase.smartcase = true;
+        assert!(uppercase.contains_query(1, "bx"));
+        assert!(!uppercase.contains_query(2, "B"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_parts_are_marked() {
+        let dir = temp_dir("symlinked_parts");
+        fs::create_dir_all(dir.join("real/sub")).expect("the tree can be created");
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("link"))
+            .expect("the symlink can be created");
+        let mut state = AppState::new(split_path(&dir.join("link/sub"), None), InitialPos::End);
+        state.show_symlinks = true;
+        state.refresh_symlinks();
+        assert!(rendered(&state, 400).trim_end().ends_with("/link@/sub"));
+        state.show_symlinks = false;
+        assert!(rendered(&state, 400).trim_end().ends_with("/link/sub"));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn esc_discards_a_count_before_quitting() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "123");
+        assert!(matches!(press(&mut state, KeyCode::Esc), EventAction::Continue));
+        assert!(state.count_input.is_empty());
+        assert!(matches!(press(&mut state, KeyCode::Esc), EventAction::Quit));
+    }
+
+    #[test]
+    fn initial_positions_pick_an_index() {
+        assert_eq!(InitialPos::End.index(5), 4);
+        assert_eq!(InitialPos::Start.index(5), 0);
+        assert_eq!(InitialPos::Middle.index(5), 2);
+        assert_eq!(InitialPos::Depth(1).index(5), 3);
+        assert_eq!(InitialPos::Depth(9).index(5), 0);
+        assert_eq!(InitialPos::End.index(0), 0);
+        let state = AppState::new(split_path(Path::new("/a/b/c"), None), InitialPos::Depth(2));
+        assert_eq!(state.selected_path(), Path::new("/a/"));
+    }
+
+    /// Feeds `event_loop` a fixed sequence of events.
+    struct ScriptedEvents(VecDeque<Event>);
+
+    impl EventSource for ScriptedEvents {
+        fn read(&mut self) -> Result<Event> {
+            self.0.pop_front().ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+        }
+
+        fn poll(&mut self, _timeout: Duration) -> Result<bool> {
+            Ok(!self.0.is_empty())
+        }
+    }
+
+    #[test]
+    fn event_loop_runs_on_scripted_events() {
+        let key = |code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        let mut state = state("/a/b/c");
+        let mut events = ScriptedEvents(VecDeque::from([
+            key(KeyCode::Char('h')),
+            key(KeyCode::Char('h')),
+            key(KeyCode::Enter),
+        ]));
+        let mut out = Vec::new();
+        let selection = event_loop(&mut state, &mut events, &mut out, None)
+            .expect("the script is handled")
+            .expect("the script confirms");
+        assert_eq!(selection.path, Path::new("/a/"));
+        assert_eq!(selection.depth, 2);
+        assert!(!selection.unchanged);
+        assert!(!out.is_empty());
+
+        let mut events = ScriptedEvents(VecDeque::from([key(KeyCode::Char('q'))]));
+        let quit = event_loop(&mut state, &mut events, &mut Vec::new(), None);
+        assert!(quit.expect("the script is handled").is_none());
+    }
+
+    #[test]
+    fn skip_root_keeps_the_selection_off_the_root() {
+        let mut state = state("/a/b");
+        state.skip_root = true;
+        state.update_min_index();
+        type_keys(&mut state, "9h");
+        assert_eq!(state.selected_path(), Path::new("/a/"));
+        type_keys(&mut state, "^");
+        assert_eq!(state.selected_path(), Path::new("/a/"));
+        assert_eq!(state.part_at(0, 0), Some(1));
+        // The root alone stays selectable.
+        let mut root = AppState::new(split_path(Path::new("/"), None), InitialPos::End);
+        root.skip_root = true;
+        root.update_min_index();
+        assert_eq!(root.selected_path(), Path::new("/"));
+    }
+
+    #[test]
+    fn the_caret_line_underlines_the_selection() {
+        let mut state = state("/ab/c");
+        state.show_caret = true;
+        assert!(rendered(&state, 80).ends_with("\r\n    ^"));
+        type_keys(&mut state, "h");
+        assert!(rendered(&state, 80).ends_with("\r\n ^^^"));
+        state.numbered = true;
+        assert_eq!(state.part_span(1), (6, 3));
+    }
+
+    #[test]
+    fn parse_inputrc_reads_single_key_bindings() {
+        let (bindings, warnings) = parse_inputrc(
+            "# comment\n\
+             \"\\C-b\": backward-char\n\
+             \"\\ef\": forward-word\n\
+             Control-a: beginning-of-line\n\
+             set editing-mode vi\n\
+             \"\\C-x\\C-e\": end-of-line\n\
+             M-k: kill-line\n",
+        );
+        let keys: Vec<String> = bindings.iter().map(|(key, _)| key.spec()).collect();
+        assert_eq!(keys, ["C-b", "M-f", "C-a"]);
+        assert!(matches!(
+            bindings.iter().map(|(_, action)| *action).collect::<Vec<_>>()[..],
+            [Action::MoveLeft, Action::MoveRight, Action::MoveToStart]
+        ));
+        assert_eq!(
+            warnings,
+            [
+                "inputrc line 5: unsupported directive: set editing-mode vi",
+                "inputrc line 6: unsupported key sequence: \"\\C-x\\C-e\": end-of-line",
+                "inputrc line 7: unsupported function: M-k: kill-line",
+            ]
+        );
+    }
+
+    #[test]
+    fn inputrc_bindings_take_precedence_over_the_keymap() {
+        let mut state = state("/a/b/c");
+        state.custom_bindings = parse_inputrc("\"h\": forward-char\n").0;
+        type_keys(&mut state, "^h");
+        assert_eq!(state.selected_path(), Path::new("/a/"));
+    }
+
+    #[test]
+    fn print_lines_ends_with_a_newline_unless_asked_not_to() {
+        let lines = [OsString::from("/a/b"), OsString::from("/c")];
+        let mut out = Vec::new();
+        print_lines(&mut out, &lines, false).expect("a Vec can be written to");
+        #[cfg(unix)]
+        assert_eq!(out, b"/a/b\n/c\n");
+        let mut out = Vec::new();
+        print_lines(&mut out, &lines, true).expect("a Vec can be written to");
+        assert_eq!(out, b"/a/b\n/c");
+        let options = Options { print0: true, ..Options::default() };
+        let mut out = Vec::new();
+        print_output(&mut out, &lines, Path::new("/"), &options).expect("a Vec can be written to");
+        assert_eq!(out, b"/a/b\0/c\0");
+        let args = |args: &[&str]| parse_args(args.iter().map(OsString::from));
+        for option in ["--print0", "-0", "--oldpwd"] {
+            let error = args(&["--no-newline", option]).err().expect("the options conflict");
+            let name = if option == "-0" { "--print0" } else { option };
+            assert_eq!(error, format!("option '--no-newline' cannot be combined with '{}'", name));
+        }
+    }
+
+    #[test]
+    fn dotdots_shows_the_ascent_from_the_deepest_part() {
+        let mut state = state("/a/b/c");
+        assert_eq!(state.dotdots(), ".");
+        state.show_dotdots = true;
+        let sep = std::path::MAIN_SEPARATOR_STR;
+        press(&mut state, KeyCode::Left);
+        press(&mut state, KeyCode::Left);
+        assert_eq!(state.dotdots(), format!("..{sep}.."));
+        assert!(rendered(&state, 80).contains(&format!("  ..{sep}..")));
+    }
+
+    #[test]
+    fn without_a_terminal_the_fallback_decides_what_is_printed() {
+        let start = Path::new("/a/b/c");
+        let mut parent = state("/a/b/c");
+        let selection = no_tty_selection(NoTtyFallback::Parent, &mut parent, start)
+            .expect("the parent fallback succeeds")
+            .expect("the parent is printed");
+        assert_eq!(selection.path, Path::new("/a/b/"));
+        assert!(!selection.unchanged);
+        let selection = no_tty_selection(NoTtyFallback::Cwd, &mut state("/a/b/c"), start)
+            .expect("the cwd fallback succeeds")
+            .expect("the start is printed");
+        assert_eq!(selection.path, start);
+        assert!(selection.unchanged);
+        assert!(no_tty_selection(NoTtyFallback::Error, &mut state("/a/b/c"), start).is_err());
+    }
+
+    #[test]
+    fn reverse_draws_from_the_deepest_part_and_mirrors_the_arrows() {
+        let mut state = state("/a/b/c");
+        state.reverse = true;
+        assert_eq!(state.display_order(), [3, 2, 1, 0]);
+        assert_eq!(rendered(&state, 80), "c < b < a < /");
+        press(&mut state, KeyCode::Right);
+        assert_eq!(state.selected_path(), Path::new("/a/b/"));
+        press(&mut state, KeyCode::Left);
+        assert_eq!(state.selected_path(), Path::new("/a/b/c"));
+    }
+
+    #[test]
+    fn dotdot_relative_path_only_covers_nearby_ancestors() {
+        let start = Path::new("/a/b/c");
+        assert_eq!(dotdot_relative_path(start, start, 0), Some(PathBuf::from(".")));
+        assert_eq!(
+            dotdot_relative_path(Path::new("/a"), start, 2),
+            Some(Path::new("..").join(".."))
+        );
+        assert_eq!(dotdot_relative_path(Path::new("/"), start, 2), None);
+        assert_eq!(dotdot_relative_path(Path::new("/a/x"), start, 3), None);
+    }
+
+    #[test]
+    fn hash_cycles_through_parts_with_the_same_name() {
+        let mut state = state("/src/a/src/b/src");
+        type_keys(&mut state, "#");
+        assert_eq!(state.selected_path(), Path::new("/src/a/src/"));
+        assert_eq!(state.status_message.as_deref(), Some("2/3"));
+        type_keys(&mut state, "##");
+        assert_eq!(state.selected_path(), Path::new("/src/a/src/b/src"));
+        assert_eq!(state.status_message.as_deref(), Some("3/3"));
+        press(&mut state, KeyCode::Left);
+        type_keys(&mut state, "#");
+        assert_eq!(state.status_message.as_deref(), Some("no other part with this name"));
+    }
+
+    #[test]
+    fn bold_last_emboldens_the_deepest_part_when_it_is_not_selected() {
+        let mut state = state("/a/b/c");
+        press(&mut state, KeyCode::Left);
+        assert!(!frame(&state, 80).contains("\x1b[1mc"));
+        state.bold_last = true;
+        assert!(frame(&state, 80).contains("\x1b[7mb/\x1b[0m\x1b[1mc\x1b[0m"));
+    }
+
+    #[test]
+    fn the_status_layout_decides_where_the_message_is_drawn() {
+        let mut state = state("/a/b/c");
+        state.status_message = Some("hi".to_string());
+        assert_eq!(rendered(&state, 10), "/a/b/c  hi");
+        state.status_layout = StatusLayout::Line;
+        assert_eq!(rendered(&state, 10), "/a/b/c\r\nhi");
+        state.status_layout = StatusLayout::Blank;
+        assert_eq!(rendered(&state, 10), "/a/b/c\r\n\r\nhi");
+        state.status_layout = StatusLayout::Rule;
+        assert_eq!(rendered(&state, 10), format!("/a/b/c\r\n{}\r\nhi", "─".repeat(10)));
+    }
+
+    #[test]
+    fn parse_single_part_defaults_to_the_ui() {
+        assert!(matches!(parse_single_part(None), SinglePart::Interactive));
+        assert!(matches!(parse_single_part(Some("ui")), SinglePart::Interactive));
+        assert!(matches!(parse_single_part(Some("confirm")), SinglePart::Confirm));
+        assert!(matches!(parse_single_part(Some("quit")), SinglePart::Quit));
+        assert!(matches!(parse_single_part(Some("bogus")), SinglePart::Interactive));
+    }
+
+    #[test]
+    fn a_custom_separator_splits_and_joins_on_that_character() {
+        assert_eq!(split_on(OsStr::new("com.example.app"), '.'), ["com.", "example.", "app"]);
+        assert_eq!(split_on(OsStr::new(""), '.'), ["."]);
+        let mut dotted = AppState::new(
+            split_path(Path::new("com.example.app"), Some('.')),
+            InitialPos::End,
+        );
+        dotted.separator = Some('.');
+        press(&mut dotted, KeyCode::Left);
+        assert_eq!(dotted.selected_path().as_os_str(), "com.example");
+    }
+
+    #[test]
+    fn preview_line_lists_a_few_entries() {
+        let names = |count: usize| DirPreview::Entries((0..count).map(|i| i.to_string()).collect());
+        assert_eq!(preview_line(&names(0)), "(empty)");
+        assert_eq!(preview_line(&names(2)), "0  1");
+        assert_eq!(preview_line(&names(7)), "0  1  2  3  4  (+2 more)");
+        let unreadable = DirPreview::Unreadable("(permission denied)");
+        assert_eq!(preview_line(&unreadable), "(permission denied)");
+    }
+
+    #[test]
+    fn the_selected_directory_is_previewed_once_the_selection_rests() {
+        let dir = temp_dir("preview");
+        fs::create_dir(dir.join("sub")).expect("the subdirectory can be created");
+        fs::write(dir.join("file"), "").expect("the file can be written");
+        let mut previewed = state(dir.to_str().expect("the temporary directory is UTF-8"));
+        previewed.preview_dir = true;
+        let now = Instant::now();
+        previewed.schedule_preview(now);
+        previewed.tick(now);
+        assert!(previewed.previews.is_empty());
+        previewed.tick(now + PREVIEW_DELAY);
+        let sep = std::path::MAIN_SEPARATOR;
+        assert!(rendered(&previewed, 80).ends_with(&format!("\r\nfile  sub{sep}")));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_uri_percent_encodes_all_but_unreserved_bytes() {
+        use std::os::unix::ffi::OsStrExt as _;
+        assert_eq!(file_uri(Path::new("/")), "file:///");
+        assert_eq!(file_uri(Path::new("/tmp/a b/ü~")), "file:///tmp/a%20b/%C3%BC~");
+        let raw = Path::new(OsStr::from_bytes(b"/x/\xff"));
+        assert_eq!(file_uri(raw), "file:///x/%FF");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn file_uri_puts_drives_in_the_path_and_servers_in_the_authority() {
+        assert_eq!(file_uri(Path::new(r"C:\Users\a b")), "file:///C:/Users/a%20b");
+        assert_eq!(file_uri(Path::new(r"\\server\share\dir")), "file://server/share/dir");
+    }
+
+    /// A writer that only counts as written once it has been flushed.
+    #[derive(Default)]
+    struct Flushed {
+        pending: Vec<u8>,
+        delivered: Vec<u8>,
+    }
+
+    impl Write for Flushed {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.delivered.append(&mut self.pending);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn printing_flushes_the_path() {
+        let mut out = Flushed::default();
+        print_lines(&mut out, &[OsString::from("/a/b")], true).expect("printing succeeds");
+        assert_eq!(out.delivered, b"/a/b");
+        let mut out = Flushed::default();
+        print_fields(&mut out, &[OsString::from("/a/b")]).expect("printing succeeds");
+        assert_eq!(out.delivered, b"/a/b\0");
+    }
+
+    #[test]
+    fn truncate_to_width_always_fits_the_width() {
+        assert_eq!(truncate_to_width("status", 6), "status");
+        assert_eq!(truncate_to_width("status", 4), "sta…");
+        assert_eq!(truncate_to_width("status", 1), "…");
+        assert_eq!(truncate_to_width("status", 0), "");
+        assert_eq!(truncate_to_width("日本語", 4), "日…");
+    }
+
+    #[test]
+    fn extra_lines_are_cut_to_a_narrow_terminal() {
+        let mut state = state("/a/b/c");
+        state.status_layout = StatusLayout::Line;
+        state.status_message = Some("a long message".to_string());
+        assert!(rendered(&state, 5).ends_with("\r\na lo…"));
+    }
+
+    #[test]
+    fn a_render_target_with_an_origin_only_clears_its_own_region() {
+        let mut state = state("/a/b/c");
+        state.status_layout = StatusLayout::Line;
+        state.status_message = Some("hello".to_string());
+        let mut buffer = Vec::new();
+        let origin = Some((2, 3));
+        let mut target = RenderTarget { writer: &mut buffer, origin, width: 8, row: 0 };
+        render(&mut target, &state).expect("rendering to a buffer works");
+        assert_eq!(
+            String::from_utf8(buffer).expect("the frame is UTF-8"),
+            "\x1b[4;3H        \x1b[4;3H/\x1b[0ma/\x1b[0mb/\x1b[0m\x1b[7mc\x1b[0m\
+             \x1b[5;3H        \x1b[5;3H\x1b[2mhello\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn each_input_source_clamps_or_wraps_on_its_own() {
+        let scroll_down = MouseEvent {
+            kind: MouseEventKind::ScrollDown,
+            column: 0,
+            row: 0,
+            modifiers: KeyModifiers::NONE,
+        };
+        let mut state = state("/a/b");
+        state.wrap.keys = Boundary::Wrap;
+        press(&mut state, KeyCode::Right);
+        assert_eq!(state.selected_path(), Path::new("/a/b"));
+        handle_mouse_event(scroll_down, &mut state).expect("scrolling is handled");
+        assert_eq!(state.selected_path(), Path::new("/a/b"));
+        type_keys(&mut state, "l");
+        assert_eq!(state.selected_path(), Path::new("/"));
+
+        state.wrap.keys = Boundary::Clamp;
+        state.wrap.scroll = Boundary::Wrap;
+        press(&mut state, KeyCode::Left);
+        type_keys(&mut state, "h");
+        assert_eq!(state.selected_path(), Path::new("/"));
+        state.current_index = 2;
+        // Not so soon after the last scroll that it counts as spinning the wheel.
+        state.last_scroll = None;
+        handle_mouse_event(scroll_down, &mut state).expect("scrolling is handled");
+        assert_eq!(state.selected_path(), Path::new("/"));
+    }
+
+    #[test]
+    fn a_visual_range_prints_every_path_or_just_its_ends() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "vhh");
+        assert!(!state.in_range(0) && state.in_range(1) && state.in_range(3));
+        assert_eq!(
+            state.range_paths(),
+            [Path::new("/a/"), Path::new("/a/b/"), Path::new("/a/b/c")]
+        );
+        state.range_output = RangeOutput::Ends;
+        assert_eq!(state.range_paths(), [Path::new("/a/"), Path::new("/a/b/c")]);
+        type_keys(&mut state, "v");
+        assert!(state.range_paths().is_empty());
+    }
+
+    /// A writer standing in for the terminal that refuses to enable mouse capture.
+    #[derive(Default)]
+    struct NoMouse(Vec<u8>);
+
+    impl Write for NoMouse {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if buf.windows(6).any(|window| window == b"?1000h") {
+                return Err(std::io::ErrorKind::Unsupported.into());
+            }
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_failed_mouse_setup_undoes_raw_mode() {
+        let mode_set = AtomicBool::new(false);
+        let raw = std::cell::Cell::new(false);
+        let mut out = NoMouse::default();
+        let enable = || {
+            raw.set(true);
+            Ok(())
+        };
+        let disable = || {
+            raw.set(false);
+            Ok(())
+        };
+        let result = set_up_terminal(&mut out, &mode_set, enable, disable);
+        assert!(result.is_err());
+        assert!(!raw.get());
+        assert!(!mode_set.load(Ordering::Relaxed));
+        // The cursor hidden before the failure is shown again.
+        assert!(String::from_utf8_lossy(&out.0).contains("\x1b[?25l\x1b[?25h"));
+    }
+
+    #[test]
+    fn with_origin_is_refused_where_only_one_line_makes_sense() {
+        let args = |args: &[&str]| parse_args(args.iter().map(OsString::from));
+        assert!(args(&["--with-origin"]).expect("--with-origin parses").with_origin);
+        assert_eq!(
+            args(&["--with-origin", "--emit-cmd", "cd"]).err().as_deref(),
+            Some("option '--emit-cmd' cannot be combined with '--with-origin'")
+        );
+    }
+
+    #[test]
+    fn the_parts_above_the_focus_root_are_dimmed() {
+        let mut state = state("/a/b/c");
+        state.focus_root = Some(PathBuf::from("/a/b"));
+        state.update_focus_index();
+        assert_eq!(state.focus_index, Some(2));
+        assert_eq!(
+            frame(&state, 80),
+            "\x1b[1G\x1b[2m/\x1b[0m\x1b[2ma/\x1b[0mb/\x1b[0m\x1b[7mc\x1b[0m\x1b[J\x1b[1G"
+        );
+        state.focus_root = Some(PathBuf::from("/elsewhere"));
+        state.update_focus_index();
+        assert!(!frame(&state, 80).contains("\x1b[2m"));
+    }
+
+    #[test]
+    fn find_pasted_matches_ancestors_and_names() {
+        let state = state("/a/src/b/src");
+        assert_eq!(state.find_pasted("/a/src\n"), Some(2));
+        assert_eq!(state.find_pasted("/a/src/"), Some(2));
+        assert_eq!(state.find_pasted("src/"), Some(4));
+        assert_eq!(state.find_pasted("b"), Some(3));
+        assert_eq!(state.find_pasted("/b"), None);
+        assert_eq!(state.find_pasted("  "), None);
+    }
+
+    #[test]
+    fn a_dotdot_part_can_be_selected_like_any_other() {
+        let mut state = state("/a/../b");
+        press(&mut state, KeyCode::Left);
+        assert_eq!(state.selected_path(), Path::new("/a/.."));
+        assert_eq!(rendered(&state, 80), "/a/../b");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_cd_command_quotes_the_path_and_guards_dashes() {
+        assert_eq!(shell_command("cd", Path::new("/tmp/a")), "cd /tmp/a");
+        assert_eq!(shell_command("cd", Path::new("/it's")), "cd '/it'\\''s'");
+        assert_eq!(shell_command("cd", Path::new("-x")), "cd -- -x");
+        let args = |args: &[&str]| parse_args(args.iter().map(OsString::from));
+        let options = args(&["--emit-cd"]).expect("--emit-cd parses");
+        assert_eq!(options.emit_command, Some("cd"));
+    }
+
+    #[test]
+    fn the_kept_breadcrumb_brackets_the_selection_once() {
+        let mut state = state("/home/user/project");
+        press(&mut state, KeyCode::Left);
+        assert_eq!(state.plain_breadcrumb(), "/home/[user/]project");
+        // Without colors the selection is already bracketed by `part_text`.
+        state.no_color = true;
+        assert_eq!(state.plain_breadcrumb(), "/home/[user/]project");
+    }
+
+    #[test]
+    fn dragging_across_parts_selects_and_confirms_a_range() {
+        let mouse = |kind, column| MouseEvent {
+            kind,
+            column,
+            row: 0,
+            modifiers: KeyModifiers::NONE,
+        };
+        let mut state = state("/a/b/c");
+        let left = MouseButton::Left;
+        handle_mouse_event(mouse(MouseEventKind::Down(left), 1), &mut state).expect("handled");
+        assert_eq!(state.anchor, None);
+        handle_mouse_event(mouse(MouseEventKind::Drag(left), 5), &mut state).expect("handled");
+        assert_eq!(state.anchor, Some(1));
+        let action = handle_mouse_event(mouse(MouseEventKind::Up(left), 5), &mut state);
+        assert!(matches!(action, Ok(EventAction::Confirm(path)) if path == Path::new("/a/b/c")));
+        assert_eq!(
+            state.range_paths(),
+            [Path::new("/a/"), Path::new("/a/b/"), Path::new("/a/b/c")]
+        );
+    }
+
+    #[test]
+    fn a_click_without_a_drag_only_selects() {
+        let mouse = |kind| MouseEvent { kind, column: 3, row: 0, modifiers: KeyModifiers::NONE };
+        let mut state = state("/a/b/c");
+        let left = MouseButton::Left;
+        handle_mouse_event(mouse(MouseEventKind::Down(left)), &mut state).expect("handled");
+        let action = handle_mouse_event(mouse(MouseEventKind::Up(left)), &mut state);
+        assert!(matches!(action, Ok(EventAction::Continue)));
+        assert_eq!(state.selected_path(), Path::new("/a/b/"));
+        assert_eq!(state.anchor, None);
+    }
+
+    #[test]
+    fn stepping_through_the_history_skips_deleted_directories() {
+        let dir = temp_dir("history");
+        let (one, gone, three) = (dir.join("one"), dir.join("gone"), dir.join("three"));
+        fs::create_dir(&one).expect("the directory can be created");
+        fs::create_dir(&three).expect("the directory can be created");
+        let file = dir.join("dirstack");
+        for path in [&one, &gone, &three] {
+            push_dirstack(&file, path).expect("the stack can be written");
+        }
+        let step = |step| step_history(&file, step).expect("the history can be read");
+        assert_eq!(step(HistoryStep::Back), Some(one.clone()));
+        assert_eq!(step(HistoryStep::Back), None);
+        assert_eq!(step(HistoryStep::Forward), Some(three));
+        assert_eq!(step(HistoryStep::Forward), None);
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn emit_cmd_takes_cd_or_pushd() {
+        assert_eq!(parse_command_word("pushd"), Ok("pushd"));
+        assert_eq!(
+            parse_command_word("popd"),
+            Err("invalid command 'popd', expected cd or pushd".to_string())
+        );
+        assert_eq!(shell_command("pushd", Path::new("-x")), "pushd -- -x");
+    }
+
+    #[test]
+    fn typing_narrows_the_path_with_the_none_keymap() {
+        let mut state = state("/usr/local/lib/share");
+        state.keymap = Keymap::None;
+        type_keys(&mut state, "l");
+        assert_eq!(state.selected_path(), Path::new("/usr/local/lib/"));
+        type_keys(&mut state, "o");
+        assert_eq!(state.selected_path(), Path::new("/usr/local/"));
+        type_keys(&mut state, "x");
+        assert_eq!(state.narrow, "lo");
+        assert_eq!(state.status_message.as_deref(), Some("no component starts with 'lox'"));
+        press(&mut state, KeyCode::Backspace);
+        press(&mut state, KeyCode::Backspace);
+        assert!(state.narrow.is_empty() && state.is_landable(4));
+    }
+
+    #[test]
+    fn tabular_cells_pad_short_parts_and_cut_long_ones() {
+        let mut state = state("/usr/local/lib");
+        state.cell_width = Some(4);
+        assert_eq!(rendered(&state, 80), "/   usr/loc…lib ");
+    }
+
+    #[test]
+    fn a_pending_jump_shows_its_prompt() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "F");
+        assert_eq!(rendered(&state, 80), "/a/b/c  F_");
+        type_keys(&mut state, "a");
+        assert_eq!(rendered(&state, 80), "/a/b/c");
+    }
+
+    #[test]
+    fn the_file_manager_is_opened_at_the_selection() {
+        let command = filemanager_command("nnn -d", Path::new("/a/b")).expect("it has a program");
+        assert_eq!(command.get_program(), "nnn");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-d", "/a/b"]);
+        assert!(filemanager_command("  ", Path::new("/a/b")).is_none());
+
+        let mut state = state("/a/b/c");
+        press(&mut state, KeyCode::Left);
+        let action = press(&mut state, KeyCode::Char('o'));
+        assert!(matches!(action, EventAction::Browse(path) if path == Path::new("/a/b/")));
+    }
+
+    #[test]
+    fn digits_select_parts_by_index_in_select_mode() {
+        let mut state = state("/0/1/2/3/4/5/6/7/8/9/10/11");
+        state.digit_mode = DigitMode::Select;
+        type_keys(&mut state, "1");
+        assert_eq!(state.current_index, 1);
+        // A quick second digit makes a two-digit index, if there is such a part.
+        type_keys(&mut state, "2");
+        assert_eq!(state.current_index, 12);
+        type_keys(&mut state, "5");
+        type_keys(&mut state, "7");
+        assert_eq!(state.current_index, 7);
+        let now = Instant::now();
+        state.select_digit(1, now);
+        state.select_digit(2, now + DIGIT_TIMEOUT);
+        assert_eq!(state.current_index, 2);
+    }
+
+    #[test]
+    fn part_name_strips_the_trailing_separator() {
+        let sep = std::path::MAIN_SEPARATOR_STR;
+        assert_eq!(part_name(OsStr::new(&format!("user{sep}")), None), "user");
+        assert_eq!(part_name(OsStr::new("project"), None), "project");
+        assert_eq!(part_name(OsStr::new(sep), None), sep);
+        assert_eq!(part_name(OsStr::new("example."), Some('.')), "example");
+    }
+
+    #[test]
+    fn exec_templates_split_like_a_shell_would() {
+        assert_eq!(
+            parse_exec_template(r#"code  -n 'a b' "c \"d\" \x" e\ f ''"#),
+            Ok(vec![
+                "code".to_string(),
+                "-n".to_string(),
+                "a b".to_string(),
+                r#"c "d" \x"#.to_string(),
+                "e f".to_string(),
+                String::new(),
+            ])
+        );
+        assert_eq!(parse_exec_template("ls 'a"), Err("unterminated quote in 'ls 'a'".to_string()));
+        let empty = parse_exec_template("  ");
+        assert_eq!(empty, Err("option '--exec' requires a command".to_string()));
+    }
+
+    #[test]
+    fn the_path_replaces_braces_or_is_appended() {
+        let words = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        let path = Path::new("/a b/c");
+        let command = exec_command(&words(&["tar", "-C", "{}", "--file={}.tar"]), path);
+        assert_eq!(command.get_program(), "tar");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-C", "/a b/c", "--file=/a b/c.tar"]);
+        let command = exec_command(&words(&["ls", "-l"]), path);
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-l", "/a b/c"]);
+    }
+
+    #[test]
+    fn f1_cycles_through_the_keymaps() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "2");
+        press(&mut state, KeyCode::F(1));
+        assert!(state.keymap == Keymap::Emacs);
+        assert!(state.count_input.is_empty());
+        assert_eq!(state.status_message.as_deref(), Some("keymap: emacs"));
+        for _ in 0..3 {
+            press(&mut state, KeyCode::F(1));
+        }
+        assert!(state.keymap == Keymap::Vim);
+        assert_eq!(state.status_message.as_deref(), Some("keymap: vim"));
+    }
+
+    #[test]
+    fn dump_keys_lists_the_configured_keys_then_the_tables() {
+        let key = |spec| KeyBinding::parse(spec).expect("the spec is valid");
+        let mut out = Vec::new();
+        dump_keys(&mut out, Keymap::None, &key("enter"), Some(&key("q")), &key("C-t"))
+            .expect("a Vec can be written to");
+        let out = String::from_utf8(out).expect("the keys are UTF-8");
+        assert!(out.starts_with(
+            "# keymap: none\nenter\tconfirm\nq\tquit\nC-t\tnext-output-format\n\
+             <char>\tnarrow\nbackspace\twiden\nleft\tmove-left\n"
+        ));
+        // The configured quit key replaces `esc`.
+        assert!(!out.contains("esc\tquit"));
+        assert!(out.ends_with("C-z\tsuspend\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn abbreviated_parents_keep_their_first_letter() {
+        assert_eq!(abbreviate_part(OsStr::new("local/")), "l/");
+        assert_eq!(abbreviate_part(OsStr::new(".config/")), ".c/");
+        assert_eq!(abbreviate_part(OsStr::new("/")), "/");
+        let mut state = state("/usr/local/.config/bin");
+        state.abbreviate = true;
+        press(&mut state, KeyCode::Left);
+        press(&mut state, KeyCode::Left);
+        assert_eq!(rendered(&state, 80), "/u/local/.c/bin");
+    }
+
+    #[test]
+    fn jumps_stop_at_either_end_and_on_an_empty_path() {
+        let mut state = state("/a/b/a");
+        type_keys(&mut state, "fa");
+        assert_eq!(state.selected_path(), Path::new("/a/b/a"));
+        type_keys(&mut state, "9Fa");
+        assert_eq!(state.selected_path(), Path::new("/a/b/a"));
+        state.current_index = 0;
+        type_keys(&mut state, "Fa");
+        assert_eq!(state.current_index, 0);
+        state.path_parts.clear();
+        state.find_and_select_char_match(JumpDirection::Forward, 'a');
+        state.find_and_select_char_match(JumpDirection::Backward, 'a');
+        assert_eq!(state.current_index, 0);
+    }
+
+    #[test]
+    fn marks_remember_a_part_to_jump_back_to() {
+        let mut state = state("/a/b/c");
+        type_keys(&mut state, "hma");
+        press(&mut state, KeyCode::End);
+        type_keys(&mut state, "`a");
+        assert_eq!(state.selected_path(), Path::new("/a/b/"));
+        type_keys(&mut state, "`z");
+        assert_eq!(state.status_message.as_deref(), Some("mark 'z' is not set"));
+        assert_eq!(state.selected_path(), Path::new("/a/b/"));
+    }
+
+    #[test]
+    fn drill_down_lists_subdirectories_before_confirming() {
+        let dir = temp_dir("drill");
+        fs::create_dir_all(dir.join("x/y")).expect("the directories can be created");
+        fs::create_dir_all(dir.join("x/z")).expect("the directories can be created");
+        fs::create_dir_all(dir.join("empty")).expect("the directory can be created");
+        let path = |path: &Path| state(path.to_str().expect("the temporary directory is UTF-8"));
+        let mut drilled = path(&dir.join("x/y"));
+        drilled.drill_down = true;
+        press(&mut drilled, KeyCode::Left);
+        assert!(matches!(drilled.confirm_or_drill(false), EventAction::Continue));
+        assert!(matches!(
+            &drilled.input_mode,
+            InputMode::Descend(list) if list.entries == ["y", "z"]
+        ));
+        drilled.input_mode = InputMode::Normal;
+        assert!(matches!(drilled.confirm_or_drill(true), EventAction::Confirm(_)));
+        press(&mut drilled, KeyCode::End);
+        assert!(matches!(drilled.confirm_or_drill(false), EventAction::Confirm(_)));
+
+        // Nothing to list means there is nothing to explore first.
+        let mut leaf = path(&dir.join("empty/missing"));
+        leaf.drill_down = true;
+        press(&mut leaf, KeyCode::Left);
+        let action = leaf.confirm_or_drill(false);
+        assert!(matches!(action, EventAction::Confirm(path) if path == dir.join("empty")));
+        assert!(matches!(leaf.input_mode, InputMode::Normal));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn ctrl_t_cycles_the_output_format() {
+        let mut state = state("/a/b/c");
+        let ctrl_t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL);
+        handle_key_event(ctrl_t, &mut state).expect("keys are handled");
+        assert!(state.output_format == OutputFormat::Relative);
+        assert_eq!(state.status_message.as_deref(), Some("output: relative"));
+        handle_key_event(ctrl_t, &mut state).expect("keys are handled");
+        assert_eq!(state.status_message.as_deref(), Some("output: ~"));
+        handle_key_event(ctrl_t, &mut state).expect("keys are handled");
+        assert!(state.output_format == OutputFormat::Absolute);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_formats_rewrite_the_absolute_path() {
+        let (path, home) = (PathBuf::from("/home/u/src"), "/home/u");
+        let cwd = Path::new("/home/u/doc/x");
+        let apply = |format: OutputFormat| format.apply(path.clone(), cwd, Some(Path::new(home)));
+        assert_eq!(apply(OutputFormat::Absolute), path);
+        assert_eq!(apply(OutputFormat::Relative), Path::new("../../src"));
+        assert_eq!(apply(OutputFormat::Tilde), Path::new("~/src"));
+        assert_eq!(relative_path(cwd, cwd), Path::new("."));
+        let outside = OutputFormat::Tilde.apply(PathBuf::from("/etc"), cwd, Some(Path::new(home)));
+        assert_eq!(outside, Path::new("/etc"));
+    }
+
+    #[test]
+    fn a_recorded_macro_replays_its_keys() {
+        let key = |c| Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        let mut state = state("/a/b/c/d/e");
+        // Record `h` into register `q`, then replay it twice.
+        let mut events = ScriptedEvents("QqhQ@q@q".chars().map(key).collect());
+        events.0.push_back(Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)));
+        let selection = event_loop(&mut state, &mut events, &mut Vec::new(), None)
+            .expect("the script is handled")
+            .expect("the script confirms");
+        assert_eq!(selection.path, Path::new("/a/b/"));
+        assert_eq!(state.registers.get(&'q').map(Vec::len), Some(1));
+    }
+
+    #[test]
+    fn saved_macros_load_back() {
+        let dir = temp_dir("macros");
+        let file = dir.join("macros");
+        let keys = vec![
+            KeyEvent::new(KeyCode::Char('h'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char('x'), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Left, KeyModifiers::NONE),
+        ];
+        save_registers(&file, &HashMap::from([('a', keys.clone())]))
+            .expect("the macros can be saved");
+        assert_eq!(fs::read_to_string(&file).expect("the file can be read"), "a\th C-x left\n");
+        let loaded = load_registers(&file).expect("the macros can be loaded");
+        assert_eq!(loaded.get(&'a'), Some(&keys));
+        assert!(load_registers(&dir.join("missing")).expect("a missing file is empty").is_empty());
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn summary_line_describes_the_outcome() {
+        let home = Path::new("/home");
+        assert_eq!(summary_line(None), "cancelled");
+        assert_eq!(summary_line(Some((home, 0))), "confirmed /home (current directory)");
+        assert_eq!(summary_line(Some((home, 1))), "confirmed /home (1 level up)");
+        assert_eq!(summary_line(Some((home, 2))), "confirmed /home (2 levels up)");
+    }
+
+    #[test]
+    fn tab_switches_between_start_paths_keeping_each_selection() {
+        let mut second = state("/x/y/z");
+        second.tab = 1;
+        let mut state = state("/a/b");
+        state.tabs.push_back(second);
+        press(&mut state, KeyCode::Left);
+        press(&mut state, KeyCode::F(1));
+        press(&mut state, KeyCode::Tab);
+        assert_eq!((state.tab, state.selected_path()), (1, PathBuf::from("/x/y/z")));
+        // The keymap belongs to the session rather than to a tab.
+        assert!(state.keymap == Keymap::Emacs);
+        press(&mut state, KeyCode::BackTab);
+        assert_eq!((state.tab, state.selected_path()), (0, PathBuf::from("/a/")));
+    }
+
+    /// A writer that keeps each write separately.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_batched_frame_is_the_same_bytes_in_one_write() {
+        let mut piecewise = Writes::default();
+        draw_frame(&mut state("/a/b/c"), &mut piecewise, &mut None).expect("the frame is drawn");
+        assert!(piecewise.0.len() > 1);
+        let mut batched = Writes::default();
+        let mut batching = state("/a/b/c");
+        batching.batch_render = true;
+        draw_frame(&mut batching, &mut batched, &mut None).expect("the frame is drawn");
+        assert_eq!(batched.0, [piecewise.0.concat()]);
+    }
+
+    #[test]
+    fn summarize_git_status_counts_each_column() {
+        assert_eq!(summarize_git_status(""), "git: clean");
+        assert_eq!(
+            summarize_git_status("M  staged.rs\n M modified.rs\nMM both.rs\n?? new.rs\n"),
+            "git: 2 staged, 2 modified, 1 untracked"
+        );
+        assert_eq!(summarize_git_status("?? a\n?? b\n"), "git: 2 untracked");
+    }
+
+    #[test]
+    fn a_selection_moved_back_to_the_start_is_unchanged() {
+        let key = |code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        let mut state = state("/a/b/c");
+        // As `configured_state` records it.
+        state.initial_path = state.selected_path();
+        let mut events = ScriptedEvents(VecDeque::from([
+            key(KeyCode::Left),
+            key(KeyCode::Right),
+            key(KeyCode::Enter),
+        ]));
+        let selection = event_loop(&mut state, &mut events, &mut Vec::new(), None)
+            .expect("the script is handled")
+            .expect("the script confirms");
+        assert!(selection.unchanged);
+    }
+
+    #[test]
+    fn parts_are_badged_with_their_subdirectory_counts() {
+        assert_eq!(superscript(0), "⁰");
+        assert_eq!(superscript(12), "¹²");
+        let dir = temp_dir("child-counts");
+        for sub in ["x/y", "x/z", "x/w"] {
+            fs::create_dir_all(dir.join(sub)).expect("the directories can be created");
+        }
+        fs::write(dir.join("x/file"), "").expect("the file can be written");
+        let path = dir.join("x/y");
+        let mut counted = state(path.to_str().expect("the temporary directory is UTF-8"));
+        counted.show_child_counts = true;
+        counted.refresh_child_counts();
+        let sep = std::path::MAIN_SEPARATOR;
+        assert!(rendered(&counted, 200).ends_with(&format!("x³{sep}y⁰")));
+        // The root has no name for a badge to follow.
+        assert!(!counted.child_counts.contains_key(&0));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn waiting_events_are_coalesced_into_few_frames() {
+        let key = |code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        let mut state = state("/a/b/c");
+        let mut events = ScriptedEvents(VecDeque::from(vec![key(KeyCode::Char('h')); 100]));
+        events.0.push_back(key(KeyCode::Enter));
+        let mut out = Vec::new();
+        event_loop(&mut state, &mut events, &mut out, None).expect("the script is handled");
+        // One frame before the first event, and one per `MAX_EVENTS_PER_FRAME` after it.
+        let frames = String::from_utf8(out).expect("the frames are UTF-8");
+        assert_eq!(frames.matches("\x1b[J").count(), 1 + 100 / MAX_EVENTS_PER_FRAME);
+    }
+
+    #[test]
+    fn the_hovered_part_is_styled_without_being_selected() {
+        let mut state = state("/a/b/c");
+        state.hover_style = Some(Attribute::Underlined);
+        state.hover_at(1, 0, Instant::now());
+        assert_eq!((state.hover_index, state.current_index), (Some(1), 3));
+        assert_eq!(
+            frame(&state, 80),
+            "\x1b[1G/\x1b[0m\x1b[4ma/\x1b[0mb/\x1b[0m\x1b[7mc\x1b[0m\x1b[J\x1b[1G"
+        );
+        // Hovering the selection leaves it drawn as selected.
+        state.hover_at(5, 0, Instant::now());
+        assert!(!frame(&state, 80).contains("\x1b[4m"));
+    }
+
+    #[test]
+    fn pick_items_are_lines_or_nul_separated() {
+        let read = |bytes: &[u8]| read_pick_items(&mut &bytes[..]).expect("a slice can be read");
+        assert_eq!(read(b"/a\r\n\n/b c\n"), ["/a", "/b c"]);
+        assert_eq!(read(b"/a\n/b\0/c\0\0"), ["/a\n/b", "/c"]);
+        assert!(read(b"").is_empty());
+        let mut picking = state("/");
+        picking.pick = true;
+        picking.set_path_parts(read(b"/x/y\n/z\n"), 0);
+        press(&mut picking, KeyCode::Right);
+        assert_eq!(picking.selected_path(), Path::new("/z"));
+    }
+
+    #[test]
+    fn print0_keeps_newlines_in_names_intact() {
+        let args = |args: &[&str]| parse_args(args.iter().map(OsString::from));
+        assert!(args(&["-0"]).expect("-0 parses").print0);
+        assert!(args(&["--print0"]).expect("--print0 parses").print0);
+        let mut out = Vec::new();
+        let lines = [OsString::from("/a\nb"), OsString::from("/c")];
+        print_fields(&mut out, &lines).expect("a Vec can be written to");
+        assert_eq!(out, b"/a\nb\0/c\0");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allowed_roots_are_reached_through_symlinks() {
+        let dir = temp_dir("allowed-symlink");
+        fs::create_dir_all(dir.join("real/sub")).expect("the directories can be created");
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("link"))
+            .expect("the symlink can be created");
+        let root = fs::canonicalize(dir.join("link")).expect("the root can be canonicalized");
+        let start = dir.join("link/sub");
+        let mut state = state(start.to_str().expect("the temporary directory is UTF-8"));
+        state.allowed_roots = vec![root];
+        assert!(state.is_path_allowed(&start));
+        assert!(state.is_path_allowed(&dir.join("link")));
+        assert!(!state.is_path_allowed(&dir));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_recent_entry_keeps_what_other_applications_recorded() {
+        let dir = temp_dir("recent-xbel");
+        let file = dir.join("recently-used.xbel");
+        let old = "2020-01-01T00:00:00Z";
+        let entry = format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<xbel version=\"1.0\">\n",
+                "  <bookmark href=\"file:///srv/a%20b\" added=\"{old}\" modified=\"{old}\" ",
+                "visited=\"{old}\">\n",
+                "    <info>\n      <metadata owner=\"http://freedesktop.org\">\n",
+                "        <bookmark:groups><bookmark:group>work</bookmark:group></bookmark:groups>\n",
+                "        <bookmark:applications>\n",
+                "          <bookmark:application name=\"nautilus\" exec=\"nautilus\" ",
+                "modified=\"{old}\" count=\"7\"/>\n",
+                "        </bookmark:applications>\n",
+                "      </metadata>\n    </info>\n  </bookmark>\n</xbel>\n"
+            ),
+            old = old
+        );
+        fs::write(&file, &entry).expect("the file can be written");
+        add_recent_xbel(&file, Path::new("/srv/a b")).expect("the entry is updated");
+        add_recent_xbel(&file, Path::new("/srv/a b")).expect("the entry is updated");
+        let text = fs::read_to_string(&file).expect("the file can be read");
+        assert_eq!(text.matches("<bookmark href=").count(), 1);
+        assert!(text.contains(&format!("added=\"{old}\"")));
+        assert!(!text.contains(&format!("visited=\"{old}\"")));
+        assert!(text.contains("<bookmark:group>work</bookmark:group>"));
+        assert!(text.contains(&format!("name=\"nautilus\" exec=\"nautilus\" modified=\"{old}\"")));
+        assert!(text.contains("count=\"7\"/>\n          <bookmark:application name=\"pd\""));
+        assert!(text.contains("count=\"2\"/>\n        </bookmark:applications>"));
+
+        add_recent_xbel(&file, Path::new("/srv/new")).expect("the entry is added");
+        let text = fs::read_to_string(&file).expect("the file can be read");
+        assert!(text.contains("  <bookmark href=\"file:///srv/new\""));
+        assert!(text.ends_with("  </bookmark>\n</xbel>\n"));
+        fs::remove_dir_all(&dir).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn xml_attributes_are_replaced_or_added() {
+        assert_eq!(set_xml_attribute("<a x=\"1\">", "x", "2"), "<a x=\"2\">");
+        assert_eq!(set_xml_attribute("<a x=\"1\">", "y", "2"), "<a x=\"1\" y=\"2\">");
+        assert_eq!(set_xml_attribute("<a x=\"1\" />", "y", "2"), "<a x=\"1\" y=\"2\"/>");
+        assert_eq!(xml_attribute("<a count=\"3\"/>", "count"), Some("3"));
+        assert_eq!(xml_attribute("<a bookmark:count=\"3\"/>", "count"), None);
+    }
+
+    #[test]
+    fn arguments_after_a_double_dash_are_paths() {
+        let args = |args: &[&str]| parse_args(args.iter().map(OsString::from));
+        let options = args(&["--verbose", "--", "-weird-dir", "--uri"]).expect("the paths parse");
+        assert!(options.verbose && !options.uri);
+        assert_eq!(options.start, Some(OsString::from("-weird-dir")));
+        assert_eq!(options.tabs, [OsString::from("--uri")]);
+        let options = args(&["/a", "--", "-b"]).expect("the paths parse");
+        assert_eq!(options.start, Some(OsString::from("/a")));
+        assert_eq!(options.tabs, [OsString::from("-b")]);
+    }
+
+    #[test]
+    fn oldpwd_prints_the_first_line_and_the_directory_left() {
+        let lines = [OsString::from("/a/b"), OsString::from("/c")];
+        let options = Options { oldpwd: true, ..Options::default() };
+        let mut out = Vec::new();
+        print_output(&mut out, &lines, Path::new("/home/u"), &options)
+            .expect("a Vec can be written to");
+        assert_eq!(out, b"/a/b\0/home/u\0");
+    }
+
+    #[test]
+    fn oldpwd_rejects_options_that_change_the_fields() {
+        let args = |args: &[&str]| parse_args(args.iter().map(OsString::from));
+        for option in ["--shell-quote", "--uri", "--with-origin", "--name-only"] {
+            let error = args(&["--oldpwd", option]).err().expect("the options conflict");
+            assert_eq!(error, format!("option '--oldpwd' cannot be combined with '{}'", option));
+        }
+        let error = args(&["--oldpwd", "--exec", "ls"]).err().expect("the options conflict");
+        assert_eq!(error, "option '--oldpwd' cannot be combined with '--exec'");
+        assert!(args(&["--oldpwd", "--print0"]).is_ok());
+    }
+}
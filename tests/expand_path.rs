@@ -0,0 +1,42 @@
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use pd::expand_path;
+
+fn expand(input: &str) -> PathBuf {
+    expand_path(OsStr::new(input))
+}
+
+#[test]
+fn expands_a_leading_tilde() {
+    let home = env::home_dir().expect("tests need a home directory");
+    assert_eq!(expand("~"), home);
+    assert_eq!(expand("~/src"), home.join("src"));
+}
+
+#[test]
+fn leaves_other_tildes_alone() {
+    assert_eq!(expand("~user/src"), Path::new("~user/src"));
+    assert_eq!(expand("src/~"), Path::new("src/~"));
+}
+
+#[test]
+fn expands_variables_with_and_without_braces() {
+    let path = env::var("PATH").expect("tests need PATH to be set");
+    assert_eq!(expand("$PATH/x"), PathBuf::from(format!("{}/x", path)));
+    assert_eq!(expand("a${PATH}b"), PathBuf::from(format!("a{}b", path)));
+}
+
+#[test]
+fn unset_variables_expand_to_nothing() {
+    assert_eq!(expand("/a/$PD_TEST_SURELY_UNSET/b"), Path::new("/a//b"));
+    assert_eq!(expand("/a/${PD_TEST_SURELY_UNSET}b"), Path::new("/a/b"));
+}
+
+#[test]
+fn keeps_a_dollar_without_a_name() {
+    assert_eq!(expand("/a/$/b"), Path::new("/a/$/b"));
+    assert_eq!(expand("/a/$1"), Path::new("/a/$1"));
+    assert_eq!(expand("/a/${b"), Path::new("/a/${b"));
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use pd::{AppState, Boundary, InitialPos, JumpDirection, handle_key_event, split_path};
+
+fn state(path: &str) -> AppState {
+    AppState::new(split_path(Path::new(path), None), InitialPos::End)
+}
+
+/// Types `keys` one after the other, as the Vim keymap reads them.
+fn type_keys(state: &mut AppState, keys: &str) {
+    for c in keys.chars() {
+        let key = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        handle_key_event(key, state).expect("keys are handled");
+    }
+}
+
+#[test]
+fn move_by_clamps_at_both_ends() {
+    let mut state = state("/a/b/c");
+    state.move_by(1, Boundary::Clamp);
+    assert_eq!(state.selected_path(), Path::new("/a/b/c"));
+    state.move_by(-10, Boundary::Clamp);
+    assert_eq!(state.selected_path(), Path::new("/"));
+}
+
+#[test]
+fn move_by_wraps_around() {
+    let mut state = state("/a/b/c");
+    state.move_by(1, Boundary::Wrap);
+    assert_eq!(state.selected_path(), Path::new("/"));
+}
+
+#[test]
+fn a_count_multiplies_the_next_motion_only() {
+    let mut state = state("/a/b/c/d");
+    type_keys(&mut state, "2");
+    state.move_by(-1, Boundary::Clamp);
+    assert_eq!(state.selected_path(), Path::new("/a/b/"));
+    state.move_by(-1, Boundary::Clamp);
+    assert_eq!(state.selected_path(), Path::new("/a/"));
+}
+
+#[test]
+fn char_jumps_find_the_nth_match() {
+    let mut state = state("/xa/xb/c/xd/e");
+    state.find_and_select_char_match(JumpDirection::Backward, 'x');
+    assert_eq!(state.selected_path(), Path::new("/xa/xb/c/xd/"));
+    type_keys(&mut state, "2");
+    state.find_and_select_char_match(JumpDirection::Backward, 'x');
+    assert_eq!(state.selected_path(), Path::new("/xa/"));
+}
+
+#[test]
+fn char_jumps_without_enough_matches_stay_put() {
+    let mut state = state("/xa/b/c");
+    type_keys(&mut state, "2");
+    state.find_and_select_char_match(JumpDirection::Backward, 'x');
+    assert_eq!(state.selected_path(), Path::new("/xa/b/c"));
+}
+
+#[test]
+fn vim_keys_move_with_counts() {
+    let mut state = state("/a/b/c/d");
+    type_keys(&mut state, "3h");
+    assert_eq!(state.selected_path(), Path::new("/a/"));
+    type_keys(&mut state, "fd");
+    assert_eq!(state.selected_path(), Path::new("/a/b/c/d"));
+}
+
+#[test]
+fn ctrl_d_and_ctrl_u_move_by_half_the_path() {
+    let mut state = state("/a/b/c/d/e");
+    let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+    handle_key_event(ctrl('u'), &mut state).expect("keys are handled");
+    assert_eq!(state.selected_path(), Path::new("/a/b/"));
+    handle_key_event(ctrl('u'), &mut state).expect("keys are handled");
+    assert_eq!(state.selected_path(), Path::new("/"));
+    handle_key_event(ctrl('d'), &mut state).expect("keys are handled");
+    assert_eq!(state.selected_path(), Path::new("/a/b/c/"));
+}
+
+#[test]
+fn fuzzy_find_matches_a_subsequence() {
+    let state = state("/home/me/Documents/src");
+    assert_eq!(state.fuzzy_find("Dcs"), Some(3));
+    assert_eq!(state.fuzzy_find("me"), Some(1));
+    assert_eq!(state.fuzzy_find("sd"), None);
+}
+
+#[test]
+fn a_count_after_the_jump_key_is_ignored() {
+    let mut state = state("/xa/xb/xc/xd/e");
+    type_keys(&mut state, "3F7x");
+    assert_eq!(state.selected_path(), Path::new("/xa/xb/"));
+}
+
+#[test]
+fn digits_after_a_counted_jump_key_do_not_change_the_count() {
+    // Neither the 7th nor the 10th `x`-containing part: the `7` is dropped.
+    let path = Path::new("/a/x1/x2/x3/x4/x5/x6/x7/x8/x9/x10");
+    let mut state = AppState::new(split_path(path, None), InitialPos::Start);
+    type_keys(&mut state, "3f7x");
+    assert_eq!(state.selected_path(), Path::new("/a/x1/x2/x3/"));
+}
+
+#[test]
+fn a_digit_without_a_count_is_a_jump_target() {
+    let mut state = state("/a/2017/b");
+    type_keys(&mut state, "F7");
+    assert_eq!(state.selected_path(), Path::new("/a/2017/"));
+}
@@ -0,0 +1,61 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use pd::split_path;
+
+fn parts(path: &str) -> Vec<OsString> {
+    split_path(Path::new(path), None)
+}
+
+/// The parts must join back into the path they were split from.
+fn assert_round_trip(path: &str) {
+    let joined: PathBuf = parts(path).iter().collect();
+    assert_eq!(joined, Path::new(path));
+}
+
+#[cfg(unix)]
+#[test]
+fn splits_absolute_unix_paths() {
+    assert_eq!(parts("/home/user/project"), ["/", "home/", "user/", "project"]);
+    assert_eq!(parts("/"), ["/"]);
+    assert_round_trip("/home/user/project");
+}
+
+#[cfg(unix)]
+#[test]
+fn keeps_parent_dirs_and_drops_current_dirs() {
+    assert_eq!(parts("/a/../b"), ["/", "a/", "../", "b"]);
+    assert_eq!(parts("/a/./b"), ["/", "a/", "b"]);
+}
+
+#[test]
+fn splits_relative_paths() {
+    assert_eq!(parts("a"), ["a"]);
+    assert_eq!(parts("."), ["."]);
+    assert_eq!(parts(""), ["."]);
+}
+
+#[test]
+fn splits_after_a_custom_separator() {
+    let parts = split_path(Path::new("com.example.app"), Some('.'));
+    assert_eq!(parts, ["com.", "example.", "app"]);
+}
+
+#[cfg(windows)]
+#[test]
+fn splits_drive_paths() {
+    assert_eq!(parts(r"C:\Users\Admin"), [r"C:\", r"Users\", "Admin"]);
+    assert_eq!(parts(r"C:dir"), ["C:", "dir"]);
+    assert_round_trip(r"C:\Users\Admin");
+}
+
+#[cfg(windows)]
+#[test]
+fn keeps_unc_and_verbatim_prefixes_whole() {
+    assert_eq!(parts(r"\\server\share\folder"), [r"\\server\share\", "folder"]);
+    assert_eq!(parts(r"\\?\C:\dir"), [r"\\?\C:\", "dir"]);
+    assert_eq!(parts(r"\\?\UNC\server\share\dir"), [r"\\?\UNC\server\share\", "dir"]);
+    assert_round_trip(r"\\server\share\folder");
+    assert_round_trip(r"\\?\C:\dir");
+    assert_round_trip(r"\\?\UNC\server\share\dir");
+}